@@ -1,19 +1,46 @@
 // cSpell:ignore ddns, chrono
-use ddns_rust::mods::{config::spawn::init_config, handle::spawn_tasks, statics::CONFIG};
-use log::{error, trace};
-use std::env;
+use ddns_rust::mods::{
+    cli::{check_config, get_record, list_interfaces, run_once, set_record, Cli, Command, USAGE},
+    config::{reload::watch_config, spawn::init_config},
+    handle::{shutdown_signal, shutdown_tasks, spawn_tasks, STOP_TIMEOUT},
+    interfaces::netlink::watch_addresses,
+    state::init_state,
+    statics::{CONFIG, DRY_RUN},
+};
+use log::{error, info, trace};
+use std::{env, process::exit, sync::atomic::Ordering};
 
 #[tokio::main]
 async fn main() {
-    let args = env::args().collect::<Vec<String>>();
-    let path = if args.len() > 1 {
-        args[1].as_str()
-    } else {
-        "./config.toml"
+    let cli = match Cli::parse(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, USAGE);
+            exit(2);
+        }
     };
+    DRY_RUN.store(cli.dry_run, Ordering::Relaxed);
+
+    // commands that do not need a loaded config
+    match cli.command {
+        Command::Help => {
+            println!("{}", USAGE);
+            return;
+        }
+        Command::Check => exit(check_config(&cli.config_path).await),
+        Command::ListInterfaces => {
+            init_logger(
+                &cli.log_level
+                    .map(|l| l.to_string())
+                    .unwrap_or("warn".to_string()),
+            );
+            exit(list_interfaces().await);
+        }
+        _ => (),
+    }
 
     // initialization config
-    match init_config(&path).await {
+    match init_config(&cli.config_path).await {
         Err(err) => {
             println!("Error: {}", err);
             panic!("Error: failed to init config in main()");
@@ -24,35 +51,32 @@ async fn main() {
 
     // initialization logger
     {
-        let log_level = CONFIG.lock().await.log_level.to_string();
-        use env_logger::Builder;
-        use log::LevelFilter;
-        use std::io::Write;
-        Builder::new()
-            .format(|buf, record| {
-                writeln!(
-                    buf,
-                    "{} [{}] - {}",
-                    chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                    record.level(),
-                    record.args()
-                )
-            })
-            .filter(
-                None,
-                log_level
-                    .parse::<LevelFilter>()
-                    .unwrap_or(LevelFilter::Info),
-            )
-            .parse_default_env()
-            .init();
+        let log_level = match &cli.log_level {
+            Some(log_level) => log_level.to_string(),
+            None => CONFIG.lock().await.log_level.to_string(),
+        };
+        init_logger(&log_level);
     }
     // initialization logger end
 
+    // initialization state
+    {
+        let state_file = CONFIG.lock().await.state_file.clone();
+        init_state(state_file.as_deref()).await;
+    }
+    // initialization state end
+
     // initialization plugins
     // todo!("initialization plugins")
     // initialization plugins end
 
+    match cli.command {
+        Command::Once(sites) => exit(run_once(&sites).await),
+        Command::Get(site) => exit(get_record(&site).await),
+        Command::Set(site, ip) => exit(set_record(&site, &ip).await),
+        _ => (),
+    }
+
     // initialization tasks
     trace!("initialization tasks");
     if let Err(value) = spawn_tasks().await {
@@ -61,5 +85,38 @@ async fn main() {
     }
     // initialization tasks end
 
-    tokio::signal::ctrl_c().await.unwrap();
+    // reload on config changes and SIGHUP
+    tokio::spawn(watch_config(cli.config_path.clone()));
+    // update right away when an interface gains or loses an address
+    tokio::spawn(watch_addresses());
+
+    shutdown_signal().await;
+    info!("Shutting down");
+    if !shutdown_tasks(STOP_TIMEOUT).await {
+        error!("Some tasks did not stop in time");
+    }
+}
+
+fn init_logger(log_level: &str) {
+    use env_logger::Builder;
+    use log::LevelFilter;
+    use std::io::Write;
+    Builder::new()
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "{} [{}] - {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                record.args()
+            )
+        })
+        .filter(
+            None,
+            log_level
+                .parse::<LevelFilter>()
+                .unwrap_or(LevelFilter::Info),
+        )
+        .parse_default_env()
+        .init();
 }
@@ -1,8 +1,12 @@
+pub mod cli;
 pub mod config;
+pub mod dns;
 pub mod handle;
 pub mod interfaces;
 pub mod plugins;
 pub mod providers;
 pub mod request;
+pub mod sources;
+pub mod state;
 pub mod statics;
 pub mod types;
@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+
+use crate::mods::{
+    config::spawn::spawn_config,
+    handle::{current_value, is_dry_run, SiteRunner},
+    interfaces::{get_interface_ips, get_interfaces, AddressDetails, IPAddress},
+    providers::{types::ProvidersErrorType, DDNSProviderTrait},
+    sources::validate_address,
+    statics::{CONFIG, STATE},
+    types::{LogLevel, SiteConfig},
+};
+
+pub const USAGE: &str = "Usage: ddns-rust [OPTIONS] [COMMAND]
+
+Commands:
+  run                 Run the daemon (default)
+  once [SITE...]      Update all enabled sites (or the given ones) once and exit
+  check               Validate the config file
+  list-interfaces     Show the interfaces and how their addresses are classified
+  get <SITE>          Show the current remote record of a site
+  set <SITE> <IP>     Force the record of a site to IP
+
+Options:
+  -c, --config <PATH>      Config file [default: ./config.toml]
+  -l, --log-level <LEVEL>  Override log_level (error, warn, info, debug, trace)
+  -n, --dry-run            Query providers but do not change any record
+  -h, --help               Show this help";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Run,
+    Once(Vec<String>), // empty means every enabled site
+    Check,
+    ListInterfaces,
+    Get(String),
+    Set(String, String),
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cli {
+    pub command: Command,
+    pub config_path: String,
+    pub log_level: Option<LogLevel>,
+    pub dry_run: bool,
+}
+
+impl Cli {
+    /// Parse the arguments without the program name.
+    ///
+    /// A lone path (`ddns-rust ./config.toml`) still means `run` with that config.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Cli, String> {
+        let mut config_path = None;
+        let mut log_level = None;
+        let mut dry_run = false;
+        let mut help = false;
+        let mut positional = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = |name: &str| match inline_value {
+                Some(value) => Ok(value.to_string()),
+                None => args.next().ok_or_else(|| format!("{} needs a value", name)),
+            };
+            match flag.as_str() {
+                "-c" | "--config" => config_path = Some(value(&flag)?),
+                "-l" | "--log-level" => {
+                    let level = value(&flag)?;
+                    log_level = match LogLevel::from_name(&level) {
+                        Some(level) => Some(level),
+                        None => return Err(format!("unknown log level {:?}", level)),
+                    };
+                }
+                "-n" | "--dry-run" => dry_run = true,
+                "-h" | "--help" => help = true,
+                _ if flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(format!("unknown option {:?}", flag))
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let command = match positional.next().as_deref() {
+            _ if help => Command::Help,
+            None | Some("run") => Command::Run,
+            Some("once") => Command::Once(positional.by_ref().collect()),
+            Some("check") => Command::Check,
+            Some("list-interfaces") => Command::ListInterfaces,
+            Some("get") => match positional.next() {
+                Some(site) => Command::Get(site),
+                None => return Err("get needs a site name".to_string()),
+            },
+            Some("set") => match (positional.next(), positional.next()) {
+                (Some(site), Some(ip)) => Command::Set(site, ip),
+                _ => return Err("set needs a site name and an IP".to_string()),
+            },
+            Some(path) if config_path.is_none() => {
+                config_path = Some(path.to_string());
+                Command::Run
+            }
+            Some(other) => return Err(format!("unknown command {:?}", other)),
+        };
+        if let Some(extra) = positional.next() {
+            return Err(format!("unexpected argument {:?}", extra));
+        }
+
+        Ok(Cli {
+            command,
+            config_path: config_path.unwrap_or("./config.toml".to_string()),
+            log_level,
+            dry_run,
+        })
+    }
+}
+
+/// `check`: validate the config, returns the exit code
+pub async fn check_config(path: &str) -> i32 {
+    match spawn_config(&path).await {
+        Ok(config) => {
+            let enabled = config.sites_config.iter().filter(|s| s.enabled).count();
+            println!(
+                "{}: ok, {} sites ({} enabled)",
+                path,
+                config.sites_config.len(),
+                enabled
+            );
+            0
+        }
+        Err(e) => {
+            // every problem, one per line
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+/// `list-interfaces`: print every address with the `index` a site would use to pick it
+pub async fn list_interfaces() -> i32 {
+    let interfaces = match get_interfaces().await {
+        Ok(interfaces) => interfaces,
+        Err(_) => {
+            eprintln!("Error: failed to get interfaces");
+            return 1;
+        }
+    };
+    for itf in interfaces.iter() {
+        println!("{}", itf.name);
+        let mut indexes: BTreeMap<String, usize> = BTreeMap::new();
+        for ip in get_interface_ips(&interfaces, &itf.name).await {
+            let (version, address_type, details) = match &ip {
+                IPAddress::V4(_, address_type) => ("V4", address_type, None),
+                IPAddress::V6(_, address_type, details) => ("V6", address_type, Some(details)),
+            };
+            let index = indexes
+                .entry(format!("{}{:?}", version, address_type))
+                .or_insert(0);
+            println!(
+                "  {} {:<12} index {}  {}{}",
+                version,
+                format!("{:?}", address_type),
+                index,
+                ip.address(),
+                details.map(describe_details).unwrap_or_default()
+            );
+            *index += 1;
+        }
+    }
+    0
+}
+
+/// What `address_policy` looks at, e.g. `  (temporary, preferred 3600s)`
+fn describe_details(details: &AddressDetails) -> String {
+    let mut flags = Vec::new();
+    if details.temporary {
+        flags.push("temporary".to_string());
+    }
+    if details.deprecated {
+        flags.push("deprecated".to_string());
+    }
+    if details.tentative {
+        flags.push("tentative".to_string());
+    }
+    if let Some(lifetime) = details.preferred_lifetime {
+        flags.push(format!("preferred {}s", lifetime));
+    }
+    match flags.is_empty() {
+        true => String::new(),
+        false => format!("  ({})", flags.join(", ")),
+    }
+}
+
+/// `once`: one update round for the given sites (all enabled ones when empty)
+pub async fn run_once(names: &[String]) -> i32 {
+    let sites = match select_sites(names).await {
+        Ok(sites) => sites,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+    let mut code = 0;
+    for site in sites.iter() {
+        // failures are already logged and recorded by the runner
+        for (name, result) in SiteRunner::new(site).await.run_round().await {
+            match result {
+                Ok(_) => println!("{}: ok", name),
+                Err(e) => {
+                    eprintln!("{}: {}", name, e);
+                    code = 1;
+                }
+            }
+        }
+    }
+    code
+}
+
+/// `get`: print the record a site currently has at its provider
+pub async fn get_record(name: &str) -> i32 {
+    let site = match find_site(name).await {
+        Ok(site) => site,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+    let mut code = 0;
+    for site in site.families().iter().flat_map(|family| family.targets()) {
+        let mut provider = site.provider.to_provider();
+        restore_record_id(&site, provider.as_mut()).await;
+        match current_value(&site, provider.as_mut()).await {
+            Ok(ip) => println!("{}: {}", site.name, ip),
+            Err(ProvidersErrorType::NoRecordFound) => println!("{}: no record", site.name),
+            Err(e) => {
+                eprintln!("{}: {}", site.name, e);
+                code = 1;
+            }
+        }
+    }
+    code
+}
+
+/// `set`: publish `ip` to every target of a site even if the records already have it
+pub async fn set_record(name: &str, ip: &str) -> i32 {
+    let site = match find_site(name).await {
+        Ok(site) => site,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+    // a dual-stack site has a family for each version, pick the one matching `ip`
+    let mut last_error = String::new();
+    let mut family = None;
+    for candidate in site.families() {
+        match validate_address(ip, &candidate.address_version) {
+            Ok(_) => {
+                family = Some(candidate);
+                break;
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    let family = match family {
+        Some(family) => family,
+        None => {
+            eprintln!("Error: {}", last_error);
+            return 1;
+        }
+    };
+    let mut code = 0;
+    for target in family.targets() {
+        if !set_target_record(&target, ip).await {
+            code = 1;
+        }
+    }
+    code
+}
+
+async fn set_target_record(site: &SiteConfig, ip: &str) -> bool {
+    let dry_run = is_dry_run(site).await;
+    let mut provider = site.provider.to_provider();
+    restore_record_id(site, provider.as_mut()).await;
+    provider.set_dry_run(dry_run);
+    // providers learn about the existing record while querying it
+    let cloud_ip = match current_value(site, provider.as_mut()).await {
+        Ok(cloud_ip) => cloud_ip,
+        Err(ProvidersErrorType::NoRecordFound) => String::new(),
+        Err(e) => {
+            eprintln!("{}: {}", site.name, e);
+            return false;
+        }
+    };
+    if dry_run {
+        println!("{}: dry run, would set {:?} -> {}", site.name, cloud_ip, ip);
+        return true;
+    }
+    match provider.update(ip).await {
+        Ok(_) => {
+            STATE
+                .lock()
+                .await
+                .record_success(&site.name, &site.provider, ip, provider.record_id())
+                .await;
+            println!("{}: {:?} -> {}", site.name, cloud_ip, ip);
+            true
+        }
+        Err(e) => {
+            eprintln!("{}: {}", site.name, e);
+            STATE.lock().await.record_failure(&site.name, &e).await;
+            false
+        }
+    }
+}
+
+async fn find_site(name: &str) -> Result<SiteConfig, String> {
+    match CONFIG
+        .lock()
+        .await
+        .sites_config
+        .iter()
+        .find(|site| site.name == name)
+    {
+        Some(site) if site.enabled => Ok(site.clone()),
+        Some(_) => Err(format!("site {:?} is disabled", name)),
+        None => Err(format!("no site named {:?}", name)),
+    }
+}
+
+async fn select_sites(names: &[String]) -> Result<Vec<SiteConfig>, String> {
+    if names.is_empty() {
+        let config = CONFIG.lock().await;
+        return Ok(config
+            .sites_config
+            .iter()
+            .filter(|site| site.enabled)
+            .cloned()
+            .collect());
+    }
+    let mut sites = Vec::new();
+    for name in names {
+        sites.push(find_site(name).await?);
+    }
+    Ok(sites)
+}
+
+async fn restore_record_id(site: &SiteConfig, provider: &mut dyn DDNSProviderTrait) {
+    let state = STATE.lock().await.site_for(&site.name, &site.provider);
+    if let Some(record_id) = state.record_id {
+        provider.set_record_id(&record_id);
+    }
+}
@@ -1 +1,3 @@
+pub mod reload;
 pub mod spawn;
+pub mod validate;
@@ -0,0 +1,87 @@
+use std::{path::Path, time::Duration, time::SystemTime};
+
+use log::{error, info, warn};
+use tokio::{sync::mpsc, time::sleep};
+
+use crate::mods::{handle::reconcile_tasks, statics::CONFIG};
+
+use super::spawn::spawn_config;
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Load `path` again and apply it to the running tasks.
+///
+/// An invalid config is rejected and the current one keeps running.
+pub async fn reload_config<T: AsRef<Path>>(path: &T) -> Result<(), String> {
+    let config = spawn_config(path).await?;
+    {
+        let mut current = CONFIG.lock().await;
+        if current.log_level.to_string() != config.log_level.to_string()
+            || current.state_file != config.state_file
+        {
+            warn!("log_level and state_file changes take effect after a restart");
+        }
+        *current = config.clone();
+    }
+    reconcile_tasks(&config).await;
+    Ok(())
+}
+
+/// Reload the config whenever the file changes or SIGHUP is received
+pub async fn watch_config(path: String) {
+    let (reload_tx, mut reload_rx) = mpsc::channel::<()>(1);
+    tokio::spawn(forward_hangup(reload_tx));
+
+    let mut modified = file_modified(&path).await;
+    loop {
+        tokio::select! {
+            _ = sleep(WATCH_INTERVAL) => {
+                let now = match file_modified(&path).await {
+                    Some(now) => now,
+                    None => continue, // being replaced, look again later
+                };
+                if Some(now) == modified {
+                    continue;
+                }
+                modified = Some(now);
+                info!("Config file changed, reloading");
+            }
+            _ = reload_rx.recv() => {
+                modified = file_modified(&path).await;
+                info!("SIGHUP received, reloading config");
+            }
+        }
+        match reload_config(&path).await {
+            Ok(_) => info!("Config reloaded"),
+            Err(e) => error!("Rejected new config, keeping the old one: {}", e),
+        }
+    }
+}
+
+async fn file_modified(path: &str) -> Option<SystemTime> {
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata.modified().ok(),
+        Err(_) => None,
+    }
+}
+
+#[cfg(unix)]
+async fn forward_hangup(reload_tx: mpsc::Sender<()>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!("Failed to listen for SIGHUP: {}", e);
+            return std::future::pending().await;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        let _ = reload_tx.try_send(());
+    }
+}
+
+#[cfg(not(unix))]
+async fn forward_hangup(_reload_tx: mpsc::Sender<()>) {
+    // no SIGHUP here, keep the sender alive so only file changes trigger a reload
+    std::future::pending().await
+}
@@ -2,23 +2,18 @@ use std::path::Path;
 
 use crate::mods::types::Config;
 
+use super::validate::validate_config;
+
+/// Load and validate the config at `path`. Nothing is printed, callers report the error
 pub async fn spawn_config<T: AsRef<Path>>(path: &T) -> Result<Config, String> {
     let raw_config = Config::new_from_path(path).await;
     let config: Config = match raw_config {
         Ok(config) => config,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            return Err("failed to open config".to_string());
-        }
+        Err(e) => return Err(format!("failed to open config: {}", e)),
     };
-    // check unique name
-    let mut names = Vec::new();
-    for site in config.sites_config.iter() {
-        if names.contains(&site.name) {
-            eprintln!("Error: duplicate site name");
-            return Err("duplicate site name".to_string());
-        }
-        names.push(site.name.clone());
+    // check every site (unique names, required fields, record types ...)
+    if let Err(errors) = validate_config(&config) {
+        return Err(format!("invalid config:\n{}", errors.join("\n")));
     }
     Ok(config)
 }
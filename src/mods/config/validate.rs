@@ -0,0 +1,626 @@
+use regex::Regex;
+
+use crate::mods::{
+    dns::{encode_name, parse_nameserver, DnsLookup},
+    interfaces::{AddressPolicy, Cidr, InterfaceId, InterfaceMatcher},
+    providers::custom::CustomRequest,
+    request::RequestMethod,
+    sources::{
+        command::CommandSource,
+        file::FileSource,
+        gateway::{parse_gateway, GatewaySource},
+        prefix::PrefixSource,
+        IpSource,
+    },
+    types::{AddressVersion, Config, DDNSProvider, SiteConfig},
+};
+
+/// Check the whole config and report every problem found, not just the first one.
+///
+/// Disabled sites are skipped entirely.
+pub fn validate_config(config: &Config) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    let mut names: Vec<&str> = Vec::new();
+    for (index, site) in config.sites_config.iter().enumerate() {
+        if !site.enabled {
+            continue;
+        }
+        let path = format!("sites_config[{}] ({:?})", index, site.name);
+        if names.contains(&site.name.as_str()) {
+            errors.push(format!("{}: name: duplicate site name", path));
+        }
+        names.push(&site.name);
+        validate_site(site, &path, &mut errors);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn validate_site(site: &SiteConfig, path: &str, errors: &mut Vec<String>) {
+    let mut error = |field: &str, message: &str| {
+        errors.push(format!("{}: {}: {}", path, field, message));
+    };
+
+    if site.name.is_empty() {
+        error("name", "must not be empty");
+    }
+    if site.interval == 0 {
+        error("interval", "must be greater than 0");
+    }
+
+    if site.source.reads_interface() && site.interface.is_empty() {
+        error("interface", "must not be empty");
+    }
+    validate_source(&site.source, &site.address_version, "source", &mut error);
+
+    if site.source.reads_interface() && !site.interface.is_empty() {
+        if let Err(e) = InterfaceMatcher::parse(&site.interface) {
+            error("interface", &e);
+        }
+    }
+
+    for (field, cidrs) in [("include", &site.include), ("exclude", &site.exclude)] {
+        for (i, cidr) in cidrs.iter().enumerate() {
+            if let Err(e) = Cidr::parse(cidr) {
+                error(&format!("{}[{}]", field, i), &e);
+            }
+        }
+    }
+
+    if let Some(policy) = &site.address_policy {
+        validate_policy(
+            policy,
+            &site.source,
+            &site.address_version,
+            "address_policy",
+            &mut error,
+        );
+    }
+
+    if let Some(lookup) = &site.lookup {
+        validate_lookup(site, lookup, "lookup", &mut error);
+        let several = site.count != 1 || site.dual_stack.as_ref().is_some_and(|f| f.count != 1);
+        if several {
+            error(
+                "lookup",
+                "only applies to sites publishing a single address",
+            );
+        }
+    }
+    if let Some(check) = &site.verify {
+        validate_lookup(site, &check.lookup, "verify", &mut error);
+        if check.poll_interval == 0 {
+            error("verify.poll_interval", "must be at least 1 second");
+        }
+        if check.deadline < check.poll_interval {
+            error("verify.deadline", "must not be shorter than poll_interval");
+        }
+    }
+
+    if let Some(family) = &site.dual_stack {
+        let version = match site.address_version {
+            AddressVersion::V4 => AddressVersion::V6,
+            AddressVersion::V6 => AddressVersion::V4,
+        };
+        if let Some(policy) = &family.address_policy {
+            validate_policy(
+                policy,
+                &family.source,
+                &version,
+                "dual_stack.address_policy",
+                &mut error,
+            );
+        }
+        if family.source.reads_interface()
+            && family.interface.is_empty()
+            && site.interface.is_empty()
+        {
+            error("dual_stack.interface", "must not be empty");
+        }
+        if family.source.reads_interface() && !family.interface.is_empty() {
+            if let Err(e) = InterfaceMatcher::parse(&family.interface) {
+                error("dual_stack.interface", &e);
+            }
+        }
+        validate_source(&family.source, &version, "dual_stack.source", &mut error);
+        if family.count != 1 && !family.source.reads_interface() {
+            error(
+                "dual_stack.count",
+                "only the Interface and Prefix sources can publish several addresses",
+            );
+        }
+    }
+
+    let mut providers = vec![("provider".to_string(), &site.provider)];
+    let mut target_names: Vec<&str> = Vec::new();
+    for (i, target) in site.targets.iter().enumerate() {
+        if target.name.is_empty() {
+            error(&format!("targets[{}].name", i), "must not be empty");
+        } else if target_names.contains(&target.name.as_str()) {
+            error(&format!("targets[{}].name", i), "duplicate target name");
+        }
+        target_names.push(&target.name);
+        if let Some(host_suffix) = &target.host_suffix {
+            let path = format!("targets[{}].host_suffix", i);
+            let prefix = [
+                Some(&site.source),
+                site.dual_stack.as_ref().map(|f| &f.source),
+            ]
+            .into_iter()
+            .flatten()
+            .find_map(|source| match source {
+                IpSource::Prefix(source) => Some(source),
+                _ => None,
+            });
+            match prefix {
+                Some(source) => {
+                    let source = PrefixSource::new(source.prefix_len, host_suffix);
+                    if let Err(e) = source.suffix() {
+                        error(&path, &e);
+                    }
+                }
+                None => error(&path, "only applies to the Prefix source"),
+            }
+        }
+        providers.push((format!("targets[{}].provider", i), &target.provider));
+    }
+    for (prefix, provider) in providers {
+        validate_provider(site, &prefix, provider, &mut error);
+    }
+}
+
+fn validate_command(source: &CommandSource, path: &str, error: &mut impl FnMut(&str, &str)) {
+    match (source.shell, source.command.as_slice()) {
+        (_, []) => error(&format!("{}.command", path), "must not be empty"),
+        (true, [_]) => {}
+        (true, _) => error(
+            &format!("{}.command", path),
+            "must be a single string when shell is set",
+        ),
+        (false, [program, ..]) if program.is_empty() => {
+            error(&format!("{}.command", path), "program must not be empty")
+        }
+        (false, _) => {}
+    }
+    if source.timeout == 0 {
+        error(&format!("{}.timeout", path), "must be at least 1 second");
+    }
+    validate_source_regex(&source.regex, path, error);
+}
+
+fn validate_file(source: &FileSource, path: &str, error: &mut impl FnMut(&str, &str)) {
+    if source.path.is_empty() {
+        error(&format!("{}.path", path), "must not be empty");
+    }
+    validate_source_regex(&source.regex, path, error);
+}
+
+fn validate_source_regex(regex: &Option<String>, path: &str, error: &mut impl FnMut(&str, &str)) {
+    if let Some(pattern) = regex {
+        if let Err(e) = Regex::new(pattern) {
+            error(&format!("{}.regex", path), &format!("invalid regex: {}", e));
+        }
+    }
+}
+
+fn validate_lookup(
+    site: &SiteConfig,
+    lookup: &DnsLookup,
+    path: &str,
+    error: &mut impl FnMut(&str, &str),
+) {
+    for (field, servers) in [
+        ("nameservers", &lookup.nameservers),
+        ("resolvers", &lookup.resolvers),
+    ] {
+        for (i, server) in servers.iter().enumerate() {
+            if let Err(e) = parse_nameserver(server) {
+                error(&format!("{}.{}[{}]", path, field, i), &e);
+            }
+        }
+    }
+    if lookup.timeout == 0 {
+        error(&format!("{}.timeout", path), "must be at least 1 second");
+    }
+    match &lookup.name {
+        Some(name) => {
+            if let Err(e) = encode_name(name) {
+                error(&format!("{}.name", path), &e);
+            }
+        }
+        None => {
+            let providers = std::iter::once(&site.provider)
+                .chain(site.targets.iter().map(|target| &target.provider));
+            if providers
+                .into_iter()
+                .any(|provider| provider.record_name().is_none())
+            {
+                error(
+                    &format!("{}.name", path),
+                    "must be set, a provider has no domain to take the record name from",
+                );
+            }
+        }
+    }
+}
+
+fn validate_gateway(
+    source: &GatewaySource,
+    version: &AddressVersion,
+    path: &str,
+    error: &mut impl FnMut(&str, &str),
+) {
+    if *version != AddressVersion::V4 {
+        error(path, "only applies to v4 addresses");
+    }
+    if source.protocols.is_empty() {
+        error(&format!("{}.protocols", path), "must not be empty");
+    }
+    if let Some(gateway) = &source.gateway {
+        if let Err(e) = parse_gateway(gateway) {
+            error(&format!("{}.gateway", path), &e);
+        }
+    }
+    if let Some(location) = &source.location {
+        if reqwest::Url::parse(location).is_err() {
+            error(
+                &format!("{}.location", path),
+                &format!("{:?} is not a valid url", location),
+            );
+        }
+    }
+}
+
+fn validate_prefix(
+    source: &PrefixSource,
+    version: &AddressVersion,
+    path: &str,
+    error: &mut impl FnMut(&str, &str),
+) {
+    if *version != AddressVersion::V6 {
+        error(path, "only applies to v6 addresses");
+    }
+    if let Err(e) = source.suffix() {
+        error(path, &e);
+    }
+}
+
+fn validate_policy(
+    policy: &AddressPolicy,
+    source: &IpSource,
+    version: &AddressVersion,
+    path: &str,
+    error: &mut impl FnMut(&str, &str),
+) {
+    if !source.reads_interface() {
+        error(path, "only applies to the Interface and Prefix sources");
+    }
+    if let Some(prefix) = &policy.prefix {
+        match (Cidr::parse(prefix), version) {
+            (Err(e), _) => error(&format!("{}.prefix", path), &e),
+            (Ok(Cidr { address, .. }), AddressVersion::V4) if address.is_ipv6() => error(
+                &format!("{}.prefix", path),
+                "is an IPv6 prefix but address_version is v4",
+            ),
+            (Ok(Cidr { address, .. }), AddressVersion::V6) if address.is_ipv4() => error(
+                &format!("{}.prefix", path),
+                "is an IPv4 prefix but address_version is v6",
+            ),
+            _ => {}
+        }
+    }
+    if let Some(interface_id) = &policy.interface_id {
+        if let Err(e) = InterfaceId::parse(interface_id) {
+            error(&format!("{}.interface_id", path), &e);
+        }
+    }
+    let v6_only = policy.exclude_ula
+        || policy.prefer_stable
+        || policy.prefer_lifetime
+        || policy.interface_id.is_some();
+    if v6_only && *version == AddressVersion::V4 {
+        error(path, "only prefix applies to v4 addresses");
+    }
+}
+
+fn validate_provider(
+    site: &SiteConfig,
+    prefix: &str,
+    provider: &DDNSProvider,
+    error: &mut impl FnMut(&str, &str),
+) {
+    let (provider_name, required, record) = provider_fields(provider);
+    let path = format!("{}.{}", prefix, provider_name);
+    if site.count != 1 {
+        if !site.source.reads_interface() {
+            error(
+                "count",
+                "only the Interface and Prefix sources can publish several addresses",
+            );
+        }
+        if !supports_record_sets(provider) {
+            error(
+                "count",
+                &format!("{} cannot publish several addresses", provider_name),
+            );
+        }
+    }
+    if let Some(family) = &site.dual_stack {
+        if family.count != 1 && !supports_record_sets(provider) {
+            error(
+                "dual_stack.count",
+                &format!("{} cannot publish several addresses", provider_name),
+            );
+        }
+        match provider {
+            DDNSProvider::Namecheap(_) => error("dual_stack", "Namecheap only supports A records"),
+            // both families would send the same requests and overwrite each other
+            DDNSProvider::Custom(_) => error(
+                "dual_stack",
+                "Custom requests cannot tell the A record from the AAAA one, use two sites",
+            ),
+            _ => {}
+        }
+    }
+    for (field, value) in required {
+        if value.is_empty() {
+            error(&format!("{}.{}", path, field), "must not be empty");
+        }
+    }
+
+    if let Some((rr, domain, record_type)) = record {
+        match (record_type, &site.address_version) {
+            ("A", AddressVersion::V6) | ("AAAA", AddressVersion::V4) => error(
+                &format!("{}.record_type", path),
+                &format!(
+                    "{} record does not match address_version {}",
+                    record_type,
+                    site.address_version.to_string()
+                ),
+            ),
+            ("A", _) | ("AAAA", _) => {}
+            (other, _) => error(
+                &format!("{}.record_type", path),
+                &format!("{:?} is not supported, use A or AAAA", other),
+            ),
+        }
+        if !domain.is_empty() {
+            if let Err(e) = validate_domain_name(domain) {
+                error(&format!("{}.domain", path), &e);
+            }
+        }
+        if !matches!(rr, "" | "@") {
+            if let Err(e) = validate_domain_name(rr.trim_start_matches("*.")) {
+                error(&format!("{}.rr", path), &e);
+            }
+        }
+    }
+
+    if let DDNSProvider::Rfc2136(rfc2136) = provider {
+        if !rfc2136.server.is_empty() {
+            if let Err(e) = parse_nameserver(&rfc2136.server) {
+                error(&format!("{}.server", path), &e);
+            }
+        }
+        if rfc2136.timeout == 0 {
+            error(&format!("{}.timeout", path), "must be at least 1 second");
+        }
+        if let Some(key) = &rfc2136.tsig {
+            if let Err(e) = validate_domain_name(&key.name) {
+                error(&format!("{}.tsig.name", path), &e);
+            }
+            if let Err(e) = key.secret() {
+                error(&format!("{}.tsig.secret", path), &e);
+            }
+        }
+    }
+
+    if let DDNSProvider::Custom(custom) = provider {
+        validate_custom_request(&custom.update, &format!("{}.update", path), error);
+        if let Some(query) = &custom.query {
+            validate_custom_request(&query.request, &format!("{}.query", path), error);
+            if let Some(pattern) = &query.regex {
+                if let Err(e) = Regex::new(pattern) {
+                    error(&format!("{}.query.regex", path), &e.to_string());
+                }
+            }
+        }
+    }
+}
+
+/// Check a source of the site or of its `dual_stack` family, under `path`
+fn validate_source(
+    source: &IpSource,
+    version: &AddressVersion,
+    path: &str,
+    error: &mut impl FnMut(&str, &str),
+) {
+    match source {
+        IpSource::Interface => {}
+        IpSource::Http(source) => {
+            if source.endpoints.is_empty() {
+                error(&format!("{}.Http.endpoints", path), "must not be empty");
+            }
+            if source.consensus > source.endpoints.len() {
+                error(
+                    &format!("{}.Http.consensus", path),
+                    &format!(
+                        "{} exceeds the number of endpoints ({})",
+                        source.consensus,
+                        source.endpoints.len()
+                    ),
+                );
+            }
+            for (i, endpoint) in source.endpoints.iter().enumerate() {
+                if reqwest::Url::parse(&endpoint.url).is_err() {
+                    error(
+                        &format!("{}.Http.endpoints[{}].url", path, i),
+                        &format!("{:?} is not a valid url", endpoint.url),
+                    );
+                }
+                if let Some(pattern) = &endpoint.regex {
+                    if let Err(e) = Regex::new(pattern) {
+                        error(
+                            &format!("{}.Http.endpoints[{}].regex", path, i),
+                            &e.to_string(),
+                        );
+                    }
+                }
+            }
+        }
+        IpSource::Stun(source) => {
+            if source.servers.is_empty() {
+                error(&format!("{}.Stun.servers", path), "must not be empty");
+            }
+            if source.consensus > source.servers.len() {
+                error(
+                    &format!("{}.Stun.consensus", path),
+                    &format!(
+                        "{} exceeds the number of servers ({})",
+                        source.consensus,
+                        source.servers.len()
+                    ),
+                );
+            }
+        }
+        IpSource::Gateway(source) => {
+            validate_gateway(source, version, &format!("{}.Gateway", path), error);
+        }
+        IpSource::Command(source) => {
+            validate_command(source, &format!("{}.Command", path), error);
+        }
+        IpSource::File(source) => {
+            validate_file(source, &format!("{}.File", path), error);
+        }
+        IpSource::Prefix(source) => {
+            validate_prefix(source, version, &format!("{}.Prefix", path), error);
+        }
+    }
+}
+
+fn supports_record_sets(provider: &DDNSProvider) -> bool {
+    matches!(
+        provider,
+        DDNSProvider::Aliyun(_)
+            | DDNSProvider::Cloudflare(_)
+            | DDNSProvider::Dnspod(_)
+            | DDNSProvider::Dynv6(_)
+    )
+}
+
+/// Returns the provider name, its required string fields and (rr, domain, record_type) if it has them
+#[allow(clippy::type_complexity)]
+fn provider_fields(
+    provider: &DDNSProvider,
+) -> (
+    &'static str,
+    Vec<(&'static str, &str)>,
+    Option<(&str, &str, &str)>,
+) {
+    match provider {
+        DDNSProvider::Aliyun(value) => (
+            "Aliyun",
+            vec![
+                ("access_key_id", value.access_key_id.as_str()),
+                ("access_key_secret", value.access_key_secret.as_str()),
+                ("domain", value.domain.as_str()),
+            ],
+            Some((&value.rr, &value.domain, &value.record_type)),
+        ),
+        DDNSProvider::Cloudflare(value) => (
+            "Cloudflare",
+            vec![
+                ("zone_id", value.zone_id.as_str()),
+                ("api_token", value.api_token.as_str()),
+                ("domain", value.domain.as_str()),
+            ],
+            Some((&value.rr, &value.domain, &value.record_type)),
+        ),
+        DDNSProvider::Dnspod(value) => (
+            "Dnspod",
+            vec![
+                ("id", value.id.as_str()),
+                ("token", value.token.as_str()),
+                ("domain", value.domain.as_str()),
+            ],
+            Some((&value.rr, &value.domain, &value.record_type)),
+        ),
+        DDNSProvider::Dynv6(value) => (
+            "Dynv6",
+            vec![("token", value.token.as_str())],
+            Some((&value.rr, "", &value.record_type)),
+        ),
+        DDNSProvider::Namecheap(value) => (
+            "Namecheap",
+            vec![
+                ("domain", value.domain.as_str()),
+                ("password", value.password.as_str()),
+            ],
+            Some((&value.rr, &value.domain, "A")),
+        ),
+        DDNSProvider::Rfc2136(value) => (
+            "Rfc2136",
+            vec![
+                ("server", value.server.as_str()),
+                ("domain", value.domain.as_str()),
+            ],
+            Some((&value.rr, &value.domain, &value.record_type)),
+        ),
+        DDNSProvider::Custom(value) => (
+            "Custom",
+            vec![("update.url", value.update.url.as_str())],
+            None,
+        ),
+    }
+}
+
+fn validate_custom_request(
+    request: &CustomRequest,
+    path: &str,
+    error: &mut impl FnMut(&str, &str),
+) {
+    if RequestMethod::from_name(&request.method).is_none() {
+        error(
+            &format!("{}.method", path),
+            &format!("{:?} is not a supported method", request.method),
+        );
+    }
+    if request.url.is_empty() {
+        error(&format!("{}.url", path), "must not be empty");
+    }
+    if let Some(pattern) = &request.success_body {
+        if let Err(e) = Regex::new(pattern) {
+            error(&format!("{}.success_body", path), &e.to_string());
+        }
+    }
+}
+
+/// Check that `name` is a valid (possibly internationalized) domain name or relative name
+pub fn validate_domain_name(name: &str) -> Result<(), String> {
+    let ascii = match idna::domain_to_ascii(name.trim_end_matches('.')) {
+        Ok(value) => value,
+        Err(_) => return Err(format!("{:?} is not a valid domain name", name)),
+    };
+    if ascii.is_empty() || ascii.len() > 253 {
+        return Err(format!("{:?} is not a valid domain name", name));
+    }
+    for label in ascii.split('.') {
+        if label.is_empty()
+            || label.len() > 63
+            || label.starts_with('-')
+            || label.ends_with('-')
+            || !label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "{:?} is not a valid domain name (label {:?})",
+                name, label
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,601 @@
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    time::{timeout, Instant},
+};
+
+use super::{sources::random_id, types::AddressVersion};
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_AAAA: u16 = 28;
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_ANY: u16 = 255;
+pub const RCODE_NXDOMAIN: u8 = 3;
+const DNS_PORT: u16 = 53;
+const INITIAL_RTO: Duration = Duration::from_millis(500);
+
+/// Read the value a record publishes with plain DNS queries, for providers that cannot
+/// be asked (write-only dyndns endpoints) or that block no-op updates
+#[derive(Deserialize, Serialize, Clone)]
+pub struct DnsLookup {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>, // the record name of the provider by default
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nameservers: Vec<String>, // "ip" or "ip:port"; the zone's authoritative servers when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resolvers: Vec<String>, // used to find the authoritative servers, /etc/resolv.conf by default
+    #[serde(default)]
+    pub tcp: bool, // UDP first (TCP for truncated answers) otherwise
+    #[serde(default = "default_timeout")]
+    pub timeout: u64, // seconds, per server
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+/// Poll DNS after an update until the new value is served
+#[derive(Deserialize, Serialize, Clone)]
+pub struct PropagationCheck {
+    #[serde(flatten)]
+    pub lookup: DnsLookup, // where to ask, the zone's authoritative servers by default
+    #[serde(default = "default_deadline")]
+    pub deadline: u64, // seconds the new value has to show up in
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64, // seconds between two queries
+    #[serde(default)]
+    pub fail_on_timeout: bool, // count a record that never converges as a failed update
+}
+
+fn default_deadline() -> u64 {
+    300
+}
+
+fn default_poll_interval() -> u64 {
+    10
+}
+
+impl DnsLookup {
+    pub fn new(nameservers: Vec<String>) -> Self {
+        Self {
+            name: None,
+            nameservers,
+            resolvers: Vec::new(),
+            tcp: false,
+            timeout: default_timeout(),
+        }
+    }
+
+    /// The A or AAAA values of `name`, sorted; empty when there is no such record
+    pub async fn lookup(
+        &self,
+        name: &str,
+        version: &AddressVersion,
+    ) -> Result<Vec<String>, String> {
+        let qtype = match version {
+            AddressVersion::V4 => TYPE_A,
+            AddressVersion::V6 => TYPE_AAAA,
+        };
+        // recursive servers may answer from their cache, authoritative ones cannot
+        let (servers, recursive) = match self.nameservers.is_empty() {
+            false => (parse_servers(&self.nameservers)?, true),
+            true => {
+                let resolvers = self.resolvers()?;
+                let servers = authoritative_servers(name, &resolvers, self.wait()).await?;
+                let servers = servers
+                    .into_iter()
+                    .map(|ip| SocketAddr::new(ip, DNS_PORT))
+                    .collect();
+                (servers, false)
+            }
+        };
+        let mut errors = Vec::new();
+        for server in servers.iter() {
+            match query(*server, name, qtype, recursive, self.tcp, self.wait()).await {
+                Ok(message) if message.rcode == RCODE_NXDOMAIN => return Ok(Vec::new()),
+                Ok(message) if message.rcode == 0 => {
+                    // CNAME targets come along in the answer section
+                    let mut ips = message
+                        .answers
+                        .iter()
+                        .filter_map(|record| match record.data {
+                            RecordData::A(ip) if qtype == TYPE_A => Some(ip.to_string()),
+                            RecordData::Aaaa(ip) if qtype == TYPE_AAAA => Some(ip.to_string()),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>();
+                    ips.sort();
+                    ips.dedup();
+                    debug!("{} answered {} with {:?}", server, name, ips);
+                    return Ok(ips);
+                }
+                Ok(message) => errors.push(format!("{}: rcode {}", server, message.rcode)),
+                Err(e) => errors.push(format!("{}: {}", server, e)),
+            }
+            warn!("DNS query to {} failed: {}", server, errors.last().unwrap());
+        }
+        Err(format!(
+            "no nameserver answered for {} ({})",
+            name,
+            errors.join("; ")
+        ))
+    }
+
+    fn resolvers(&self) -> Result<Vec<SocketAddr>, String> {
+        match self.resolvers.is_empty() {
+            false => parse_servers(&self.resolvers),
+            true => system_resolvers(),
+        }
+    }
+
+    fn wait(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+impl PropagationCheck {
+    pub fn new(lookup: DnsLookup) -> Self {
+        Self {
+            lookup,
+            deadline: default_deadline(),
+            poll_interval: default_poll_interval(),
+            fail_on_timeout: false,
+        }
+    }
+
+    /// Query `name` until it serves exactly `expected` (sorted), returns how long it took.
+    /// A stale duplicate record keeps it from converging
+    pub async fn wait_for(
+        &self,
+        name: &str,
+        version: &AddressVersion,
+        expected: &[String],
+    ) -> Result<Duration, String> {
+        let started = Instant::now();
+        let deadline = started + Duration::from_secs(self.deadline);
+        let mut served = Vec::new();
+        loop {
+            match self.lookup.lookup(name, version).await {
+                Ok(ips) if ips == expected => return Ok(started.elapsed()),
+                Ok(ips) => served = ips,
+                Err(e) => debug!("propagation check of {}: {}", name, e),
+            }
+            let next = Instant::now() + Duration::from_secs(self.poll_interval.max(1));
+            if next > deadline {
+                return Err(format!(
+                    "{} still serves {:?} instead of {:?} after {}s",
+                    name, served, expected, self.deadline
+                ));
+            }
+            tokio::time::sleep_until(next).await;
+        }
+    }
+}
+
+/// Addresses of the nameservers of the zone `name` belongs to: the NS records of the
+/// closest enclosing name that has some, asked from `resolvers`
+pub async fn authoritative_servers(
+    name: &str,
+    resolvers: &[SocketAddr],
+    wait: Duration,
+) -> Result<Vec<IpAddr>, String> {
+    // answers carry the ASCII form of the names
+    let name = to_ascii(name)?;
+    let mut zone = name.as_str();
+    loop {
+        let message = ask_resolvers(resolvers, zone, TYPE_NS, wait).await?;
+        let hosts = message
+            .answers
+            .iter()
+            .filter(|record| record.name.eq_ignore_ascii_case(zone))
+            .filter_map(|record| match &record.data {
+                RecordData::Name(host) if record.rtype == TYPE_NS => Some(host.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !hosts.is_empty() {
+            trace!("nameservers of {}: {:?}", zone, hosts);
+            let mut servers = Vec::new();
+            for host in hosts.iter() {
+                servers.append(&mut host_addresses(&message, host, resolvers, wait).await);
+            }
+            return match servers.is_empty() {
+                true => Err(format!("no address for the nameservers {:?}", hosts)),
+                false => Ok(servers),
+            };
+        }
+        zone = match zone.split_once('.') {
+            Some((_, parent)) if parent.contains('.') => parent,
+            _ => return Err(format!("no NS records found for {}", name)),
+        };
+    }
+}
+
+/// Glue records of `message` for `host`, or its A and AAAA records
+async fn host_addresses(
+    message: &Message,
+    host: &str,
+    resolvers: &[SocketAddr],
+    wait: Duration,
+) -> Vec<IpAddr> {
+    let addresses = |message: &Message| {
+        message
+            .answers
+            .iter()
+            .chain(message.additional.iter())
+            .filter(|record| record.name.eq_ignore_ascii_case(host))
+            .filter_map(|record| match record.data {
+                RecordData::A(ip) => Some(IpAddr::V4(ip)),
+                RecordData::Aaaa(ip) => Some(IpAddr::V6(ip)),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    let glue = addresses(message);
+    if !glue.is_empty() {
+        return glue;
+    }
+    let mut ips = Vec::new();
+    for qtype in [TYPE_A, TYPE_AAAA] {
+        match ask_resolvers(resolvers, host, qtype, wait).await {
+            Ok(message) => ips.append(&mut addresses(&message)),
+            Err(e) => warn!("failed to resolve nameserver {}: {}", host, e),
+        }
+    }
+    ips
+}
+
+/// A recursive query, to the first resolver answering
+async fn ask_resolvers(
+    resolvers: &[SocketAddr],
+    name: &str,
+    qtype: u16,
+    wait: Duration,
+) -> Result<Message, String> {
+    let mut last_error = "no resolver configured".to_string();
+    for resolver in resolvers.iter() {
+        match query(*resolver, name, qtype, true, false, wait).await {
+            Ok(message) => return Ok(message),
+            Err(e) => last_error = format!("{}: {}", resolver, e),
+        }
+    }
+    Err(last_error)
+}
+
+/// "ip" or "ip:port", port 53 by default
+pub fn parse_nameserver(value: &str) -> Result<SocketAddr, String> {
+    if let Ok(address) = value.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+    match value.parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, DNS_PORT)),
+        Err(_) => Err(format!("{:?} is not an address or address:port", value)),
+    }
+}
+
+fn parse_servers(values: &[String]) -> Result<Vec<SocketAddr>, String> {
+    values.iter().map(|value| parse_nameserver(value)).collect()
+}
+
+/// The `nameserver` lines of /etc/resolv.conf
+pub fn system_resolvers() -> Result<Vec<SocketAddr>, String> {
+    let content = match std::fs::read_to_string("/etc/resolv.conf") {
+        Ok(content) => content,
+        Err(e) => return Err(format!("failed to read /etc/resolv.conf: {}", e)),
+    };
+    let resolvers = parse_resolv_conf(&content);
+    match resolvers.is_empty() {
+        true => Err("no nameserver in /etc/resolv.conf".to_string()),
+        false => Ok(resolvers),
+    }
+}
+
+pub fn parse_resolv_conf(content: &str) -> Vec<SocketAddr> {
+    content
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        // link-local servers carry a zone (`fe80::1%eth0`) that SocketAddr cannot parse
+        .filter_map(|server| server.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, DNS_PORT))
+        .collect()
+}
+
+/// One question to one server
+pub async fn query(
+    server: SocketAddr,
+    name: &str,
+    qtype: u16,
+    recursion_desired: bool,
+    tcp: bool,
+    wait: Duration,
+) -> Result<Message, String> {
+    let request = build_query(message_id(), name, qtype, recursion_desired)?;
+    parse_message(&exchange(server, &request, tcp, wait).await?)
+}
+
+/// A random ID for a new message
+pub fn message_id() -> u16 {
+    let id = random_id();
+    u16::from_be_bytes([id[0], id[1]])
+}
+
+/// Send `request` and return the raw answer carrying its ID: over UDP with retransmissions,
+/// then over TCP if the answer was truncated (or right away with `tcp`)
+pub async fn exchange(
+    server: SocketAddr,
+    request: &[u8],
+    tcp: bool,
+    wait: Duration,
+) -> Result<Vec<u8>, String> {
+    if !tcp {
+        let response = exchange_udp(server, request, wait).await?;
+        if response[2] & 0x02 == 0 {
+            return Ok(response);
+        }
+        trace!("{} truncated its answer, retrying over TCP", server);
+    }
+    match timeout(wait, exchange_tcp(server, request)).await {
+        Ok(result) => result,
+        Err(_) => Err("no response over TCP".to_string()),
+    }
+}
+
+async fn exchange_udp(
+    server: SocketAddr,
+    request: &[u8],
+    wait: Duration,
+) -> Result<Vec<u8>, String> {
+    let local_address = match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = match UdpSocket::bind(local_address).await {
+        Ok(socket) => socket,
+        Err(e) => return Err(e.to_string()),
+    };
+    if let Err(e) = socket.connect(server).await {
+        return Err(e.to_string());
+    }
+    let deadline = Instant::now() + wait;
+    let mut rto = INITIAL_RTO;
+    let mut buf = [0u8; 4096];
+    loop {
+        if let Err(e) = socket.send(request).await {
+            return Err(e.to_string());
+        }
+        let until = Instant::now() + rto.min(deadline.saturating_duration_since(Instant::now()));
+        while let Ok(received) = timeout(
+            until.saturating_duration_since(Instant::now()),
+            socket.recv(&mut buf),
+        )
+        .await
+        {
+            match received {
+                Ok(len) if len >= 12 && buf[..2] == request[..2] => return Ok(buf[..len].to_vec()),
+                Ok(_) => continue, // a late answer to an earlier message
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        if Instant::now() >= deadline {
+            return Err("no response".to_string());
+        }
+        rto *= 2;
+    }
+}
+
+/// Messages over TCP are prefixed with their length
+async fn exchange_tcp(server: SocketAddr, request: &[u8]) -> Result<Vec<u8>, String> {
+    let mut stream = match TcpStream::connect(server).await {
+        Ok(stream) => stream,
+        Err(e) => return Err(e.to_string()),
+    };
+    let mut framed = (request.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(request);
+    if let Err(e) = stream.write_all(&framed).await {
+        return Err(e.to_string());
+    }
+    let len = match stream.read_u16().await {
+        Ok(len) => len as usize,
+        Err(e) => return Err(e.to_string()),
+    };
+    let mut buf = vec![0u8; len];
+    if let Err(e) = stream.read_exact(&mut buf).await {
+        return Err(e.to_string());
+    }
+    match len >= 12 && buf[..2] == request[..2] {
+        true => Ok(buf),
+        false => Err("answer to another message".to_string()),
+    }
+}
+
+/// A query with a single question of class IN
+pub fn build_query(
+    id: u16,
+    name: &str,
+    qtype: u16,
+    recursion_desired: bool,
+) -> Result<Vec<u8>, String> {
+    let flags: u16 = match recursion_desired {
+        true => 0x0100,
+        false => 0,
+    };
+    let mut request = Vec::new();
+    request.extend_from_slice(&id.to_be_bytes());
+    request.extend_from_slice(&flags.to_be_bytes());
+    request.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]); // one question
+    request.append(&mut encode_name(name)?);
+    request.extend_from_slice(&qtype.to_be_bytes());
+    request.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(request)
+}
+
+/// Uncompressed wire format of a domain name
+pub fn encode_name(name: &str) -> Result<Vec<u8>, String> {
+    let ascii = to_ascii(name)?;
+    let mut encoded = Vec::new();
+    for label in ascii.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("{:?} is not a valid domain name", name));
+        }
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+    match encoded.len() {
+        len if len > 255 => Err(format!("{:?} is too long", name)),
+        _ => Ok(encoded),
+    }
+}
+
+/// `name` without its trailing dot, internationalized labels in their `xn--` form as they
+/// go on the wire
+pub fn to_ascii(name: &str) -> Result<String, String> {
+    match idna::domain_to_ascii(name.trim_end_matches('.')) {
+        Ok(ascii) => Ok(ascii),
+        Err(_) => Err(format!("{:?} is not a valid domain name", name)),
+    }
+}
+
+/// The parts of a DNS response the lookups need
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub id: u16,
+    pub truncated: bool,
+    pub rcode: u8,
+    pub answers: Vec<Record>,
+    pub authority: Vec<Record>,
+    pub additional: Vec<Record>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub offset: usize, // where the record starts in the message
+    pub name: String,  // without the trailing dot
+    pub rtype: u16,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Name(String), // NS and CNAME
+    Other(Vec<u8>),
+}
+
+pub fn parse_message(buf: &[u8]) -> Result<Message, String> {
+    if buf.len() < 12 {
+        return Err("truncated DNS header".to_string());
+    }
+    let count = |offset: usize| u16::from_be_bytes([buf[offset], buf[offset + 1]]) as usize;
+    let mut offset = 12;
+    for _ in 0..count(4) {
+        let (_, next) = read_name(buf, offset)?;
+        offset = next + 4; // type and class
+    }
+    let mut sections = Vec::new();
+    for records in [count(6), count(8), count(10)] {
+        let mut section = Vec::new();
+        for _ in 0..records {
+            let (record, next) = read_record(buf, offset)?;
+            section.push(record);
+            offset = next;
+        }
+        sections.push(section);
+    }
+    let additional = sections.pop().unwrap();
+    let authority = sections.pop().unwrap();
+    let answers = sections.pop().unwrap();
+    Ok(Message {
+        id: u16::from_be_bytes([buf[0], buf[1]]),
+        truncated: buf[2] & 0x02 != 0,
+        rcode: buf[3] & 0x0f,
+        answers,
+        authority,
+        additional,
+    })
+}
+
+fn read_record(buf: &[u8], start: usize) -> Result<(Record, usize), String> {
+    let (name, offset) = read_name(buf, start)?;
+    let header = match buf.get(offset..offset + 10) {
+        Some(header) => header,
+        None => return Err("truncated record".to_string()),
+    };
+    let rtype = u16::from_be_bytes([header[0], header[1]]);
+    let ttl = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+    let len = u16::from_be_bytes([header[8], header[9]]) as usize;
+    let rdata_start = offset + 10;
+    let rdata = match buf.get(rdata_start..rdata_start + len) {
+        Some(rdata) => rdata,
+        None => return Err("truncated record data".to_string()),
+    };
+    let data = match (rtype, len) {
+        (TYPE_A, 4) => RecordData::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+        (TYPE_AAAA, 16) => {
+            let octets: [u8; 16] = rdata.try_into().unwrap();
+            RecordData::Aaaa(Ipv6Addr::from(octets))
+        }
+        (TYPE_NS | TYPE_CNAME, _) => RecordData::Name(read_name(buf, rdata_start)?.0),
+        _ => RecordData::Other(rdata.to_vec()),
+    };
+    let record = Record {
+        offset: start,
+        name,
+        rtype,
+        ttl,
+        data,
+    };
+    Ok((record, rdata_start + len))
+}
+
+/// A possibly compressed name at `offset`, and the offset right after it
+fn read_name(buf: &[u8], mut offset: usize) -> Result<(String, usize), String> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // every pointer must go backwards, so following them always terminates
+    let mut limit = offset;
+    loop {
+        let len = match buf.get(offset) {
+            Some(len) => *len as usize,
+            None => return Err("truncated name".to_string()),
+        };
+        match len {
+            0 => {
+                let end = end.unwrap_or(offset + 1);
+                return Ok((labels.join("."), end));
+            }
+            len if len & 0xc0 == 0xc0 => {
+                let target = match buf.get(offset + 1) {
+                    Some(low) => ((len & 0x3f) << 8) | *low as usize,
+                    None => return Err("truncated name pointer".to_string()),
+                };
+                if target >= limit {
+                    return Err("invalid name pointer".to_string());
+                }
+                end.get_or_insert(offset + 2);
+                limit = target;
+                offset = target;
+            }
+            len if len <= 63 => {
+                let label = match buf.get(offset + 1..offset + 1 + len) {
+                    Some(label) => label,
+                    None => return Err("truncated label".to_string()),
+                };
+                labels.push(String::from_utf8_lossy(label).to_string());
+                offset += 1 + len;
+            }
+            _ => return Err("invalid label".to_string()),
+        }
+    }
+}
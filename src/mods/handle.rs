@@ -1,165 +1,762 @@
-use std::time::Duration;
+use std::{
+    collections::BTreeMap,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
 
-use log::{error, trace};
-use tokio::time::sleep;
+use log::{error, info, trace, warn};
+use serde::Serialize;
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        watch, Mutex,
+    },
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
 
 use crate::mods::{
-    providers::types::ProvidersErrorType,
-    types::{AddressVersion, DDNSProvider},
+    providers::types::{ProvidersErrorType, RecordSetPlan},
+    types::{Config, SiteConfig},
 };
 
 use super::{
-    interfaces::{get_interface_ips, get_interfaces},
+    interfaces::InterfaceMatcher,
     providers::DDNSProviderTrait,
-    statics::CONFIG,
+    sources::{resolve_address, resolve_addresses},
+    statics::{ADDRESS_EVENTS, CONFIG, DRY_RUN, STATE, TASKS},
 };
 
+/// Longest delay between two restarts of a failing task
+pub const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+/// How long a stopped task may take to finish its in-flight update
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub enum TaskState {
+    Running,
+    BackingOff { until: i64 }, // unix timestamp of the next restart
+    Stopped,
+}
+
+/// What the supervisor knows about a site task
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SiteStatus {
+    pub state: TaskState,
+    pub last_error: Option<String>,
+    pub restarts: u32,
+}
+
+/// A supervised site task and the config it was started with
+pub struct SiteTask {
+    pub site: SiteConfig,
+    status: Arc<Mutex<SiteStatus>>,
+    stop_tx: watch::Sender<bool>,
+    handle: JoinHandle<()>,
+}
+
+impl SiteTask {
+    fn spawn(site: SiteConfig) -> SiteTask {
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let status = Arc::new(Mutex::new(SiteStatus {
+            state: TaskState::Running,
+            last_error: None,
+            restarts: 0,
+        }));
+        let handle = tokio::spawn(supervise_site(site.clone(), stop_rx, status.clone()));
+        SiteTask {
+            site,
+            status,
+            stop_tx,
+            handle,
+        }
+    }
+
+    /// Ask the task to stop and wait for it, aborting it after `wait`.
+    ///
+    /// Returns `false` if it had to be aborted.
+    async fn stop(mut self, wait: Duration) -> bool {
+        let _ = self.stop_tx.send(true);
+        match timeout(wait, &mut self.handle).await {
+            Ok(_) => true,
+            Err(_) => {
+                self.handle.abort();
+                false
+            }
+        }
+    }
+}
+
+/// Sites to stop and (re)start to go from the running tasks to a new config
+#[derive(Debug, Default, PartialEq)]
+pub struct ReloadPlan {
+    pub stop: Vec<String>,
+    pub start: Vec<String>,
+}
+
 pub async fn spawn_tasks() -> Result<(), String> {
     let config = CONFIG.lock().await.clone();
-    let sites_config = config.sites_config;
-    for single_site in sites_config {
-        tokio::spawn(async move {
-            let mut interval_duration = Duration::from_secs(0);
-            let site = single_site.clone();
-            let mut failures: u32 = 0;
-            loop {
-                sleep(interval_duration).await;
-
-                let interfaces = match get_interfaces().await {
-                    Ok(interfaces) => interfaces,
-                    Err(_e) => {
-                        if site.retry_on_failure {
-                            if site.retry_count == 0 {
-                                interval_duration = Duration::from_secs(site.retry_interval);
-                                continue;
-                            } else if failures < site.retry_count {
-                                failures += 1;
-                                interval_duration = Duration::from_secs(site.retry_interval);
-                                continue;
-                            } else {
-                                break;
-                            }
-                        } else {
-                            break;
-                        }
-                    }
-                };
+    reconcile_tasks(&config).await;
+    Ok(())
+}
 
-                trace!("interfaces: {:?}", interfaces);
-
-                let ips = get_interface_ips(&interfaces, &site.interface)
-                    .await
-                    .into_iter()
-                    .filter(|ip| match ip {
-                        super::interfaces::IPAddress::V4(_ip, address_type) => {
-                            if site.address_version == AddressVersion::V4
-                                && *address_type == site.address_type
-                            {
-                                true
-                            } else {
-                                false
-                            }
-                        }
-                        super::interfaces::IPAddress::V6(_ip, address_type) => {
-                            if site.address_version == AddressVersion::V6
-                                && *address_type == site.address_type
-                            {
-                                true
-                            } else {
-                                false
-                            }
-                        }
-                    })
-                    .collect::<Vec<_>>();
-
-                trace!("ips: {:?}", ips);
-                if ips.len() < site.index + 1 {
-                    if site.retry_on_failure {
-                        trace!("retrying");
-                        if site.retry_count == 0 {
-                            interval_duration = Duration::from_secs(site.retry_interval);
-                            continue;
-                        } else if failures < site.retry_count {
-                            failures += 1;
-                            interval_duration = Duration::from_secs(site.retry_interval);
-                            continue;
-                        } else {
-                            break;
-                        }
-                    } else {
-                        break;
-                    }
+/// Compare the running sites with `config`: removed, disabled and changed sites are stopped,
+/// new and changed sites are started, everything else is left alone.
+pub fn plan_reload(running: &BTreeMap<String, SiteConfig>, config: &Config) -> ReloadPlan {
+    let mut plan = ReloadPlan::default();
+    let wanted = config
+        .sites_config
+        .iter()
+        .filter(|site| site.enabled)
+        .map(|site| (site.name.clone(), site))
+        .collect::<BTreeMap<_, _>>();
+    for (name, site) in running.iter() {
+        match wanted.get(name) {
+            Some(new_site) if same_site(site, new_site) => {}
+            Some(_) => {
+                plan.stop.push(name.clone());
+                plan.start.push(name.clone());
+            }
+            None => plan.stop.push(name.clone()),
+        }
+    }
+    for name in wanted.keys() {
+        if !running.contains_key(name) {
+            plan.start.push(name.clone());
+        }
+    }
+    plan
+}
+
+fn same_site(a: &SiteConfig, b: &SiteConfig) -> bool {
+    // runtime fields of the providers are `#[serde(skip)]`, so this only compares the config
+    match (serde_json::to_value(a), serde_json::to_value(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Bring the running tasks in line with `config`.
+///
+/// A changed site keeps the state of its name: only `last_ip` and `record_id` saved for the
+/// same provider config are trusted, see `StateStore::site_for`.
+pub async fn reconcile_tasks(config: &Config) {
+    for site in config.sites_config.iter().filter(|site| !site.enabled) {
+        trace!("Skipping disabled site: {}", site.name);
+    }
+
+    // `TASKS` is only held to take tasks out and put them in, never while waiting for them
+    let (plan, stopping) = {
+        let mut tasks = TASKS.lock().await;
+        let running = tasks
+            .iter()
+            .map(|(name, task)| (name.clone(), task.site.clone()))
+            .collect::<BTreeMap<_, _>>();
+        let plan = plan_reload(&running, config);
+        let stopping = plan
+            .stop
+            .iter()
+            .filter_map(|name| tasks.remove(name).map(|task| (name.clone(), task)))
+            .collect::<Vec<_>>();
+        (plan, stopping)
+    };
+    for (name, _) in stopping.iter() {
+        info!("Stopping site: {}", name);
+    }
+    // let in-flight updates finish so a changed site never runs twice
+    stop_tasks(stopping, STOP_TIMEOUT).await;
+
+    let mut tasks = TASKS.lock().await;
+    for name in plan.start.iter() {
+        let site = match config
+            .sites_config
+            .iter()
+            .find(|site| site.enabled && &site.name == name)
+        {
+            Some(site) => site.clone(),
+            None => continue,
+        };
+        info!("Starting site: {}", name);
+        tasks.insert(name.clone(), SiteTask::spawn(site));
+    }
+}
+
+/// Stop every site task, waiting up to `timeout` for in-flight updates.
+///
+/// Returns `false` if some tasks had to be aborted.
+pub async fn shutdown_tasks(timeout: Duration) -> bool {
+    let tasks = std::mem::take(&mut *TASKS.lock().await);
+    stop_tasks(tasks.into_iter().collect(), timeout).await
+}
+
+/// Stop `tasks` all at once, each one may take up to `wait`.
+///
+/// Returns `false` if some tasks had to be aborted.
+async fn stop_tasks(tasks: Vec<(String, SiteTask)>, wait: Duration) -> bool {
+    let stops = tasks
+        .into_iter()
+        .map(|(name, task)| (name, tokio::spawn(task.stop(wait))))
+        .collect::<Vec<_>>();
+    let mut clean = true;
+    for (name, stop) in stops {
+        if !stop.await.unwrap_or(false) {
+            warn!("{}: did not stop in time, aborted", name);
+            clean = false;
+        }
+    }
+    clean
+}
+
+/// Current status of every site task
+pub async fn site_statuses() -> BTreeMap<String, SiteStatus> {
+    let tasks = TASKS.lock().await;
+    let mut statuses = BTreeMap::new();
+    for (name, task) in tasks.iter() {
+        statuses.insert(name.clone(), task.status.lock().await.clone());
+    }
+    statuses
+}
+
+/// Resolves on SIGINT (ctrl-c) or SIGTERM
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
                 }
-                let needed_ip = match ips[site.index].clone() {
-                    super::interfaces::IPAddress::V4(ip, _address_type) => ip,
-                    super::interfaces::IPAddress::V6(ip, _address_type) => ip,
-                };
-                let mut provider: Box<dyn DDNSProviderTrait> = match site.provider.clone() {
-                    DDNSProvider::Aliyun(value) => Box::new(value) as Box<dyn DDNSProviderTrait>,
-                    DDNSProvider::Custom => {
-                        panic!("Custom provider not implemented");
-                    }
-                    DDNSProvider::Dynv6(value) => Box::new(value) as Box<dyn DDNSProviderTrait>,
-                };
-                let cloud_ip = match provider.get_ip_address().await {
-                    Ok(cloud_ip) => cloud_ip,
-                    Err(e) => match e {
-                        ProvidersErrorType::NoRecordFound => {
-                            trace!("No record found, should create new record");
-                            String::new()
-                        }
-                        _ => {
-                            error!("Failed to get cloud IP address: {}", e);
-                            if site.retry_on_failure {
-                                if site.retry_count == 0 {
-                                    interval_duration = Duration::from_secs(site.retry_interval);
-                                    continue;
-                                } else if failures < site.retry_count {
-                                    failures += 1;
-                                    interval_duration = Duration::from_secs(site.retry_interval);
-                                    continue;
-                                } else {
-                                    break;
-                                }
-                            } else {
-                                break;
-                            }
-                        }
-                    },
-                };
-                if cloud_ip == needed_ip {
-                    interval_duration = Duration::from_secs(site.interval);
-                    failures = 0;
+                return;
+            }
+            Err(e) => warn!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Delay before the `attempt`-th restart of a task: `base` doubled each time, capped at `MAX_BACKOFF`
+pub fn backoff_delay(base: Duration, attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.min(16));
+    base.saturating_mul(factor).min(MAX_BACKOFF)
+}
+
+/// Sleep for `duration`, returns `true` if asked to stop meanwhile
+async fn sleep_or_stop(duration: Duration, stop: &mut watch::Receiver<bool>) -> bool {
+    if *stop.borrow() {
+        return true;
+    }
+    tokio::select! {
+        _ = sleep(duration) => *stop.borrow(),
+        _ = stop.changed() => true,
+    }
+}
+
+/// Like `sleep_or_stop`, but also wakes up early when the addresses of one of `interfaces` changed
+async fn sleep_or_change(
+    duration: Duration,
+    stop: &mut watch::Receiver<bool>,
+    changes: &mut broadcast::Receiver<String>,
+    interfaces: &[InterfaceMatcher],
+) -> bool {
+    if interfaces.is_empty() {
+        return sleep_or_stop(duration, stop).await;
+    }
+    if *stop.borrow() {
+        return true;
+    }
+    let deadline = sleep(duration);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            _ = &mut deadline => return *stop.borrow(),
+            _ = stop.changed() => return true,
+            change = changes.recv() => match change {
+                Ok(name) if interfaces.iter().any(|itf| itf.may_match(&name)) => {
+                    info!("Addresses of {} changed, checking now", name);
+                    return *stop.borrow();
+                }
+                Ok(_) => (),
+                // some changes were missed, one of them may have been ours
+                Err(RecvError::Lagged(_)) => return *stop.borrow(),
+                Err(RecvError::Closed) => tokio::select! {
+                    _ = &mut deadline => return *stop.borrow(),
+                    _ = stop.changed() => return true,
+                },
+            },
+        }
+    }
+}
+
+/// Interfaces the families of a site read their addresses from
+fn watched_interfaces(site: &SiteConfig) -> Vec<InterfaceMatcher> {
+    site.families()
+        .into_iter()
+        .filter(|family| family.source.reads_interface())
+        .filter_map(|family| InterfaceMatcher::parse(&family.interface).ok())
+        .collect()
+}
+
+/// Aborts the inner task when the supervisor itself gets aborted
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Runs a site and restarts it with exponential backoff when it exits or panics
+async fn supervise_site(
+    site: SiteConfig,
+    mut stop: watch::Receiver<bool>,
+    status: Arc<Mutex<SiteStatus>>,
+) {
+    let base = Duration::from_secs(site.retry_interval.max(1));
+    let mut attempt: u32 = 0;
+    loop {
+        status.lock().await.state = TaskState::Running;
+        let started = Instant::now();
+        let mut task = AbortOnDrop(tokio::spawn(run_site(
+            site.clone(),
+            stop.clone(),
+            status.clone(),
+        )));
+        let result = (&mut task.0).await;
+        if *stop.borrow() {
+            break;
+        }
+        match result {
+            Ok(_) => warn!("{}: task exited, restarting", site.name),
+            Err(e) => {
+                error!("{}: task panicked, restarting: {}", site.name, e);
+                status.lock().await.last_error = Some(format!("task panicked: {}", e));
+            }
+        }
+        // a task that ran for a while starts over from the base delay
+        if started.elapsed() > MAX_BACKOFF {
+            attempt = 0;
+        }
+        let delay = backoff_delay(base, attempt);
+        attempt = attempt.saturating_add(1);
+        {
+            let mut status = status.lock().await;
+            status.restarts += 1;
+            status.state = TaskState::BackingOff {
+                until: chrono::Utc::now().timestamp() + delay.as_secs() as i64,
+            };
+        }
+        if sleep_or_stop(delay, &mut stop).await {
+            break;
+        }
+    }
+    status.lock().await.state = TaskState::Stopped;
+    info!("Stopped site: {}", site.name);
+}
+
+async fn run_site(
+    site: SiteConfig,
+    mut stop: watch::Receiver<bool>,
+    status: Arc<Mutex<SiteStatus>>,
+) {
+    let mut interval_duration = Duration::from_secs(0);
+    let mut failures: u32 = 0;
+    let mut runner = SiteRunner::new(&site).await.with_stop(stop.clone());
+    // polling stays the fallback, address changes only cut the wait short
+    let interfaces = watched_interfaces(&site);
+    let mut changes = ADDRESS_EVENTS.subscribe();
+    loop {
+        if sleep_or_change(interval_duration, &mut stop, &mut changes, &interfaces).await {
+            return;
+        }
+
+        let errors = runner
+            .run_round()
+            .await
+            .into_iter()
+            .filter_map(|(name, result)| result.err().map(|e| format!("{}: {}", name, e)))
+            .collect::<Vec<_>>();
+
+        if errors.is_empty() {
+            failures = 0;
+            interval_duration = Duration::from_secs(site.interval);
+            continue;
+        }
+        status.lock().await.last_error = Some(errors.join("; "));
+        if site.retry_on_failure {
+            trace!("retrying");
+            if site.retry_count == 0 {
+                interval_duration = Duration::from_secs(site.retry_interval);
+            } else if failures < site.retry_count {
+                failures += 1;
+                interval_duration = Duration::from_secs(site.retry_interval);
+            } else {
+                break;
+            }
+        } else {
+            break;
+        }
+    }
+    error!("Exit task: {}", site.name);
+}
+
+/// Dry run from the command line, the config or the site itself
+pub async fn is_dry_run(site: &SiteConfig) -> bool {
+    DRY_RUN.load(Ordering::Relaxed) || site.dry_run || CONFIG.lock().await.dry_run
+}
+
+/// Whether `value` was published by the last (successful) round before a restart, so the
+/// provider need not be asked. Only the first round may skip: later rounds always ask, so a
+/// record edited or deleted at the provider gets repaired.
+async fn unchanged_since_last_update(site: &SiteConfig, value: &str, first_round: bool) -> bool {
+    if !first_round {
+        return false;
+    }
+    let state = STATE.lock().await;
+    let site_state = state.site_for(&site.name, &site.provider);
+    if state.is_persistent()
+        && site_state.error_count == 0
+        && site_state.last_ip.as_deref() == Some(value)
+    {
+        trace!(
+            "{}: {} unchanged since before the restart, skipping remote query",
+            site.name,
+            value
+        );
+        return true;
+    }
+    false
+}
+
+/// A target of a family with the provider that publishes to it
+type Target = (SiteConfig, Box<dyn DDNSProviderTrait>);
+
+/// A site ready to run rounds: its families, each with the providers of all its targets.
+///
+/// Providers are kept across rounds so they can reuse what they learned (record IDs etc.).
+pub struct SiteRunner {
+    families: Vec<(SiteConfig, Vec<Target>)>,
+    first_round: bool,
+    stop: Option<watch::Receiver<bool>>, // cuts the propagation check short
+}
+
+impl SiteRunner {
+    pub async fn new(site: &SiteConfig) -> SiteRunner {
+        let mut families = Vec::new();
+        for family in site.families() {
+            let mut targets = Vec::new();
+            for target in family.targets() {
+                let mut provider = target.provider.to_provider();
+                let state = STATE.lock().await.site_for(&target.name, &target.provider);
+                if let Some(record_id) = state.record_id {
+                    trace!("{}: reusing cached record ID {}", target.name, record_id);
+                    provider.set_record_id(&record_id);
+                }
+                targets.push((target, provider));
+            }
+            families.push((family, targets));
+        }
+        SiteRunner {
+            families,
+            first_round: true,
+            stop: None,
+        }
+    }
+
+    /// Stop waiting for updates to propagate once `stop` is set
+    pub fn with_stop(mut self, stop: watch::Receiver<bool>) -> SiteRunner {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Detect the address of every family once and publish it to all targets.
+    ///
+    /// Returns the result of each target (or family, when detection failed); failures are
+    /// recorded in the state, one failing does not hold back the others.
+    pub async fn run_round(&mut self) -> Vec<(String, Result<(), String>)> {
+        let mut results = Vec::new();
+        let mut absent = 0;
+        for (family, targets) in self.families.iter_mut() {
+            let ips = match detect_addresses(family).await {
+                Ok(Some(ips)) => ips,
+                Ok(None) => {
+                    absent += 1;
+                    continue;
+                }
+                Err(e) => {
+                    error!("{}: {}", family.name, e);
+                    STATE.lock().await.record_failure(&family.name, &e).await;
+                    results.push((family.name.clone(), Err(e)));
                     continue;
-                } else {
-                    match provider.update(&needed_ip).await {
-                        Ok(_) => {
-                            failures = 0;
-                        }
-                        Err(e) => {
-                            error!("Failed to update IP address: {}", e);
-
-                            if site.retry_on_failure {
-                                if site.retry_count == 0 {
-                                    interval_duration = Duration::from_secs(site.retry_interval);
-                                    continue;
-                                } else if failures < site.retry_count {
-                                    failures += 1;
-                                    interval_duration = Duration::from_secs(site.retry_interval);
-                                    continue;
-                                } else {
-                                    break;
-                                }
-                            } else {
-                                break;
-                            }
-                        }
+                }
+            };
+            for (target, provider) in targets.iter_mut() {
+                // a target may put its own host suffix on a delegated prefix
+                let result = match target.source.apply(ips.clone()) {
+                    Ok(ips) => {
+                        let (first_round, stop) = (self.first_round, self.stop.as_ref());
+                        publish_addresses(target, provider.as_mut(), &ips, first_round, stop).await
                     }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = &result {
+                    error!("{}: {}", target.name, e);
+                    STATE.lock().await.record_failure(&target.name, e).await;
                 }
-                interval_duration = Duration::from_secs(site.interval);
+                results.push((target.name.clone(), result));
             }
-            error!("Exit task: {}", site.name);
-        });
+        }
+        // one family of a dual-stack site may be missing, not both
+        if absent == self.families.len() {
+            let name = self.families[0].0.name.clone();
+            let e = "Failed to get IP address: no address of any family".to_string();
+            error!("{}: {}", name, e);
+            STATE.lock().await.record_failure(&name, &e).await;
+            results.push((name, Err(e)));
+        }
+        self.first_round = false;
+        results
     }
+}
+
+/// One detection and publish round for a site, as the first one after a start
+pub async fn update_site(
+    site: &SiteConfig,
+    provider: &mut dyn DDNSProviderTrait,
+) -> Result<(), String> {
+    match detect_addresses(site).await? {
+        Some(ips) => publish_addresses(site, provider, &ips, true, None).await,
+        None => Ok(()),
+    }
+}
+
+/// The addresses a site publishes, sorted for sets. `None` when an optional family has none
+async fn detect_addresses(site: &SiteConfig) -> Result<Option<Vec<String>>, String> {
+    let result = match site.count {
+        1 => resolve_address(site).await.map(|ip| vec![ip]),
+        _ => resolve_addresses(site).await.map(|mut ips| {
+            ips.sort();
+            ips.dedup();
+            ips
+        }),
+    };
+    match result {
+        Ok(ips) => Ok(Some(ips)),
+        Err(e) if site.optional => {
+            warn!("{}: {}, skipping", site.name, e);
+            Ok(None)
+        }
+        Err(e) => Err(format!("Failed to get IP address: {}", e)),
+    }
+}
+
+async fn publish_addresses(
+    site: &SiteConfig,
+    provider: &mut dyn DDNSProviderTrait,
+    ips: &[String],
+    first_round: bool,
+    stop: Option<&watch::Receiver<bool>>,
+) -> Result<(), String> {
+    if site.count != 1 {
+        return publish_set(site, provider, ips, first_round, stop).await;
+    }
+    let needed_ip = match ips.first() {
+        Some(ip) => ip.clone(),
+        None => return Err("no address to publish".to_string()),
+    };
+
+    if unchanged_since_last_update(site, &needed_ip, first_round).await {
+        return Ok(());
+    }
+    let dry_run = is_dry_run(site).await;
+    provider.set_dry_run(dry_run);
+    let cloud_ip = match current_value(site, provider).await {
+        Ok(cloud_ip) => cloud_ip,
+        Err(ProvidersErrorType::NoRecordFound) => {
+            trace!("No record found, should create new record");
+            String::new()
+        }
+        Err(e) => return Err(format!("Failed to get cloud IP address: {}", e)),
+    };
+    if dry_run {
+        // nothing is published, so the state is left alone too
+        match cloud_ip.as_str() {
+            ip if ip == needed_ip => info!("{}: dry run, {} is up to date", site.name, ip),
+            "" => info!("{}: dry run, would create record {}", site.name, needed_ip),
+            ip => info!(
+                "{}: dry run, would update {} -> {}",
+                site.name, ip, needed_ip
+            ),
+        }
+        return Ok(());
+    }
+    if cloud_ip != needed_ip {
+        if let Err(e) = provider.update(&needed_ip).await {
+            return Err(format!("Failed to update IP address: {}", e));
+        }
+        info!("{}: updated {:?} -> {}", site.name, cloud_ip, needed_ip);
+        verify_propagation(site, std::slice::from_ref(&needed_ip), stop).await?;
+    }
+
+    STATE
+        .lock()
+        .await
+        .record_success(&site.name, &site.provider, &needed_ip, provider.record_id())
+        .await;
+    Ok(())
+}
+
+/// With `verify`, wait for DNS to serve exactly `ips` after an update and remember how
+/// long it took. A record that never converges is only a warning unless `fail_on_timeout`.
+///
+/// Stopping the site gives up the wait: the update itself went through.
+async fn verify_propagation(
+    site: &SiteConfig,
+    ips: &[String],
+    stop: Option<&watch::Receiver<bool>>,
+) -> Result<(), String> {
+    let check = match &site.verify {
+        Some(check) => check,
+        None => return Ok(()),
+    };
+    let name = match check
+        .lookup
+        .name
+        .clone()
+        .or_else(|| site.provider.record_name())
+    {
+        Some(name) => name,
+        None => {
+            warn!("{}: verify needs a name for this provider", site.name);
+            return Ok(());
+        }
+    };
+    let result = match stop {
+        Some(stop) => {
+            let mut stop = stop.clone();
+            tokio::select! {
+                result = check.wait_for(&name, &site.address_version, ips) => result,
+                _ = stop.wait_for(|stop| *stop) => {
+                    info!("{}: stopping, not waiting for {} to propagate", site.name, name);
+                    return Ok(());
+                }
+            }
+        }
+        None => check.wait_for(&name, &site.address_version, ips).await,
+    };
+    match result {
+        Ok(elapsed) => {
+            info!(
+                "{}: {} serves {:?} after {:?}",
+                site.name, name, ips, elapsed
+            );
+            STATE
+                .lock()
+                .await
+                .record_propagation(&site.name, Some(elapsed.as_secs()))
+                .await;
+            Ok(())
+        }
+        Err(e) => {
+            warn!("{}: update did not propagate: {}", site.name, e);
+            STATE
+                .lock()
+                .await
+                .record_propagation(&site.name, None)
+                .await;
+            match check.fail_on_timeout {
+                true => Err(format!("Update did not propagate: {}", e)),
+                false => Ok(()),
+            }
+        }
+    }
+}
+
+/// What the record of a site holds now: looked up with DNS when the site has `lookup`,
+/// asked from its provider otherwise
+pub async fn current_value(
+    site: &SiteConfig,
+    provider: &mut dyn DDNSProviderTrait,
+) -> Result<String, ProvidersErrorType> {
+    let lookup = match &site.lookup {
+        Some(lookup) => lookup,
+        None => return provider.get_ip_address().await,
+    };
+    let name = match lookup.name.clone().or_else(|| site.provider.record_name()) {
+        Some(name) => name,
+        None => {
+            error!("{}: lookup needs a name for this provider", site.name);
+            return Err(ProvidersErrorType::NotInitialized);
+        }
+    };
+    match lookup.lookup(&name, &site.address_version).await {
+        Ok(ips) => match ips.into_iter().next() {
+            Some(ip) => Ok(ip),
+            None => Err(ProvidersErrorType::NoRecordFound),
+        },
+        Err(e) => {
+            error!("{}: DNS lookup of {} failed: {}", site.name, name, e);
+            Err(ProvidersErrorType::NetworkError)
+        }
+    }
+}
+
+/// Like `publish_addresses`, for sites publishing a set of addresses
+async fn publish_set(
+    site: &SiteConfig,
+    provider: &mut dyn DDNSProviderTrait,
+    needed_ips: &[String],
+    first_round: bool,
+    stop: Option<&watch::Receiver<bool>>,
+) -> Result<(), String> {
+    let needed = needed_ips.join(",");
+
+    if unchanged_since_last_update(site, &needed, first_round).await {
+        return Ok(());
+    }
+
+    let dry_run = is_dry_run(site).await;
+    provider.set_dry_run(dry_run);
+    let records = match provider.get_record_set().await {
+        Ok(records) => records,
+        Err(ProvidersErrorType::NoRecordFound) => Vec::new(),
+        Err(e) => return Err(format!("Failed to get cloud IP addresses: {}", e)),
+    };
+    let plan = RecordSetPlan::new(&records, needed_ips);
+    if dry_run {
+        if plan.is_empty() {
+            info!("{}: dry run, {} is up to date", site.name, needed);
+        }
+        for (record, ip) in plan.change.iter() {
+            info!(
+                "{}: dry run, would update record {} {} -> {}",
+                site.name, record.id, record.value, ip
+            );
+        }
+        for ip in plan.add.iter() {
+            info!("{}: dry run, would create record {}", site.name, ip);
+        }
+        for record in plan.remove.iter() {
+            info!(
+                "{}: dry run, would delete record {} ({})",
+                site.name, record.id, record.value
+            );
+        }
+        return Ok(());
+    }
+    if !plan.is_empty() {
+        if let Err(e) = provider.update_set(&plan).await {
+            return Err(format!("Failed to update IP addresses: {}", e));
+        }
+        info!("{}: record set is now {}", site.name, needed);
+        verify_propagation(site, needed_ips, stop).await?;
+    }
+
+    STATE
+        .lock()
+        .await
+        .record_success(&site.name, &site.provider, &needed, None)
+        .await;
     Ok(())
 }
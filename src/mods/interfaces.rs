@@ -1,7 +1,20 @@
-use log::{error, trace};
+pub mod netlink;
+
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use lazy_static::lazy_static;
+use log::{debug, error, trace};
 use network_interface::{NetworkInterface, NetworkInterfaceConfig};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use self::netlink::dump_address_details;
+use super::types::AddressVersion;
+
 pub async fn get_interfaces() -> Result<Vec<NetworkInterface>, ()> {
     let network_interfaces: Vec<NetworkInterface> = match NetworkInterface::show() {
         Ok(network_interfaces) => network_interfaces,
@@ -37,11 +50,23 @@ pub async fn get_interface_ips(
     interfaces: &Vec<NetworkInterface>,
     interface: &str,
 ) -> Vec<IPAddress> {
+    let mut details = None;
     let mut ips = Vec::new();
     for itf in interfaces.iter() {
         if itf.name == interface {
             for addr in itf.addr.iter() {
-                ips.push(IPAddress::from(addr))
+                let mut ip = IPAddress::from(addr);
+                if let IPAddress::V6(value, _, ip_details) = &mut ip {
+                    // only ask the kernel when there is an IPv6 address to describe
+                    if details.is_none() {
+                        details = Some(get_address_details().await);
+                    }
+                    let key = (itf.index, value.parse().unwrap_or(Ipv6Addr::UNSPECIFIED));
+                    if let Some(found) = details.as_ref().and_then(|d| d.get(&key)) {
+                        *ip_details = found.clone();
+                    }
+                }
+                ips.push(ip)
             }
         }
     }
@@ -49,69 +74,467 @@ pub async fn get_interface_ips(
     ips
 }
 
+async fn get_address_details() -> HashMap<(u32, Ipv6Addr), AddressDetails> {
+    match tokio::task::spawn_blocking(dump_address_details).await {
+        Ok(Ok(details)) => details
+            .into_iter()
+            .map(|(index, ip, details)| ((index, ip), details))
+            .collect(),
+        Ok(Err(e)) => {
+            debug!("No IPv6 address details: {}", e);
+            HashMap::new()
+        }
+        Err(e) => {
+            error!("Error getting IPv6 address details: {}", e);
+            HashMap::new()
+        }
+    }
+}
+
+/// What `SiteConfig.interface` can be
+#[derive(Clone, Debug)]
+pub enum InterfaceMatcher {
+    Name(String),
+    Pattern(Regex), // "/regex/" or a glob with `*`, `?` or `[...]`
+    Mac(String),    // lowercase, `:` separated
+    DefaultRoute,   // "default-route": the interface of the default route of the site's version
+}
+
+impl InterfaceMatcher {
+    pub fn parse(value: &str) -> Result<InterfaceMatcher, String> {
+        let mac = Regex::new(r"^([0-9a-fA-F]{2}[:-]){5}[0-9a-fA-F]{2}$").unwrap();
+        if value == "default-route" {
+            Ok(InterfaceMatcher::DefaultRoute)
+        } else if mac.is_match(value) {
+            Ok(InterfaceMatcher::Mac(
+                value.to_lowercase().replace('-', ":"),
+            ))
+        } else if value.len() > 2 && value.starts_with('/') && value.ends_with('/') {
+            match Regex::new(&value[1..value.len() - 1]) {
+                Ok(pattern) => Ok(InterfaceMatcher::Pattern(pattern)),
+                Err(e) => Err(e.to_string()),
+            }
+        } else if value.contains(['*', '?', '[']) {
+            match Regex::new(&glob_to_regex(value)) {
+                Ok(pattern) => Ok(InterfaceMatcher::Pattern(pattern)),
+                Err(_) => Err(format!("{:?} is not a valid glob", value)),
+            }
+        } else {
+            Ok(InterfaceMatcher::Name(value.to_string()))
+        }
+    }
+
+    /// Names of the matching interfaces, sorted so `index` keeps meaning the same address
+    pub fn find(
+        &self,
+        interfaces: &[NetworkInterface],
+        version: &AddressVersion,
+    ) -> Result<Vec<String>, String> {
+        let default_route = match self {
+            InterfaceMatcher::DefaultRoute => Some(default_route_interface(version)?),
+            _ => None,
+        };
+        let mut names = interfaces
+            .iter()
+            .filter(|itf| match self {
+                InterfaceMatcher::Name(name) => itf.name == *name,
+                InterfaceMatcher::Pattern(pattern) => pattern.is_match(&itf.name),
+                InterfaceMatcher::Mac(mac) => itf
+                    .mac_addr
+                    .as_ref()
+                    .is_some_and(|addr| addr.to_lowercase().replace('-', ":") == *mac),
+                InterfaceMatcher::DefaultRoute => default_route.as_ref() == Some(&itf.name),
+            })
+            .map(|itf| itf.name.clone())
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    /// Whether an interface called `name` could be one of ours, without looking it up
+    pub fn may_match(&self, name: &str) -> bool {
+        match self {
+            InterfaceMatcher::Name(own) => own == name,
+            InterfaceMatcher::Pattern(pattern) => pattern.is_match(name),
+            InterfaceMatcher::Mac(_) | InterfaceMatcher::DefaultRoute => true,
+        }
+    }
+}
+
+/// Anchored regex for a shell glob, `[...]` is kept as a character class
+fn glob_to_regex(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    let mut in_class = false;
+    for c in glob.chars() {
+        match c {
+            '*' if !in_class => pattern.push_str(".*"),
+            '?' if !in_class => pattern.push('.'),
+            '[' if !in_class => {
+                in_class = true;
+                pattern.push('[');
+            }
+            ']' if in_class => {
+                in_class = false;
+                pattern.push(']');
+            }
+            '!' if in_class && pattern.ends_with('[') => pattern.push('^'),
+            c if in_class => pattern.push(c),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+/// Interface of the default route with the lowest metric, from /proc (Linux only)
+pub fn default_route_interface(version: &AddressVersion) -> Result<String, String> {
+    let (path, parse): (&str, fn(&str) -> Option<String>) = match version {
+        AddressVersion::V4 => ("/proc/net/route", parse_ipv4_default_route),
+        AddressVersion::V6 => ("/proc/net/ipv6_route", parse_ipv6_default_route),
+    };
+    match std::fs::read_to_string(path) {
+        Ok(routes) => match parse(&routes) {
+            Some(name) => Ok(name),
+            None => Err(format!("no {} default route", version.to_string())),
+        },
+        Err(e) => Err(format!("failed to read {}: {}", path, e)),
+    }
+}
+
+const RTF_UP: u32 = 0x0001;
+const RTF_REJECT: u32 = 0x0200;
+
+/// `Iface Destination Gateway Flags RefCnt Use Metric Mask ...`, hex fields
+pub fn parse_ipv4_default_route(routes: &str) -> Option<String> {
+    ipv4_default_route(routes).map(|(name, _)| name)
+}
+
+/// The gateway of the IPv4 default route with the lowest metric, from /proc (Linux only)
+pub fn default_gateway() -> Result<Ipv4Addr, String> {
+    match std::fs::read_to_string("/proc/net/route") {
+        Ok(routes) => match parse_ipv4_default_gateway(&routes) {
+            Some(gateway) => Ok(gateway),
+            None => Err("no v4 default gateway".to_string()),
+        },
+        Err(e) => Err(format!("failed to read /proc/net/route: {}", e)),
+    }
+}
+
+pub fn parse_ipv4_default_gateway(routes: &str) -> Option<Ipv4Addr> {
+    ipv4_default_route(routes)
+        .map(|(_, gateway)| gateway)
+        .filter(|gateway| !gateway.is_unspecified()) // point-to-point links have none
+}
+
+fn ipv4_default_route(routes: &str) -> Option<(String, Ipv4Addr)> {
+    routes
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if fields.len() < 8 || fields[1] != "00000000" || fields[7] != "00000000" {
+                return None;
+            }
+            let flags = u32::from_str_radix(fields[3], 16).ok()?;
+            if flags & RTF_UP == 0 || flags & RTF_REJECT != 0 {
+                return None;
+            }
+            // printed as a host order number, the bytes are in network order
+            let gateway = u32::from_str_radix(fields[2], 16).ok()?.to_ne_bytes();
+            Some((
+                fields[6].parse::<u32>().ok()?,
+                fields[0].to_string(),
+                Ipv4Addr::from(gateway),
+            ))
+        })
+        .min()
+        .map(|(_, name, gateway)| (name, gateway))
+}
+
+/// `Destination PrefixLen Source PrefixLen NextHop Metric RefCnt Use Flags Iface`, hex fields
+pub fn parse_ipv6_default_route(routes: &str) -> Option<String> {
+    routes
+        .lines()
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if fields.len() < 10
+                || !fields[0].trim_start_matches('0').is_empty()
+                || fields[1] != "00"
+            {
+                return None;
+            }
+            let flags = u32::from_str_radix(fields[8], 16).ok()?;
+            if flags & RTF_UP == 0 || flags & RTF_REJECT != 0 || fields[9] == "lo" {
+                return None;
+            }
+            Some((
+                u32::from_str_radix(fields[5], 16).ok()?,
+                fields[9].to_string(),
+            ))
+        })
+        .min()
+        .map(|(_, name)| name)
+}
+
 #[derive(Clone, Debug)]
 
 pub enum IPAddress {
     V4(String, AddressType),
-    V6(String, AddressType),
+    V6(String, AddressType, AddressDetails),
+}
+
+impl IPAddress {
+    pub fn address(&self) -> &str {
+        match self {
+            IPAddress::V4(ip, _) => ip,
+            IPAddress::V6(ip, _, _) => ip,
+        }
+    }
+}
+
+/// Kernel flags and lifetimes of an IPv6 address, left at their defaults where unknown
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AddressDetails {
+    pub prefix_len: u8,
+    pub temporary: bool,                 // privacy extension address
+    pub deprecated: bool,                // preferred lifetime is over
+    pub tentative: bool,                 // duplicate address detection not done or failed
+    pub preferred_lifetime: Option<u32>, // seconds, None is forever
+}
+
+impl AddressDetails {
+    /// Not an address that is about to go away
+    pub fn is_stable(&self) -> bool {
+        !self.temporary && !self.deprecated && !self.tentative
+    }
+}
+
+/// An address prefix such as `2001:db8::/32`, a lone address is a prefix of its full length
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cidr {
+    pub address: IpAddr,
+    pub prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn parse(value: &str) -> Result<Cidr, String> {
+        let (address, prefix_len) = match value.trim().split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (value.trim(), None),
+        };
+        let address = match address.parse::<IpAddr>() {
+            Ok(address) => address,
+            Err(_) => return Err(format!("{:?} is not a valid CIDR", value)),
+        };
+        let max = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_len = match prefix_len.map(|len| len.parse::<u8>()) {
+            None => max,
+            Some(Ok(len)) if len <= max => len,
+            Some(_) => return Err(format!("{:?} is not a valid CIDR", value)),
+        };
+        Ok(Cidr {
+            address,
+            prefix_len,
+        })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// How to choose among the addresses of an interface, before `index` and `count` apply
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq)]
+#[serde(default)]
+pub struct AddressPolicy {
+    pub prefix: Option<String>,       // only addresses inside this CIDR
+    pub exclude_ula: bool,            // skip fc00::/7
+    pub prefer_stable: bool,          // non-temporary, non-deprecated addresses first
+    pub interface_id: Option<String>, // "eui64" or an interface ID such as "::1234" first
+    pub prefer_lifetime: bool,        // longest preferred lifetime first
+}
+
+impl AddressPolicy {
+    /// Drop the excluded addresses and sort the rest by preference, addresses the
+    /// policy cannot tell apart keep the order of the OS
+    pub fn select(&self, ips: Vec<IPAddress>) -> Vec<IPAddress> {
+        let prefix = self.prefix.as_deref().and_then(|p| Cidr::parse(p).ok());
+        let interface_id = self
+            .interface_id
+            .as_deref()
+            .and_then(|id| InterfaceId::parse(id).ok());
+        let mut ips = ips
+            .into_iter()
+            .filter(|ip| {
+                let address = match ip.address().parse::<IpAddr>() {
+                    Ok(address) => address,
+                    Err(_) => return false,
+                };
+                let in_prefix = match prefix {
+                    Some(prefix) => prefix.contains(&address),
+                    None => true,
+                };
+                let ula = matches!(address, IpAddr::V6(v6) if v6.octets()[0] & 0xfe == 0xfc);
+                in_prefix && !(self.exclude_ula && ula)
+            })
+            .collect::<Vec<_>>();
+        ips.sort_by_key(|ip| {
+            let (address, details) = match ip {
+                IPAddress::V6(address, _, details) => (address.parse().ok(), details.clone()),
+                IPAddress::V4(_, _) => (None, AddressDetails::default()),
+            };
+            let id_matches = match (&interface_id, address) {
+                (Some(id), Some(address)) => id.matches(&address),
+                _ => true,
+            };
+            let lifetime = match self.prefer_lifetime {
+                true => details.preferred_lifetime.unwrap_or(u32::MAX),
+                false => 0,
+            };
+            (
+                self.prefer_stable && !details.is_stable(),
+                !id_matches,
+                Reverse(lifetime),
+            )
+        });
+        ips
+    }
+}
+
+/// The interface ID (low 64 bits) an IPv6 address should have
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InterfaceId {
+    Eui64, // derived from the MAC address, ff:fe in the middle
+    Exact(u64),
+}
+
+impl InterfaceId {
+    pub fn parse(value: &str) -> Result<InterfaceId, String> {
+        if value.eq_ignore_ascii_case("eui64") {
+            return Ok(InterfaceId::Eui64);
+        }
+        match value.parse::<Ipv6Addr>() {
+            Ok(id) if u128::from(id) >> 64 == 0 => Ok(InterfaceId::Exact(u128::from(id) as u64)),
+            _ => Err(format!(
+                "{:?} is neither \"eui64\" nor an interface ID such as \"::1234\"",
+                value
+            )),
+        }
+    }
+
+    pub fn matches(&self, ip: &Ipv6Addr) -> bool {
+        match self {
+            InterfaceId::Eui64 => ip.octets()[11] == 0xff && ip.octets()[12] == 0xfe,
+            InterfaceId::Exact(id) => u128::from(*ip) as u64 == *id,
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
 pub enum AddressType {
     Public,
-    Private,
+    Private, // RFC 1918 and IPv6 ULA
     Loopback,
     Multicast,
     Unspecified,
     Broadcast,
     LinkLocal,
+    Shared,        // carrier-grade NAT, 100.64.0.0/10
+    Documentation, // examples only, never on the internet
+    Benchmarking,
+    Transition, // 6to4, Teredo, NAT64 and IPv4-mapped: not an address of its own
+    Reserved,
     Other,
 }
 
+lazy_static! {
+    /// The IANA IPv4 and IPv6 special-purpose address registries, the most specific
+    /// entries first. Anything else is `Public` (IPv4, 2000::/3) or `Other`.
+    static ref SPECIAL_PURPOSE: Vec<(Cidr, AddressType)> = [
+        ("0.0.0.0/32", AddressType::Unspecified),
+        ("0.0.0.0/8", AddressType::Reserved),
+        ("10.0.0.0/8", AddressType::Private),
+        ("100.64.0.0/10", AddressType::Shared),
+        ("127.0.0.0/8", AddressType::Loopback),
+        ("169.254.0.0/16", AddressType::LinkLocal),
+        ("172.16.0.0/12", AddressType::Private),
+        ("192.0.0.9/32", AddressType::Public), // PCP anycast
+        ("192.0.0.10/32", AddressType::Public), // TURN anycast
+        ("192.0.0.0/24", AddressType::Reserved),
+        ("192.0.2.0/24", AddressType::Documentation),
+        ("192.88.99.0/24", AddressType::Transition), // 6to4 relay anycast
+        ("192.168.0.0/16", AddressType::Private),
+        ("198.18.0.0/15", AddressType::Benchmarking),
+        ("198.51.100.0/24", AddressType::Documentation),
+        ("203.0.113.0/24", AddressType::Documentation),
+        ("224.0.0.0/4", AddressType::Multicast),
+        ("255.255.255.255/32", AddressType::Broadcast),
+        ("240.0.0.0/4", AddressType::Reserved),
+        ("::/128", AddressType::Unspecified),
+        ("::1/128", AddressType::Loopback),
+        ("::ffff:0:0/96", AddressType::Transition),
+        ("64:ff9b::/96", AddressType::Transition),
+        ("64:ff9b:1::/48", AddressType::Transition),
+        ("100::/64", AddressType::Reserved), // discard-only
+        ("2001::/32", AddressType::Transition), // Teredo
+        ("2001:1::1/128", AddressType::Public), // PCP anycast
+        ("2001:1::2/128", AddressType::Public), // TURN anycast
+        ("2001:1::3/128", AddressType::Public), // DNS-SD SRP anycast
+        ("2001:2::/48", AddressType::Benchmarking),
+        ("2001:3::/32", AddressType::Public), // AMT
+        ("2001:4:112::/48", AddressType::Public), // AS112
+        ("2001::/23", AddressType::Reserved),
+        ("2001:db8::/32", AddressType::Documentation),
+        ("2002::/16", AddressType::Transition), // 6to4
+        ("3fff::/20", AddressType::Documentation),
+        ("5f00::/16", AddressType::Reserved), // SRv6 SIDs
+        ("fc00::/7", AddressType::Private),
+        ("fe80::/10", AddressType::LinkLocal),
+        ("ff00::/8", AddressType::Multicast),
+        ("2000::/3", AddressType::Public),
+    ]
+    .into_iter()
+    .map(|(cidr, address_type)| (Cidr::parse(cidr).unwrap(), address_type))
+    .collect();
+}
+
 impl AddressType {
     pub fn declare_address_type(addr: &network_interface::Addr) -> Self {
         match addr {
-            network_interface::Addr::V4(address) => {
-                if address.ip.is_loopback() {
-                    AddressType::Loopback
-                } else if address.ip.is_multicast() {
-                    AddressType::Multicast
-                } else if address.ip.is_unspecified() {
-                    AddressType::Unspecified
-                } else if address.ip.is_private() {
-                    AddressType::Private
-                } else if address.ip.is_broadcast() {
-                    AddressType::Broadcast
-                } else if address.ip.is_link_local() {
-                    AddressType::LinkLocal
-                } else {
-                    AddressType::Public
-                }
-            }
-            network_interface::Addr::V6(address) => {
-                if address.ip.is_loopback() {
-                    AddressType::Loopback
-                } else if address.ip.is_multicast() {
-                    AddressType::Multicast
-                } else if address.ip.is_unspecified() {
-                    AddressType::Unspecified
-                } else if address.ip.octets()[0] == 0b11111100
-                    || address.ip.octets()[0] == 0b11111101
-                {
-                    AddressType::Private // fc00::/7
-                } else if address.ip.octets()[0] == 0b11111110
-                    && (address.ip.octets()[1] >> 6 == 0b10)
-                {
-                    AddressType::LinkLocal //fe80::/10
-                } else if address.ip.octets()[0] >= 0b00100000
-                    && address.ip.octets()[0] <= 0b00111111
-                {
-                    AddressType::Public // 2000::/3
-                } else {
-                    AddressType::Other
-                }
+            network_interface::Addr::V4(address) => Self::classify(&IpAddr::V4(address.ip)),
+            network_interface::Addr::V6(address) => Self::classify(&IpAddr::V6(address.ip)),
+        }
+    }
+
+    /// The first matching entry of `SPECIAL_PURPOSE`
+    pub fn classify(ip: &IpAddr) -> Self {
+        for (cidr, address_type) in SPECIAL_PURPOSE.iter() {
+            if cidr.contains(ip) {
+                return address_type.clone();
             }
         }
+        match ip {
+            IpAddr::V4(_) => AddressType::Public,
+            IpAddr::V6(_) => AddressType::Other,
+        }
     }
 }
 
@@ -121,9 +544,11 @@ impl From<&network_interface::Addr> for IPAddress {
             network_interface::Addr::V4(v4_ip) => {
                 IPAddress::V4(v4_ip.ip.to_string(), AddressType::declare_address_type(&ip))
             }
-            network_interface::Addr::V6(v6_ip) => {
-                IPAddress::V6(v6_ip.ip.to_string(), AddressType::declare_address_type(&ip))
-            }
+            network_interface::Addr::V6(v6_ip) => IPAddress::V6(
+                v6_ip.ip.to_string(),
+                AddressType::declare_address_type(&ip),
+                AddressDetails::default(),
+            ),
         }
     }
 }
@@ -0,0 +1,416 @@
+use std::{
+    collections::BTreeSet,
+    net::{IpAddr, Ipv6Addr},
+    time::Duration,
+};
+
+use log::{debug, warn};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time::sleep,
+};
+
+use super::AddressDetails;
+use crate::mods::statics::ADDRESS_EVENTS;
+
+/// How long address events have to stop before the changed interfaces are reported
+pub const DEBOUNCE: Duration = Duration::from_secs(2);
+
+const NLMSG_HDRLEN: usize = 16;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const IFADDRMSG_LEN: usize = 8;
+const RTM_NEWADDR: u16 = 20;
+const RTM_DELADDR: u16 = 21;
+const RTM_GETADDR: u16 = 22;
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+const IFA_LABEL: u16 = 3;
+const IFA_CACHEINFO: u16 = 6;
+const IFA_FLAGS: u16 = 8;
+const IFA_F_TEMPORARY: u32 = 0x01;
+const IFA_F_DADFAILED: u32 = 0x08;
+const IFA_F_DEPRECATED: u32 = 0x20;
+const IFA_F_TENTATIVE: u32 = 0x40;
+const INFINITY_LIFE_TIME: u32 = u32::MAX;
+
+/// An address added to or removed from an interface
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressEvent {
+    pub index: u32,
+    pub label: Option<String>, // IFA_LABEL, the kernel only sends it for IPv4
+}
+
+/// What an RTM_NEWADDR / RTM_DELADDR message says about an address
+struct AddressMessage {
+    index: u32,
+    prefix_len: u8,
+    flags: u32,
+    label: Option<String>,
+    address: Option<IpAddr>,
+    preferred_lifetime: Option<u32>, // seconds, None is forever
+}
+
+/// Watch the kernel for address changes and send the names of the changed interfaces
+/// to `ADDRESS_EVENTS`, at most once per burst.
+///
+/// Returns right away where netlink is not available: sites then only poll.
+pub async fn watch_addresses() {
+    let (tx, rx) = mpsc::channel(256);
+    if let Err(e) = spawn_reader(tx) {
+        warn!("Not watching address changes, polling only: {}", e);
+        return;
+    }
+    debounce(rx, DEBOUNCE, ADDRESS_EVENTS.clone()).await;
+}
+
+/// Forward the interface names received on `events` once none arrived for `quiet`.
+///
+/// SLAAC renumbering or a PPPoE reconnect produce a handful of events per interface,
+/// they are reported as one change.
+pub async fn debounce(
+    mut events: mpsc::Receiver<String>,
+    quiet: Duration,
+    changed: broadcast::Sender<String>,
+) {
+    let mut pending = BTreeSet::new();
+    loop {
+        let event = if pending.is_empty() {
+            events.recv().await
+        } else {
+            tokio::select! {
+                event = events.recv() => event,
+                _ = sleep(quiet) => None,
+            }
+        };
+        match event {
+            Some(name) => {
+                pending.insert(name);
+                continue;
+            }
+            None if pending.is_empty() => break, // the reader is gone
+            None => (),
+        }
+        for name in std::mem::take(&mut pending) {
+            debug!("Addresses of {} changed", name);
+            let _ = changed.send(name); // nobody listening is fine
+        }
+    }
+}
+
+/// Address events in a buffer of rtnetlink messages, anything else is skipped
+pub fn parse_address_events(buf: &[u8]) -> Vec<AddressEvent> {
+    split_messages(buf)
+        .into_iter()
+        .filter(|(kind, _)| *kind == RTM_NEWADDR || *kind == RTM_DELADDR)
+        .filter_map(|(_, payload)| parse_address(payload))
+        .map(|message| AddressEvent {
+            index: message.index,
+            label: message.label,
+        })
+        .collect()
+}
+
+/// IPv6 addresses and their details in the reply to an RTM_GETADDR dump, keyed by
+/// interface index. The second value is `true` once the end of the dump was reached.
+pub fn parse_address_details(buf: &[u8]) -> (Vec<(u32, Ipv6Addr, AddressDetails)>, bool) {
+    let mut addresses = Vec::new();
+    for (kind, payload) in split_messages(buf) {
+        match kind {
+            NLMSG_DONE | NLMSG_ERROR => return (addresses, true),
+            RTM_NEWADDR => (),
+            _ => continue,
+        }
+        let message = match parse_address(payload) {
+            Some(message) => message,
+            None => continue,
+        };
+        if let Some(IpAddr::V6(address)) = message.address {
+            addresses.push((
+                message.index,
+                address,
+                AddressDetails {
+                    prefix_len: message.prefix_len,
+                    temporary: message.flags & IFA_F_TEMPORARY != 0,
+                    deprecated: message.flags & IFA_F_DEPRECATED != 0,
+                    tentative: message.flags & (IFA_F_TENTATIVE | IFA_F_DADFAILED) != 0,
+                    preferred_lifetime: message.preferred_lifetime,
+                },
+            ));
+        }
+    }
+    (addresses, false)
+}
+
+/// Message types and payloads of a buffer of netlink messages
+fn split_messages(buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut messages = Vec::new();
+    let mut offset = 0;
+    while offset + NLMSG_HDRLEN <= buf.len() {
+        let len = read_u32(buf, offset) as usize;
+        let kind = read_u16(buf, offset + 4);
+        if len < NLMSG_HDRLEN || offset + len > buf.len() {
+            break;
+        }
+        messages.push((kind, &buf[offset + NLMSG_HDRLEN..offset + len]));
+        offset += align(len);
+    }
+    messages
+}
+
+fn parse_address(message: &[u8]) -> Option<AddressMessage> {
+    if message.len() < IFADDRMSG_LEN {
+        return None;
+    }
+    let mut address = AddressMessage {
+        index: read_u32(message, 4),
+        prefix_len: message[1],
+        flags: message[2] as u32,
+        label: None,
+        address: None,
+        preferred_lifetime: None,
+    };
+    let mut local = None;
+    let mut attributes = &message[IFADDRMSG_LEN..];
+    while attributes.len() >= 4 {
+        let len = read_u16(attributes, 0) as usize;
+        let kind = read_u16(attributes, 2);
+        if len < 4 || len > attributes.len() {
+            break;
+        }
+        let value = &attributes[4..len];
+        match kind {
+            IFA_ADDRESS => address.address = parse_ip(value),
+            IFA_LOCAL => local = parse_ip(value), // the own end of a point-to-point link
+            IFA_LABEL => {
+                let value = value.split(|b| *b == 0).next().unwrap_or(&[]);
+                address.label = String::from_utf8(value.to_vec()).ok();
+            }
+            IFA_CACHEINFO if value.len() >= 8 => {
+                address.preferred_lifetime = match read_u32(value, 0) {
+                    INFINITY_LIFE_TIME => None,
+                    lifetime => Some(lifetime),
+                }
+            }
+            IFA_FLAGS if value.len() >= 4 => address.flags = read_u32(value, 0),
+            _ => (),
+        }
+        attributes = &attributes[align(len).min(attributes.len())..];
+    }
+    if local.is_some() {
+        address.address = local;
+    }
+    Some(address)
+}
+
+fn parse_ip(value: &[u8]) -> Option<IpAddr> {
+    match value.len() {
+        4 => Some(IpAddr::from(<[u8; 4]>::try_from(value).ok()?)),
+        16 => Some(IpAddr::from(<[u8; 16]>::try_from(value).ok()?)),
+        _ => None,
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Flags and lifetimes of every IPv6 address, from an RTM_GETADDR dump (blocking)
+#[cfg(target_os = "linux")]
+pub fn dump_address_details() -> Result<Vec<(u32, Ipv6Addr, AddressDetails)>, String> {
+    let socket = linux::NetlinkSocket::open_blocking().map_err(|e| e.to_string())?;
+    let mut request = Vec::with_capacity(NLMSG_HDRLEN + IFADDRMSG_LEN);
+    request.extend_from_slice(&((NLMSG_HDRLEN + IFADDRMSG_LEN) as u32).to_ne_bytes());
+    request.extend_from_slice(&RTM_GETADDR.to_ne_bytes());
+    request.extend_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+    request.extend_from_slice(&1u32.to_ne_bytes()); // sequence number
+    request.extend_from_slice(&0u32.to_ne_bytes()); // port ID, 0 is the kernel
+    request.extend_from_slice(&[libc::AF_INET6 as u8, 0, 0, 0, 0, 0, 0, 0]);
+    socket.send(&request).map_err(|e| e.to_string())?;
+
+    let mut addresses = Vec::new();
+    let mut buf = vec![0u8; 32768];
+    loop {
+        let len = socket.recv(&mut buf).map_err(|e| e.to_string())?;
+        let (mut found, done) = parse_address_details(&buf[..len]);
+        addresses.append(&mut found);
+        if done || len == 0 {
+            return Ok(addresses);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn dump_address_details() -> Result<Vec<(u32, Ipv6Addr, AddressDetails)>, String> {
+    Err("only supported on Linux".to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn spawn_reader(tx: mpsc::Sender<String>) -> Result<(), String> {
+    let socket = linux::NetlinkSocket::open().map_err(|e| format!("netlink socket: {}", e))?;
+    let socket =
+        tokio::io::unix::AsyncFd::new(socket).map_err(|e| format!("netlink socket: {}", e))?;
+    tokio::spawn(linux::read_events(socket, tx));
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn spawn_reader(_tx: mpsc::Sender<String>) -> Result<(), String> {
+    Err("only supported on Linux".to_string())
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        ffi::CStr,
+        io,
+        os::fd::{AsRawFd, RawFd},
+    };
+
+    use log::{trace, warn};
+    use tokio::{io::unix::AsyncFd, sync::mpsc};
+
+    use super::{parse_address_events, AddressEvent};
+
+    /// A non-blocking NETLINK_ROUTE socket subscribed to IPv4 and IPv6 address changes
+    pub struct NetlinkSocket(RawFd);
+
+    impl NetlinkSocket {
+        /// Subscribed to address changes, for the event loop
+        pub fn open() -> io::Result<NetlinkSocket> {
+            NetlinkSocket::bind(
+                libc::SOCK_NONBLOCK,
+                (libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32,
+            )
+        }
+
+        /// Not subscribed to anything, for one request and its reply
+        pub fn open_blocking() -> io::Result<NetlinkSocket> {
+            NetlinkSocket::bind(0, 0)
+        }
+
+        fn bind(flags: libc::c_int, groups: u32) -> io::Result<NetlinkSocket> {
+            let fd = unsafe {
+                libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_RAW | libc::SOCK_CLOEXEC | flags,
+                    libc::NETLINK_ROUTE,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let socket = NetlinkSocket(fd);
+
+            let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+            address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            address.nl_groups = groups;
+            let result = unsafe {
+                libc::bind(
+                    fd,
+                    &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            };
+            if result < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(socket)
+        }
+
+        pub fn send(&self, buf: &[u8]) -> io::Result<()> {
+            let len =
+                unsafe { libc::send(self.0, buf.as_ptr() as *const libc::c_void, buf.len(), 0) };
+            match len {
+                len if len < 0 => Err(io::Error::last_os_error()),
+                _ => Ok(()),
+            }
+        }
+
+        pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+            let len =
+                unsafe { libc::recv(self.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            match len {
+                len if len < 0 => Err(io::Error::last_os_error()),
+                len => Ok(len as usize),
+            }
+        }
+    }
+
+    impl AsRawFd for NetlinkSocket {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0
+        }
+    }
+
+    impl Drop for NetlinkSocket {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.0);
+            }
+        }
+    }
+
+    pub async fn read_events(socket: AsyncFd<NetlinkSocket>, tx: mpsc::Sender<String>) {
+        let mut buf = vec![0u8; 16384];
+        loop {
+            let mut guard = match socket.readable().await {
+                Ok(guard) => guard,
+                Err(e) => {
+                    warn!("Stopped watching address changes: {}", e);
+                    return;
+                }
+            };
+            let len = match guard.try_io(|socket| socket.get_ref().recv(&mut buf)) {
+                Ok(Ok(len)) => len,
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    // the kernel dropped events, polling catches up with those
+                    warn!("Missed some address changes: {}", e);
+                    continue;
+                }
+                Ok(Err(e)) => {
+                    warn!("Stopped watching address changes: {}", e);
+                    return;
+                }
+                Err(_would_block) => continue,
+            };
+            for event in parse_address_events(&buf[..len]) {
+                match interface_name(&event) {
+                    Some(name) => {
+                        if tx.send(name).await.is_err() {
+                            return;
+                        }
+                    }
+                    None => trace!("Address change on unknown interface {}", event.index),
+                }
+            }
+        }
+    }
+
+    /// The current name of the interface, or the label of the event if it is already gone
+    fn interface_name(event: &AddressEvent) -> Option<String> {
+        let mut name = [0 as libc::c_char; libc::IF_NAMESIZE];
+        let found = unsafe { libc::if_indextoname(event.index, name.as_mut_ptr()) };
+        if found.is_null() {
+            return event.label.clone();
+        }
+        Some(
+            unsafe { CStr::from_ptr(name.as_ptr()) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
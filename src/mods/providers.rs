@@ -1,13 +1,15 @@
 pub mod aliyun;
 pub mod cloudflare;
+pub mod custom;
 pub mod dnspod;
 pub mod dynv6;
 pub mod namecheap;
+pub mod rfc2136;
 pub mod types;
 
 use std::collections::HashMap;
 
-use self::types::ProvidersErrorType;
+use self::types::{ProvidersErrorType, RecordEntry, RecordSetPlan};
 
 use super::request::RequestStructure;
 use async_trait::async_trait;
@@ -17,6 +19,42 @@ pub trait DDNSProviderTrait: Send + Sync {
     // fn new() -> Self;
     async fn update(&mut self, ip: &str) -> Result<(), String>;
     async fn get_ip_address(&mut self) -> Result<String, ProvidersErrorType>;
+    /// ID of the record found or created last, for providers that have one
+    fn record_id(&self) -> Option<String> {
+        None
+    }
+    /// Reuse a record ID remembered from a previous run instead of querying for it
+    fn set_record_id(&mut self, _record_id: &str) {}
+    /// Only log clean-ups such as deleting duplicate records instead of doing them
+    fn set_dry_run(&mut self, _dry_run: bool) {}
+
+    // Record sets, for sites publishing several addresses (`count` != 1)
+    /// Every record of the site
+    async fn get_record_set(&mut self) -> Result<Vec<RecordEntry>, ProvidersErrorType> {
+        Err(ProvidersErrorType::NotSupported)
+    }
+    async fn add_set_record(&mut self, _ip: &str) -> Result<(), String> {
+        Err("record sets are not supported by this provider".to_string())
+    }
+    async fn change_set_record(&mut self, _record_id: &str, _ip: &str) -> Result<(), String> {
+        Err("record sets are not supported by this provider".to_string())
+    }
+    async fn remove_set_record(&mut self, _record_id: &str) -> Result<(), String> {
+        Err("record sets are not supported by this provider".to_string())
+    }
+    /// Apply `plan`; records are added before stale ones are removed so the name keeps resolving
+    async fn update_set(&mut self, plan: &RecordSetPlan) -> Result<(), String> {
+        for (record, ip) in plan.change.iter() {
+            self.change_set_record(&record.id, ip).await?;
+        }
+        for ip in plan.add.iter() {
+            self.add_set_record(ip).await?;
+        }
+        for record in plan.remove.iter() {
+            self.remove_set_record(&record.id).await?;
+        }
+        Ok(())
+    }
     async fn request_execute(
         &self,
         request: &RequestStructure,
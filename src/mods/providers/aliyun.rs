@@ -1,4 +1,7 @@
-use super::{types::ProvidersErrorType, DDNSProviderTrait};
+use super::{
+    types::{ProvidersErrorType, RecordEntry},
+    DDNSProviderTrait,
+};
 use aliyun_dns::{AliyunDns, DomainRecord};
 use async_trait::async_trait;
 use log::{error, info};
@@ -18,6 +21,8 @@ pub struct Aliyun {
     has_record: Option<bool>,
     #[serde(skip)]
     record_id: String,
+    #[serde(skip)]
+    dry_run: bool,
 }
 
 // 辅助结构体，用于反序列化
@@ -55,6 +60,7 @@ impl<'de> Deserialize<'de> for Aliyun {
             record_type: helper.record_type,
             aliyun_dns,
             has_record: None,
+            dry_run: false,
             record_id: String::new(),
         })
     }
@@ -71,6 +77,7 @@ impl Clone for Aliyun {
             record_type: self.record_type.clone(),
             aliyun_dns: AliyunDns::new(self.access_key_id.clone(), self.access_key_secret.clone()),
             has_record: self.has_record.clone(),
+            dry_run: self.dry_run,
             record_id: self.record_id.clone(),
         }
     }
@@ -95,10 +102,43 @@ impl Aliyun {
             record_type: record_type.to_string(),
             aliyun_dns,
             has_record: Option::None,
+            dry_run: false,
             record_id: String::new(),
         }
     }
 
+    /// Every record of the subdomain, all pages
+    async fn query_records(&self) -> Result<Vec<DomainRecord>, ProvidersErrorType> {
+        let sub_domain = format!("{}.{}", self.rr, self.domain);
+        let mut domain_records: Vec<DomainRecord> = Vec::with_capacity(8);
+        let mut page_number = 1;
+        loop {
+            let query_response = match self
+                .aliyun_dns
+                .query_subdomain_records(
+                    &self.domain,
+                    &sub_domain,
+                    &self.record_type,
+                    Some(page_number),
+                    Some(20),
+                )
+                .await
+            {
+                Ok(query_response) => query_response,
+                Err(e) => {
+                    error!("Failed to query domain records: {}", e);
+                    return Err(ProvidersErrorType::QueryDomainRecordsError);
+                }
+            };
+            domain_records.extend(query_response.domain_records.records);
+            if (20 * page_number) >= query_response.total_count {
+                break;
+            }
+            page_number += 1;
+        }
+        Ok(domain_records)
+    }
+
     pub async fn delete_subdomain_records(&mut self) -> Result<(), String> {
         match self
             .aliyun_dns
@@ -175,40 +215,26 @@ impl DDNSProviderTrait for Aliyun {
 
     async fn get_ip_address(&mut self) -> Result<String, ProvidersErrorType> {
         let sub_domain = format!("{}.{}", self.rr, self.domain);
-        let mut domain_records: Vec<DomainRecord> = Vec::with_capacity(8);
-        let mut total_record_count: u32;
-        let mut page_number = 1;
-        loop {
-            let query_response = match self
-                .aliyun_dns
-                .query_subdomain_records(
-                    &self.domain,
-                    &sub_domain,
-                    &self.record_type,
-                    Some(1),
-                    Some(20),
-                )
-                .await
-            {
-                Ok(query_response) => query_response,
-                Err(e) => {
-                    error!("Failed to query domain records: {}", e);
-                    return Err(ProvidersErrorType::QueryDomainRecordsError);
-                }
-            };
-            domain_records.extend(query_response.domain_records.records);
-            total_record_count = query_response.total_count;
-            if (20 * page_number) >= total_record_count {
-                break;
-            }
-            page_number += 1;
-        }
+        let domain_records = self.query_records().await?;
+        let total_record_count = domain_records.len();
 
         // println!("Total Records: {}", total_record_count);
         if total_record_count == 0 {
             self.has_record = Option::Some(false);
             return Err(ProvidersErrorType::NoRecordFound);
         } else if total_record_count > 1 {
+            if self.dry_run {
+                let values = domain_records
+                    .iter()
+                    .map(|record| record.value.as_str())
+                    .collect::<Vec<_>>();
+                info!(
+                    "dry run, would delete all {} records of {} ({:?}) and create a new one",
+                    total_record_count, sub_domain, values
+                );
+                self.has_record = Option::Some(false);
+                return Err(ProvidersErrorType::NoRecordFound);
+            }
             match self.delete_subdomain_records().await {
                 Ok(_) => {}
                 Err(e) => {
@@ -226,4 +252,66 @@ impl DDNSProviderTrait for Aliyun {
         self.has_record = Option::Some(true);
         Ok(ip)
     }
+
+    fn record_id(&self) -> Option<String> {
+        match self.has_record {
+            Some(true) => Some(self.record_id.clone()),
+            _ => None,
+        }
+    }
+
+    fn set_record_id(&mut self, record_id: &str) {
+        self.record_id = record_id.to_string();
+        self.has_record = Some(true);
+    }
+
+    fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    async fn get_record_set(&mut self) -> Result<Vec<RecordEntry>, ProvidersErrorType> {
+        let domain_records = self.query_records().await?;
+        Ok(domain_records
+            .iter()
+            .map(|record| RecordEntry::new(&record.record_id, &record.value))
+            .collect())
+    }
+
+    async fn add_set_record(&mut self, ip: &str) -> Result<(), String> {
+        match self
+            .aliyun_dns
+            .add_domain_record(&self.domain, &self.rr, &self.record_type, ip, self.ttl)
+            .await
+        {
+            Ok(response) => {
+                info!("Added Record ID: {}", response.record_id);
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to add domain record: {}", e)),
+        }
+    }
+
+    async fn change_set_record(&mut self, record_id: &str, ip: &str) -> Result<(), String> {
+        match self
+            .aliyun_dns
+            .update_domain_record(record_id, &self.rr, &self.record_type, ip, self.ttl)
+            .await
+        {
+            Ok(_) => {
+                info!("Updated Record ID: {}", record_id);
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to update domain record: {}", e)),
+        }
+    }
+
+    async fn remove_set_record(&mut self, record_id: &str) -> Result<(), String> {
+        match self.aliyun_dns.delete_domain_record(record_id).await {
+            Ok(_) => {
+                info!("Deleted Record ID: {}", record_id);
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to delete domain record: {}", e)),
+        }
+    }
 }
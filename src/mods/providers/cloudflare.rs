@@ -1,85 +1,376 @@
-// use async_trait::async_trait;
-// use reqwest::header::{HeaderMap, HeaderValue};
-// use serde::Serialize;
-
-// use crate::mods::request::{RequestMethod, RequestStructure};
-
-// use super::{types::ProvidersErrorType, DDNSProviderTrait};
-
-// #[derive(Serialize)]
-// pub struct CloudFlare {
-//     pub zone_id: String,
-//     pub api_token: String,
-//     pub domain: String,
-//     pub rr: String,
-//     pub ttl: Option<u64>,
-//     pub record_type: String,
-//     #[serde(skip)]
-//     has_record: Option<bool>,
-//     #[serde(skip)]
-//     record_id: String,
-// }
-
-// impl CloudFlare {
-//     pub fn new(
-//         zone_id: String,
-//         api_token: String,
-//         domain: String,
-//         rr: String,
-//         ttl: Option<u64>,
-//         record_type: String,
-//     ) -> Self {
-//         CloudFlare {
-//             zone_id,
-//             api_token,
-//             domain,
-//             rr,
-//             ttl,
-//             record_type,
-//             has_record: None,
-//             record_id: String::new(),
-//         }
-//     }
-// }
-
-// #[async_trait]
-// impl DDNSProviderTrait for CloudFlare {
-//     async fn update(&mut self, ip: &str) -> Result<(), String> {
-//         todo!("update")
-//     }
-
-//     async fn get_ip_address(&mut self) -> Result<String, ProvidersErrorType> {
-//         // 使用 Cloudflare API 获取特定子域名的 A 记录
-
-//         // 构造请求
-//         let mut headers = HeaderMap::new();
-//         headers.insert(
-//             "Authorization",
-//             HeaderValue::from_str(&format!("Bearer {}", self.api_token))
-//                 .unwrap_or(HeaderValue::from_static("")),
-//         );
-//         headers.insert("Content-Type", HeaderValue::from_static("application/json"));
-
-//         let url = format!(
-//             "https://api.cloudflare.com/client/v4/zones/{}/dns_records?type={}&name={}",
-//             self.zone_id, self.record_type, self.rr
-//         );
-
-//         let request = RequestStructure::new(
-//             RequestMethod::GET,
-//             url,
-//             "".to_string(),
-//             Some(headers),
-//             None,
-//             None,
-//             None,
-//         );
-//         let (headers, body) = match self.request_execute(&request).await {
-//             Ok((headers, body)) => (headers, body),
-//             Err(_) => {
-//                 return Err(ProvidersErrorType::NetworkError);
-//             }
-//         };
-
-//     }
-// }
+use super::{
+    types::{ProvidersErrorType, RecordEntry},
+    DDNSProviderTrait,
+};
+use crate::mods::request::{RequestMethod, RequestStructure};
+use async_trait::async_trait;
+use log::{error, info, warn};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+
+const CLOUDFLARE_API_BASE: &str = "https://api.cloudflare.com/client/v4";
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Cloudflare {
+    pub zone_id: String,
+    pub api_token: String, //Bearer Token
+    pub domain: String,
+    pub rr: String,
+    pub ttl: Option<u64>, // 1 means automatic
+    #[serde(default)]
+    pub proxied: bool,
+    pub record_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_base: Option<String>, // override for testing or API gateways
+    #[serde(skip)]
+    has_record: Option<bool>,
+    #[serde(skip)]
+    record: Option<DnsRecord>,
+    #[serde(skip)]
+    dry_run: bool,
+}
+
+impl Cloudflare {
+    pub fn new(
+        zone_id: &str,
+        api_token: &str,
+        domain: &str,
+        rr: &str,
+        ttl: Option<u64>,
+        proxied: bool,
+        record_type: &str,
+    ) -> Self {
+        Self {
+            zone_id: zone_id.to_string(),
+            api_token: api_token.to_string(),
+            domain: domain.to_string(),
+            rr: rr.to_string(),
+            ttl,
+            proxied,
+            record_type: record_type.to_string(),
+            api_base: None,
+            has_record: Option::None,
+            dry_run: false,
+            record: Option::None,
+        }
+    }
+
+    fn full_name(&self) -> String {
+        match self.rr.as_str() {
+            "@" | "" => self.domain.clone(),
+            rr => format!("{}.{}", rr, self.domain),
+        }
+    }
+
+    fn records_url(&self) -> String {
+        format!(
+            "{}/zones/{}/dns_records",
+            self.api_base
+                .as_deref()
+                .unwrap_or(CLOUDFLARE_API_BASE)
+                .trim_end_matches('/'),
+            self.zone_id
+        )
+    }
+
+    fn headers(&self) -> HeaderMap {
+        HeaderMap::from_iter(vec![
+            (
+                HeaderName::from_static("authorization"),
+                HeaderValue::from_str(&format!("Bearer {}", self.api_token))
+                    .unwrap_or(HeaderValue::from_static("")),
+            ),
+            (
+                HeaderName::from_static("content-type"),
+                "application/json".parse().unwrap(),
+            ),
+            (
+                HeaderName::from_static("accept"),
+                "application/json".parse().unwrap(),
+            ),
+        ])
+    }
+
+    fn record_body(&self, ip: &str) -> String {
+        json!({
+            "type": self.record_type,
+            "name": self.full_name(),
+            "content": ip,
+            "ttl": self.ttl.unwrap_or(1),
+            "proxied": self.proxied,
+        })
+        .to_string()
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        mathod: RequestMethod,
+        url: String,
+        content: String,
+    ) -> Result<T, ProvidersErrorType> {
+        let request =
+            RequestStructure::new(mathod, url, content, Some(self.headers()), None, None, None);
+        let (status, _, rsp_body) = match self.request_execute(&request).await {
+            Ok(value) => value,
+            Err(_) => return Err(ProvidersErrorType::NetworkError),
+        };
+        parse_response(status, &rsp_body)
+    }
+
+    async fn query_records(&self) -> Result<Vec<DnsRecord>, ProvidersErrorType> {
+        let url = format!(
+            "{}?type={}&name={}",
+            self.records_url(),
+            self.record_type,
+            self.full_name()
+        );
+        self.send(RequestMethod::GET, url, String::new()).await
+    }
+
+    async fn delete_record(&self, record_id: &str) -> Result<(), ProvidersErrorType> {
+        let url = format!("{}/{}", self.records_url(), record_id);
+        self.send::<serde_json::Value>(RequestMethod::DELETE, url, String::new())
+            .await
+            .map(|_| ())
+    }
+}
+
+#[async_trait]
+impl DDNSProviderTrait for Cloudflare {
+    async fn update(&mut self, ip: &str) -> Result<(), String> {
+        let has_record = match self.has_record {
+            Some(has_record) => has_record,
+            None => self.get_ip_address().await.is_ok(),
+        };
+
+        match (has_record, self.record.clone()) {
+            (true, Some(record)) => {
+                if record.content == ip
+                    && record.ttl == self.ttl.unwrap_or(1)
+                    && record.proxied == self.proxied
+                {
+                    info!("Record {} is up to date", record.id);
+                    return Ok(());
+                }
+                let url = format!("{}/{}", self.records_url(), record.id);
+                match self
+                    .send::<DnsRecord>(RequestMethod::PATCH, url, self.record_body(ip))
+                    .await
+                {
+                    Ok(record) => {
+                        info!("Updated Record ID: {}", record.id);
+                        self.record = Some(record);
+                    }
+                    Err(e) => {
+                        error!("Failed to update domain record: {}", e);
+                        self.has_record = Option::None;
+                        return Err(format!("Failed to update domain record: {}", e));
+                    }
+                }
+            }
+            _ => {
+                match self
+                    .send::<DnsRecord>(
+                        RequestMethod::POST,
+                        self.records_url(),
+                        self.record_body(ip),
+                    )
+                    .await
+                {
+                    Ok(record) => {
+                        info!("Added Record ID: {}", record.id);
+                        self.has_record = Some(true);
+                        self.record = Some(record);
+                    }
+                    Err(e) => {
+                        error!("Failed to add domain record: {}", e);
+                        return Err(format!("Failed to add domain record: {}", e));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get_ip_address(&mut self) -> Result<String, ProvidersErrorType> {
+        let records = match self.query_records().await {
+            Ok(records) => records,
+            Err(e) => {
+                error!("Failed to query domain records: {}", e);
+                return Err(e);
+            }
+        };
+
+        if records.is_empty() {
+            self.has_record = Option::Some(false);
+            self.record = Option::None;
+            return Err(ProvidersErrorType::NoRecordFound);
+        } else if records.len() > 1 {
+            // delete mutiple records (retain the first one)
+            warn!(
+                "Found {} records for {}, deleting duplicates",
+                records.len(),
+                self.full_name()
+            );
+            for record in records.iter().skip(1) {
+                if self.dry_run {
+                    info!(
+                        "dry run, would delete duplicate record {} ({})",
+                        record.id, record.content
+                    );
+                    continue;
+                }
+                if let Err(e) = self.delete_record(&record.id).await {
+                    error!("Failed to delete subdomain records: {}", e);
+                    return Err(ProvidersErrorType::DeleteDomainRecordsError);
+                }
+            }
+        }
+
+        let ip = records[0].content.clone();
+        self.record = Some(records[0].clone());
+        self.has_record = Option::Some(true);
+        Ok(ip)
+    }
+
+    fn record_id(&self) -> Option<String> {
+        self.record.as_ref().map(|record| record.id.clone())
+    }
+
+    fn set_record_id(&mut self, record_id: &str) {
+        // content is unknown, so the next update always patches
+        self.record = Some(DnsRecord {
+            id: record_id.to_string(),
+            record_type: self.record_type.clone(),
+            name: self.full_name(),
+            content: String::new(),
+            ttl: 0,
+            proxied: self.proxied,
+        });
+        self.has_record = Some(true);
+    }
+
+    fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    async fn get_record_set(&mut self) -> Result<Vec<RecordEntry>, ProvidersErrorType> {
+        let records = self.query_records().await?;
+        Ok(records
+            .iter()
+            .map(|record| RecordEntry::new(&record.id, &record.content))
+            .collect())
+    }
+
+    async fn add_set_record(&mut self, ip: &str) -> Result<(), String> {
+        match self
+            .send::<DnsRecord>(
+                RequestMethod::POST,
+                self.records_url(),
+                self.record_body(ip),
+            )
+            .await
+        {
+            Ok(record) => {
+                info!("Added Record ID: {}", record.id);
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to add domain record: {}", e)),
+        }
+    }
+
+    async fn change_set_record(&mut self, record_id: &str, ip: &str) -> Result<(), String> {
+        let url = format!("{}/{}", self.records_url(), record_id);
+        match self
+            .send::<DnsRecord>(RequestMethod::PATCH, url, self.record_body(ip))
+            .await
+        {
+            Ok(record) => {
+                info!("Updated Record ID: {}", record.id);
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to update domain record: {}", e)),
+        }
+    }
+
+    async fn remove_set_record(&mut self, record_id: &str) -> Result<(), String> {
+        match self.delete_record(record_id).await {
+            Ok(_) => {
+                info!("Deleted Record ID: {}", record_id);
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to delete domain record: {}", e)),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct DnsRecord {
+    id: String,
+    #[serde(default, rename = "type")]
+    record_type: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    ttl: u64,
+    #[serde(default)]
+    proxied: bool,
+}
+
+#[derive(Deserialize)]
+struct CloudflareResponse<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<CloudflareError>,
+    result: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct CloudflareError {
+    code: u64,
+    #[serde(default)]
+    message: String,
+}
+
+/// Parse the `success`/`errors`/`result` envelope returned by every Cloudflare v4 endpoint.
+fn parse_response<T: DeserializeOwned>(status: u16, body: &str) -> Result<T, ProvidersErrorType> {
+    let response: CloudflareResponse<T> = match serde_json::from_str(body) {
+        Ok(value) => value,
+        Err(e) => {
+            error!(
+                "Failed to parse Cloudflare response (status {}): {}",
+                status, e
+            );
+            return match status {
+                401 | 403 => Err(ProvidersErrorType::KeyError),
+                _ => Err(ProvidersErrorType::OtherError),
+            };
+        }
+    };
+
+    if !response.success {
+        for err in response.errors.iter() {
+            error!("Cloudflare error {}: {}", err.code, err.message);
+        }
+        return Err(match response.errors.first().map(|err| err.code) {
+            // invalid request headers / authentication error
+            Some(6003 | 6111 | 9103 | 9109 | 10000) => ProvidersErrorType::KeyError,
+            // record does not exist
+            Some(81044) => ProvidersErrorType::NoRecordFound,
+            _ => match status {
+                401 | 403 => ProvidersErrorType::KeyError,
+                404 => ProvidersErrorType::NoRecordFound,
+                _ => ProvidersErrorType::OtherError,
+            },
+        });
+    }
+
+    match response.result {
+        Some(result) => Ok(result),
+        None => Err(ProvidersErrorType::OtherError),
+    }
+}
@@ -0,0 +1,242 @@
+use super::{types::ProvidersErrorType, DDNSProviderTrait};
+use crate::mods::request::{url_encode, RequestMethod, RequestStructure};
+use async_trait::async_trait;
+use log::{error, info, trace};
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Generic provider driven entirely by the config file.
+///
+/// `url`, `headers` and `body` may contain the `{ip}`, `{name}` and `{domain}` placeholders.
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct Custom {
+    #[serde(default)]
+    pub domain: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub update: CustomRequest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<CustomQuery>,
+    #[serde(skip)]
+    last_ip: Option<String>, // used when no query request is configured
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct CustomRequest {
+    #[serde(default = "default_method")]
+    pub method: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub success_status: Vec<u16>, // empty means any 2xx
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success_body: Option<String>, // regex the response body must match
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct CustomQuery {
+    #[serde(flatten)]
+    pub request: CustomRequest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_path: Option<String>, // e.g. `$.result[0].content`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>, // first capture group (or whole match) is the value
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+impl Custom {
+    pub fn new(
+        domain: &str,
+        name: &str,
+        update: CustomRequest,
+        query: Option<CustomQuery>,
+    ) -> Self {
+        Self {
+            domain: domain.to_string(),
+            name: name.to_string(),
+            update,
+            query,
+            last_ip: Option::None,
+        }
+    }
+
+    /// Fill in `{ip}`, `{name}` and `{domain}`, percent-encoded for URLs so values such as
+    /// `@` or an IPv6 zone ID cannot change the request
+    fn render(&self, template: &str, ip: &str, url: bool) -> String {
+        let value = |value: &str| match url {
+            true => url_encode(value),
+            false => value.to_string(),
+        };
+        template
+            .replace("{ip}", &value(ip))
+            .replace("{name}", &value(&self.name))
+            .replace("{domain}", &value(&self.domain))
+    }
+
+    async fn execute(&self, request: &CustomRequest, ip: &str) -> Result<String, String> {
+        let mathod = match RequestMethod::from_name(&request.method) {
+            Some(value) => value,
+            None => return Err(format!("Unsupported method: {}", request.method)),
+        };
+        let mut headers = HeaderMap::new();
+        for (key, value) in request.headers.iter() {
+            let key = match HeaderName::from_bytes(key.as_bytes()) {
+                Ok(value) => value,
+                Err(_) => return Err(format!("Invalid header name: {}", key)),
+            };
+            let value = match HeaderValue::from_str(&self.render(value, ip, false)) {
+                Ok(value) => value,
+                Err(_) => return Err(format!("Invalid header value for {}", key)),
+            };
+            headers.insert(key, value);
+        }
+        let structure = RequestStructure::new(
+            mathod,
+            self.render(&request.url, ip, true),
+            self.render(&request.body, ip, false),
+            Some(headers),
+            None,
+            None,
+            None,
+        );
+        let (status, _, rsp_body) = match self.request_execute(&structure).await {
+            Ok(value) => value,
+            Err(_) => return Err("Failed to send request".to_string()),
+        };
+        trace!("Custom provider response {}: {}", status, rsp_body);
+
+        let status_ok = if request.success_status.is_empty() {
+            (200..300).contains(&status)
+        } else {
+            request.success_status.contains(&status)
+        };
+        if !status_ok {
+            return Err(format!("Unexpected status code: {}", status));
+        }
+        if let Some(pattern) = &request.success_body {
+            let re = match Regex::new(pattern) {
+                Ok(value) => value,
+                Err(e) => return Err(format!("Invalid success_body regex: {}", e)),
+            };
+            if !re.is_match(&rsp_body) {
+                return Err(format!("Response body does not match: {}", rsp_body));
+            }
+        }
+        Ok(rsp_body)
+    }
+}
+
+#[async_trait]
+impl DDNSProviderTrait for Custom {
+    async fn update(&mut self, ip: &str) -> Result<(), String> {
+        match self.execute(&self.update, ip).await {
+            Ok(_) => {
+                info!("Updated {}.{} to {}", self.name, self.domain, ip);
+                self.last_ip = Some(ip.to_string());
+                Ok(())
+            }
+            Err(e) => {
+                error!("Failed to update domain record: {}", e);
+                self.last_ip = Option::None;
+                Err(format!("Failed to update domain record: {}", e))
+            }
+        }
+    }
+
+    async fn get_ip_address(&mut self) -> Result<String, ProvidersErrorType> {
+        let query = match &self.query {
+            Some(query) => query,
+            None => {
+                return match self.last_ip.clone() {
+                    Some(ip) => Ok(ip),
+                    None => Err(ProvidersErrorType::NoRecordFound),
+                }
+            }
+        };
+        let rsp_body = match self.execute(&query.request, "").await {
+            Ok(value) => value,
+            Err(e) => {
+                error!("Failed to query domain records: {}", e);
+                return Err(ProvidersErrorType::QueryDomainRecordsError);
+            }
+        };
+        match extract_value(
+            &rsp_body,
+            query.json_path.as_deref(),
+            query.regex.as_deref(),
+        ) {
+            Ok(Some(ip)) => Ok(ip),
+            Ok(None) => Err(ProvidersErrorType::NoRecordFound),
+            Err(e) => {
+                error!("Failed to extract value from response: {}", e);
+                Err(ProvidersErrorType::OtherError)
+            }
+        }
+    }
+}
+
+/// Apply the optional JSONPath and then the optional regex to a response body
+pub fn extract_value(
+    body: &str,
+    json_path: Option<&str>,
+    regex: Option<&str>,
+) -> Result<Option<String>, String> {
+    let mut value = body.to_string();
+    if let Some(path) = json_path {
+        let json: Value = match serde_json::from_str(body) {
+            Ok(value) => value,
+            Err(e) => return Err(format!("Response is not JSON: {}", e)),
+        };
+        value = match json_lookup(&json, path) {
+            Some(Value::String(value)) => value.clone(),
+            Some(Value::Null) | None => return Ok(None),
+            Some(value) => value.to_string(),
+        };
+    }
+    if let Some(pattern) = regex {
+        let re = match Regex::new(pattern) {
+            Ok(value) => value,
+            Err(e) => return Err(format!("Invalid regex: {}", e)),
+        };
+        value = match re.captures(&value) {
+            Some(captures) => match captures.get(1).or_else(|| captures.get(0)) {
+                Some(matched) => matched.as_str().to_string(),
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+    }
+    let value = value.trim().to_string();
+    if value.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(value))
+    }
+}
+
+/// Minimal JSONPath: `$.a.b[0].c`, `a.b.0.c` and `$['a']` are all accepted
+pub fn json_lookup<'a>(json: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.trim().trim_start_matches('$');
+    let mut current = json;
+    let normalized = path.replace('[', ".").replace(']', "");
+    for segment in normalized.split('.').filter(|s| !s.is_empty()) {
+        let segment = segment.trim_matches(|c| c == '\'' || c == '"');
+        current = match current {
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            Value::Object(map) => map.get(segment)?,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
@@ -0,0 +1,572 @@
+use super::{
+    types::{ProvidersErrorType, RecordEntry},
+    DDNSProviderTrait,
+};
+use crate::mods::request::{url_encode, RequestMethod, RequestStructure};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+const LEGACY_ENDPOINT: &str = "https://dnsapi.cn";
+const TENCENT_CLOUD_ENDPOINT: &str = "https://dnspod.tencentcloudapi.com";
+const TENCENT_CLOUD_SERVICE: &str = "dnspod";
+const TENCENT_CLOUD_VERSION: &str = "2021-03-23";
+const DEFAULT_RECORD_LINE: &str = "默认";
+// DNSPod blocks requests without a descriptive user agent
+const USER_AGENT: &str = concat!("ddns-rust/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub enum DnspodApi {
+    Legacy,       // dnsapi.cn with a `ID,Token` login token
+    TencentCloud, // TencentCloud API 3.0 with SecretId / SecretKey
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Dnspod {
+    pub api: DnspodApi,
+    pub id: String,    // token ID (Legacy) or SecretId (TencentCloud)
+    pub token: String, // token (Legacy) or SecretKey (TencentCloud)
+    pub domain: String,
+    pub rr: String,
+    pub ttl: Option<u64>,
+    pub record_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record_line: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>, // override for testing
+    #[serde(skip)]
+    has_record: Option<bool>,
+    #[serde(skip)]
+    record_id: String,
+    #[serde(skip)]
+    dry_run: bool,
+}
+
+impl Dnspod {
+    pub fn new(
+        api: DnspodApi,
+        id: &str,
+        token: &str,
+        domain: &str,
+        rr: &str,
+        ttl: Option<u64>,
+        record_type: &str,
+    ) -> Self {
+        Self {
+            api,
+            id: id.to_string(),
+            token: token.to_string(),
+            domain: domain.to_string(),
+            rr: rr.to_string(),
+            ttl,
+            record_type: record_type.to_string(),
+            record_line: Option::None,
+            endpoint: Option::None,
+            has_record: Option::None,
+            dry_run: false,
+            record_id: String::new(),
+        }
+    }
+
+    fn sub_domain(&self) -> &str {
+        match self.rr.as_str() {
+            "" => "@",
+            rr => rr,
+        }
+    }
+
+    fn record_line(&self) -> &str {
+        self.record_line.as_deref().unwrap_or(DEFAULT_RECORD_LINE)
+    }
+
+    fn endpoint(&self) -> &str {
+        let default = match self.api {
+            DnspodApi::Legacy => LEGACY_ENDPOINT,
+            DnspodApi::TencentCloud => TENCENT_CLOUD_ENDPOINT,
+        };
+        self.endpoint
+            .as_deref()
+            .unwrap_or(default)
+            .trim_end_matches('/')
+    }
+
+    /// Returns (record_id, value) of every matching record
+    async fn query_records(&self) -> Result<Vec<(String, String)>, ProvidersErrorType> {
+        let records = match self.api {
+            DnspodApi::Legacy => {
+                let params = vec![
+                    ("domain", self.domain.clone()),
+                    ("sub_domain", self.sub_domain().to_string()),
+                    ("record_type", self.record_type.clone()),
+                ];
+                match self.legacy_request("Record.List", params).await {
+                    Ok(rsp) => rsp["records"].as_array().cloned().unwrap_or_default(),
+                    Err(ProvidersErrorType::NoRecordFound) => Vec::new(),
+                    Err(e) => return Err(e),
+                }
+                .iter()
+                .filter(|record| record["type"].as_str() == Some(self.record_type.as_str()))
+                .map(|record| {
+                    (
+                        value_to_string(&record["id"]),
+                        value_to_string(&record["value"]),
+                    )
+                })
+                .collect()
+            }
+            DnspodApi::TencentCloud => {
+                let payload = json!({
+                    "Domain": self.domain,
+                    "Subdomain": self.sub_domain(),
+                    "RecordType": self.record_type,
+                });
+                match self
+                    .tencent_cloud_request("DescribeRecordList", payload)
+                    .await
+                {
+                    Ok(rsp) => rsp["RecordList"].as_array().cloned().unwrap_or_default(),
+                    Err(ProvidersErrorType::NoRecordFound) => Vec::new(),
+                    Err(e) => return Err(e),
+                }
+                .iter()
+                .map(|record| {
+                    (
+                        value_to_string(&record["RecordId"]),
+                        value_to_string(&record["Value"]),
+                    )
+                })
+                .collect()
+            }
+        };
+        Ok(records)
+    }
+
+    async fn create_record(&self, ip: &str) -> Result<String, ProvidersErrorType> {
+        match self.api {
+            DnspodApi::Legacy => {
+                let mut params = vec![
+                    ("domain", self.domain.clone()),
+                    ("sub_domain", self.sub_domain().to_string()),
+                    ("record_type", self.record_type.clone()),
+                    ("record_line", self.record_line().to_string()),
+                    ("value", ip.to_string()),
+                ];
+                if let Some(ttl) = self.ttl {
+                    params.push(("ttl", ttl.to_string()));
+                }
+                let rsp = self.legacy_request("Record.Create", params).await?;
+                Ok(value_to_string(&rsp["record"]["id"]))
+            }
+            DnspodApi::TencentCloud => {
+                let mut payload = json!({
+                    "Domain": self.domain,
+                    "SubDomain": self.sub_domain(),
+                    "RecordType": self.record_type,
+                    "RecordLine": self.record_line(),
+                    "Value": ip,
+                });
+                if let Some(ttl) = self.ttl {
+                    payload["TTL"] = json!(ttl);
+                }
+                let rsp = self.tencent_cloud_request("CreateRecord", payload).await?;
+                Ok(value_to_string(&rsp["RecordId"]))
+            }
+        }
+    }
+
+    async fn modify_record(&self, record_id: &str, ip: &str) -> Result<(), ProvidersErrorType> {
+        match self.api {
+            DnspodApi::Legacy => {
+                let mut params = vec![
+                    ("domain", self.domain.clone()),
+                    ("record_id", record_id.to_string()),
+                    ("sub_domain", self.sub_domain().to_string()),
+                    ("record_line", self.record_line().to_string()),
+                    ("value", ip.to_string()),
+                ];
+                // Record.Ddns only handles A records
+                let action = if self.record_type == "A" {
+                    "Record.Ddns"
+                } else {
+                    params.push(("record_type", self.record_type.clone()));
+                    "Record.Modify"
+                };
+                if let Some(ttl) = self.ttl {
+                    params.push(("ttl", ttl.to_string()));
+                }
+                self.legacy_request(action, params).await.map(|_| ())
+            }
+            DnspodApi::TencentCloud => {
+                let mut payload = json!({
+                    "Domain": self.domain,
+                    "SubDomain": self.sub_domain(),
+                    "RecordType": self.record_type,
+                    "RecordLine": self.record_line(),
+                    "Value": ip,
+                    "RecordId": record_id.parse::<u64>().unwrap_or_default(),
+                });
+                if let Some(ttl) = self.ttl {
+                    payload["TTL"] = json!(ttl);
+                }
+                self.tencent_cloud_request("ModifyRecord", payload)
+                    .await
+                    .map(|_| ())
+            }
+        }
+    }
+
+    async fn delete_record(&self, record_id: &str) -> Result<(), ProvidersErrorType> {
+        match self.api {
+            DnspodApi::Legacy => {
+                let params = vec![
+                    ("domain", self.domain.clone()),
+                    ("record_id", record_id.to_string()),
+                ];
+                self.legacy_request("Record.Remove", params)
+                    .await
+                    .map(|_| ())
+            }
+            DnspodApi::TencentCloud => {
+                let payload = json!({
+                    "Domain": self.domain,
+                    "RecordId": record_id.parse::<u64>().unwrap_or_default(),
+                });
+                self.tencent_cloud_request("DeleteRecord", payload)
+                    .await
+                    .map(|_| ())
+            }
+        }
+    }
+
+    async fn legacy_request(
+        &self,
+        action: &str,
+        params: Vec<(&str, String)>,
+    ) -> Result<Value, ProvidersErrorType> {
+        let mut content = format!(
+            "login_token={}&format=json&lang=en",
+            url_encode(&format!("{},{}", self.id, self.token))
+        );
+        for (key, value) in params.iter() {
+            content.push_str(&format!("&{}={}", key, url_encode(value)));
+        }
+        let headers = HeaderMap::from_iter(vec![(
+            HeaderName::from_static("content-type"),
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        )]);
+        let request = RequestStructure::new(
+            RequestMethod::POST,
+            format!("{}/{}", self.endpoint(), action),
+            content,
+            Some(headers),
+            None,
+            Some(USER_AGENT.to_string()),
+            None,
+        );
+        let (_, _, rsp_body) = match self.request_execute(&request).await {
+            Ok(value) => value,
+            Err(_) => return Err(ProvidersErrorType::NetworkError),
+        };
+        let rsp: Value = match serde_json::from_str(&rsp_body) {
+            Ok(value) => value,
+            Err(e) => {
+                error!("Failed to parse DNSPod response: {}", e);
+                return Err(ProvidersErrorType::OtherError);
+            }
+        };
+        let code = value_to_string(&rsp["status"]["code"]);
+        match code.as_str() {
+            "1" => Ok(rsp),
+            // no records
+            "10" => Err(ProvidersErrorType::NoRecordFound),
+            // login failed / token invalid / login fail too many times
+            "-1" | "-7" | "-8" | "-99" | "85" => {
+                error!("DNSPod {} failed: {}", action, rsp["status"]["message"]);
+                Err(ProvidersErrorType::KeyError)
+            }
+            _ => {
+                error!(
+                    "DNSPod {} failed with code {}: {}",
+                    action, code, rsp["status"]["message"]
+                );
+                Err(ProvidersErrorType::OtherError)
+            }
+        }
+    }
+
+    async fn tencent_cloud_request(
+        &self,
+        action: &str,
+        payload: Value,
+    ) -> Result<Value, ProvidersErrorType> {
+        let url = self.endpoint().to_string();
+        let host = match reqwest::Url::parse(&url) {
+            Ok(value) => match value.port() {
+                Some(port) => format!("{}:{}", value.host_str().unwrap_or_default(), port),
+                None => value.host_str().unwrap_or_default().to_string(),
+            },
+            Err(_) => {
+                error!("Invalid DNSPod endpoint: {}", url);
+                return Err(ProvidersErrorType::OtherError);
+            }
+        };
+        let payload = payload.to_string();
+        let timestamp = chrono::Utc::now().timestamp();
+        let authorization = tc3_authorization(&self.id, &self.token, &host, &payload, timestamp);
+
+        let headers = HeaderMap::from_iter(vec![
+            (
+                HeaderName::from_static("authorization"),
+                HeaderValue::from_str(&authorization).unwrap_or(HeaderValue::from_static("")),
+            ),
+            (
+                HeaderName::from_static("content-type"),
+                HeaderValue::from_static(TC3_CONTENT_TYPE),
+            ),
+            (
+                HeaderName::from_static("x-tc-action"),
+                HeaderValue::from_str(action).unwrap_or(HeaderValue::from_static("")),
+            ),
+            (
+                HeaderName::from_static("x-tc-timestamp"),
+                HeaderValue::from(timestamp),
+            ),
+            (
+                HeaderName::from_static("x-tc-version"),
+                HeaderValue::from_static(TENCENT_CLOUD_VERSION),
+            ),
+        ]);
+        let request = RequestStructure::new(
+            RequestMethod::POST,
+            url,
+            payload,
+            Some(headers),
+            None,
+            Some(USER_AGENT.to_string()),
+            None,
+        );
+        let (_, _, rsp_body) = match self.request_execute(&request).await {
+            Ok(value) => value,
+            Err(_) => return Err(ProvidersErrorType::NetworkError),
+        };
+        let rsp: Value = match serde_json::from_str(&rsp_body) {
+            Ok(value) => value,
+            Err(e) => {
+                error!("Failed to parse TencentCloud response: {}", e);
+                return Err(ProvidersErrorType::OtherError);
+            }
+        };
+        let rsp = rsp["Response"].clone();
+        match rsp["Error"]["Code"].as_str() {
+            None => Ok(rsp),
+            Some(code) => {
+                if code == "ResourceNotFound.NoDataOfRecord" {
+                    return Err(ProvidersErrorType::NoRecordFound);
+                }
+                error!(
+                    "TencentCloud {} failed with code {}: {}",
+                    action, code, rsp["Error"]["Message"]
+                );
+                if code.starts_with("AuthFailure") {
+                    Err(ProvidersErrorType::KeyError)
+                } else {
+                    Err(ProvidersErrorType::OtherError)
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl DDNSProviderTrait for Dnspod {
+    async fn update(&mut self, ip: &str) -> Result<(), String> {
+        let has_record = match self.has_record {
+            Some(has_record) => has_record,
+            None => self.get_ip_address().await.is_ok(),
+        };
+
+        if has_record {
+            match self.modify_record(&self.record_id, ip).await {
+                Ok(_) => {
+                    info!("Updated Record ID: {}", self.record_id);
+                }
+                Err(e) => {
+                    error!("Failed to update domain record: {}", e);
+                    self.has_record = Option::None;
+                    return Err(format!("Failed to update domain record: {}", e));
+                }
+            }
+        } else {
+            match self.create_record(ip).await {
+                Ok(record_id) => {
+                    self.record_id = record_id;
+                    self.has_record = Some(true);
+                    info!("Added Record ID: {}", self.record_id);
+                }
+                Err(e) => {
+                    error!("Failed to add domain record: {}", e);
+                    return Err(format!("Failed to add domain record: {}", e));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get_ip_address(&mut self) -> Result<String, ProvidersErrorType> {
+        let records = match self.query_records().await {
+            Ok(records) => records,
+            Err(e) => {
+                error!("Failed to query domain records: {}", e);
+                return Err(e);
+            }
+        };
+
+        if records.is_empty() {
+            self.has_record = Option::Some(false);
+            return Err(ProvidersErrorType::NoRecordFound);
+        } else if records.len() > 1 {
+            // delete mutiple records (retain the first one)
+            warn!(
+                "Found {} records for {}.{}, deleting duplicates",
+                records.len(),
+                self.sub_domain(),
+                self.domain
+            );
+            for (record_id, value) in records.iter().skip(1) {
+                if self.dry_run {
+                    info!(
+                        "dry run, would delete duplicate record {} ({})",
+                        record_id, value
+                    );
+                    continue;
+                }
+                if let Err(e) = self.delete_record(record_id).await {
+                    error!("Failed to delete subdomain records: {}", e);
+                    return Err(ProvidersErrorType::DeleteDomainRecordsError);
+                }
+            }
+        }
+
+        let (record_id, ip) = records[0].clone();
+        self.record_id = record_id;
+        self.has_record = Option::Some(true);
+        Ok(ip)
+    }
+
+    fn record_id(&self) -> Option<String> {
+        match self.has_record {
+            Some(true) => Some(self.record_id.clone()),
+            _ => None,
+        }
+    }
+
+    fn set_record_id(&mut self, record_id: &str) {
+        self.record_id = record_id.to_string();
+        self.has_record = Some(true);
+    }
+
+    fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    async fn get_record_set(&mut self) -> Result<Vec<RecordEntry>, ProvidersErrorType> {
+        let records = self.query_records().await?;
+        Ok(records
+            .iter()
+            .map(|(record_id, value)| RecordEntry::new(record_id, value))
+            .collect())
+    }
+
+    async fn add_set_record(&mut self, ip: &str) -> Result<(), String> {
+        match self.create_record(ip).await {
+            Ok(record_id) => {
+                info!("Added Record ID: {}", record_id);
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to add domain record: {}", e)),
+        }
+    }
+
+    async fn change_set_record(&mut self, record_id: &str, ip: &str) -> Result<(), String> {
+        match self.modify_record(record_id, ip).await {
+            Ok(_) => {
+                info!("Updated Record ID: {}", record_id);
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to update domain record: {}", e)),
+        }
+    }
+
+    async fn remove_set_record(&mut self, record_id: &str) -> Result<(), String> {
+        match self.delete_record(record_id).await {
+            Ok(_) => {
+                info!("Deleted Record ID: {}", record_id);
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to delete domain record: {}", e)),
+        }
+    }
+}
+
+const TC3_CONTENT_TYPE: &str = "application/json; charset=utf-8";
+
+/// Build the `Authorization` header for a TencentCloud API 3.0 POST request (TC3-HMAC-SHA256)
+pub fn tc3_authorization(
+    secret_id: &str,
+    secret_key: &str,
+    host: &str,
+    payload: &str,
+    timestamp: i64,
+) -> String {
+    let date = chrono::DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%d")
+        .to_string();
+    let signed_headers = "content-type;host";
+    let canonical_request = format!(
+        "POST\n/\n\ncontent-type:{}\nhost:{}\n\n{}\n{}",
+        TC3_CONTENT_TYPE,
+        host,
+        signed_headers,
+        hex_encode(&Sha256::digest(payload.as_bytes()))
+    );
+    let credential_scope = format!("{}/{}/tc3_request", date, TENCENT_CLOUD_SERVICE);
+    let string_to_sign = format!(
+        "TC3-HMAC-SHA256\n{}\n{}\n{}",
+        timestamp,
+        credential_scope,
+        hex_encode(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let secret_date = hmac_sha256(format!("TC3{}", secret_key).as_bytes(), date.as_bytes());
+    let secret_service = hmac_sha256(&secret_date, TENCENT_CLOUD_SERVICE.as_bytes());
+    let secret_signing = hmac_sha256(&secret_service, b"tc3_request");
+    let signature = hex_encode(&hmac_sha256(&secret_signing, string_to_sign.as_bytes()));
+    format!(
+        "TC3-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        secret_id, credential_scope, signed_headers, signature
+    )
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take key of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// DNSPod returns ids as numbers or strings depending on the endpoint
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
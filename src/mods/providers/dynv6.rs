@@ -1,4 +1,7 @@
-use super::{types::ProvidersErrorType, DDNSProviderTrait};
+use super::{
+    types::{ProvidersErrorType, RecordEntry},
+    DDNSProviderTrait,
+};
 use crate::mods::request::{RequestMethod, RequestStructure};
 use async_trait::async_trait;
 use log::{error, info};
@@ -19,6 +22,8 @@ pub struct Dynv6 {
     has_record: Option<bool>,
     #[serde(skip)]
     record_id: u64,
+    #[serde(skip)]
+    dry_run: bool,
 }
 
 // 辅助结构体，用于反序列化
@@ -54,6 +59,7 @@ impl<'de> Deserialize<'de> for Dynv6 {
             record_type: helper.record_type,
             client,
             has_record: None,
+            dry_run: false,
             record_id: 0,
         })
     }
@@ -68,6 +74,7 @@ impl Clone for Dynv6 {
             client: Dynv6Client::new(&self.token, self.zone_id),
             record_type: self.record_type.clone(),
             has_record: self.has_record,
+            dry_run: self.dry_run,
             record_id: self.record_id.clone(),
         }
     }
@@ -82,6 +89,7 @@ impl Dynv6 {
             client: Dynv6Client::new(token, zone_id),
             record_type: record_type.to_string(),
             has_record: Option::None,
+            dry_run: false,
             record_id: 0,
         }
     }
@@ -119,6 +127,7 @@ impl DDNSProviderTrait for Dynv6 {
                 }
                 Err(e) => {
                     error!("Failed to update domain record: {}", e);
+                    self.has_record = Option::None;
                     return Err("Failed to update domain record".to_string());
                 }
             }
@@ -151,6 +160,7 @@ impl DDNSProviderTrait for Dynv6 {
         match query_response {
             Ok(query_response) => {
                 if query_response.is_empty() {
+                    self.has_record = Option::Some(false);
                     return Err(ProvidersErrorType::NoRecordFound);
                 } else if query_response.len() > 1 {
                     // delete mutiple records (retain the first one)
@@ -160,6 +170,10 @@ impl DDNSProviderTrait for Dynv6 {
                         .map(|record| record.id.to_string())
                         .collect::<Vec<String>>();
                     for record_id in record_ids.iter() {
+                        if self.dry_run {
+                            info!("dry run, would delete duplicate record {}", record_id);
+                            continue;
+                        }
                         match self.delete_subdomain_records(record_id).await {
                             Ok(_) => {}
                             Err(e) => {
@@ -173,6 +187,7 @@ impl DDNSProviderTrait for Dynv6 {
                 let ip = query_response[0].data.clone();
                 let record_id = query_response[0].id;
                 self.record_id = record_id;
+                self.has_record = Option::Some(true);
                 Ok(ip)
             }
             Err(e) => {
@@ -181,6 +196,83 @@ impl DDNSProviderTrait for Dynv6 {
             }
         }
     }
+
+    fn record_id(&self) -> Option<String> {
+        match self.has_record {
+            Some(true) => Some(self.record_id.to_string()),
+            _ => None,
+        }
+    }
+
+    fn set_record_id(&mut self, record_id: &str) {
+        if let Ok(record_id) = record_id.parse::<u64>() {
+            self.record_id = record_id;
+            self.has_record = Some(true);
+        }
+    }
+
+    fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    async fn get_record_set(&mut self) -> Result<Vec<RecordEntry>, ProvidersErrorType> {
+        match self
+            .client
+            .query_subdomain_records(&self.rr, &self.record_type)
+            .await
+        {
+            Ok(records) => Ok(records
+                .iter()
+                .map(|record| RecordEntry::new(&record.id.to_string(), &record.data))
+                .collect()),
+            Err(e) => {
+                error!("Failed to get ip address: {}", e);
+                Err(ProvidersErrorType::QueryDomainRecordsError)
+            }
+        }
+    }
+
+    async fn add_set_record(&mut self, ip: &str) -> Result<(), String> {
+        match self
+            .client
+            .add_domain_record(&self.rr, &self.record_type, ip)
+            .await
+        {
+            Ok(record_id) => {
+                info!("Added Record ID: {}", record_id);
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to add domain record: {}", e)),
+        }
+    }
+
+    async fn change_set_record(&mut self, record_id: &str, ip: &str) -> Result<(), String> {
+        let record_id = match record_id.parse::<u64>() {
+            Ok(record_id) => record_id,
+            Err(_) => return Err(format!("Invalid record ID: {}", record_id)),
+        };
+        match self
+            .client
+            .update_domain_record(record_id, &self.rr, &self.record_type, ip)
+            .await
+        {
+            Ok(_) => {
+                info!("Updated Record ID: {}", record_id);
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to update domain record: {}", e)),
+        }
+    }
+
+    async fn remove_set_record(&mut self, record_id: &str) -> Result<(), String> {
+        match self.client.delete_domain_record(record_id).await {
+            Ok(_) => {
+                info!("Deleted Record ID: {}", record_id);
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to delete domain record: {}", e)),
+        }
+    }
 }
 
 struct Dynv6Client {
@@ -0,0 +1,138 @@
+use super::{types::ProvidersErrorType, DDNSProviderTrait};
+use crate::mods::{
+    dns::DnsLookup,
+    request::{url_encode, RequestMethod, RequestStructure},
+    types::AddressVersion,
+};
+use async_trait::async_trait;
+use log::{error, info, trace};
+use serde::{Deserialize, Serialize};
+
+const NAMECHEAP_ENDPOINT: &str = "https://dynamicdns.park-your-domain.com/update";
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Namecheap {
+    pub domain: String,
+    pub rr: String,       // host, `@` for the apex
+    pub password: String, // Dynamic DNS password from the Advanced DNS page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>, // override for testing
+    #[serde(skip)]
+    pub nameservers: Vec<String>, // override for testing, the zone's authoritative servers otherwise
+    #[serde(skip)]
+    last_ip: Option<String>, // Namecheap has no read API, remember what we pushed
+}
+
+impl Namecheap {
+    pub fn new(domain: &str, rr: &str, password: &str) -> Self {
+        Self {
+            domain: domain.to_string(),
+            rr: rr.to_string(),
+            password: password.to_string(),
+            endpoint: Option::None,
+            nameservers: Vec::new(),
+            last_ip: Option::None,
+        }
+    }
+
+    fn host(&self) -> &str {
+        match self.rr.as_str() {
+            "" => "@",
+            rr => rr,
+        }
+    }
+
+    fn full_name(&self) -> String {
+        match self.host() {
+            "@" => self.domain.clone(),
+            rr => format!("{}.{}", rr, self.domain),
+        }
+    }
+}
+
+#[async_trait]
+impl DDNSProviderTrait for Namecheap {
+    async fn update(&mut self, ip: &str) -> Result<(), String> {
+        let url = format!(
+            "{}?host={}&domain={}&password={}&ip={}",
+            self.endpoint.as_deref().unwrap_or(NAMECHEAP_ENDPOINT),
+            url_encode(self.host()),
+            url_encode(&self.domain),
+            url_encode(&self.password),
+            url_encode(ip)
+        );
+        let request = RequestStructure::new_default(RequestMethod::GET, url, String::new());
+        let rsp_body = match self.request_execute(&request).await {
+            Ok((_, _, rsp_body)) => rsp_body,
+            Err(_) => {
+                error!("Failed to update domain record: network error");
+                return Err("Failed to update domain record: NetworkError".to_string());
+            }
+        };
+        match parse_update_response(&rsp_body) {
+            Ok(updated_ip) => {
+                info!("Updated {} to {}", self.full_name(), updated_ip);
+                self.last_ip = Some(ip.to_string());
+                Ok(())
+            }
+            Err(e) => {
+                error!("Failed to update domain record: {}", e);
+                self.last_ip = Option::None;
+                Err(format!("Failed to update domain record: {}", e))
+            }
+        }
+    }
+
+    async fn get_ip_address(&mut self) -> Result<String, ProvidersErrorType> {
+        if let Some(ip) = self.last_ip.clone() {
+            return Ok(ip);
+        }
+
+        // fall back to the authoritative servers, a resolver may answer from its cache;
+        // Namecheap dynamic DNS only manages A records
+        let lookup = DnsLookup::new(self.nameservers.clone());
+        match lookup.lookup(&self.full_name(), &AddressVersion::V4).await {
+            Ok(ips) => match ips.into_iter().next() {
+                Some(ip) => Ok(ip),
+                None => Err(ProvidersErrorType::NoRecordFound),
+            },
+            Err(e) => {
+                trace!("Failed to look up {}: {}", self.full_name(), e);
+                Err(ProvidersErrorType::NetworkError)
+            }
+        }
+    }
+}
+
+/// Parse the `interface-response` XML document returned by the update endpoint,
+/// returning the IP Namecheap recorded on success.
+pub fn parse_update_response(body: &str) -> Result<String, ProvidersErrorType> {
+    let err_count = match xml_tag(body, "ErrCount").and_then(|v| v.parse::<u32>().ok()) {
+        Some(value) => value,
+        None => {
+            error!("Unexpected Namecheap response: {}", body);
+            return Err(ProvidersErrorType::OtherError);
+        }
+    };
+    if err_count > 0 {
+        let message = xml_tag(body, "Err1").unwrap_or_default();
+        error!("Namecheap error: {}", message);
+        let message = message.to_lowercase();
+        return Err(if message.contains("password") {
+            ProvidersErrorType::KeyError
+        } else if message.contains("not found") || message.contains("no records") {
+            ProvidersErrorType::NoRecordFound
+        } else {
+            ProvidersErrorType::OtherError
+        });
+    }
+    Ok(xml_tag(body, "IP").unwrap_or_default())
+}
+
+fn xml_tag(body: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = body.find(&open)? + open.len();
+    let end = body[start..].find(&close)? + start;
+    Some(body[start..end].trim().to_string())
+}
@@ -0,0 +1,488 @@
+use super::{types::ProvidersErrorType, DDNSProviderTrait};
+use crate::mods::dns::{
+    build_query, encode_name, exchange, message_id, parse_message, parse_nameserver, Message,
+    RecordData, CLASS_ANY, CLASS_IN, RCODE_NXDOMAIN, TYPE_A, TYPE_AAAA, TYPE_SOA,
+};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use log::{error, info, trace};
+use serde::{Deserialize, Serialize};
+use sha2::{Sha256, Sha512};
+use std::{fmt::Display, net::IpAddr, time::Duration};
+
+const TYPE_TSIG: u16 = 250;
+const OPCODE_UPDATE: u16 = 5;
+const FUDGE: u16 = 300; // seconds of clock skew allowed between us and the server
+const RCODE_NOTAUTH: u8 = 9;
+
+/// Standard dynamic DNS (RFC 2136) against our own primary nameserver (BIND, Knot,
+/// PowerDNS...), authenticated with TSIG
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Rfc2136 {
+    pub server: String, // primary nameserver, "ip" or "ip:port"
+    pub domain: String, // the zone, as configured on the server
+    pub rr: String,     // relative to the zone, `@` for the apex
+    pub ttl: Option<u64>,
+    pub record_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tsig: Option<TsigKey>, // unsigned messages for servers allowing updates by address
+    #[serde(default)]
+    pub tcp: bool, // UDP first (TCP for truncated answers) otherwise
+    #[serde(default = "default_timeout")]
+    pub timeout: u64, // seconds
+}
+
+/// A TSIG key as written in the server config
+#[derive(Deserialize, Serialize, Clone)]
+pub struct TsigKey {
+    pub name: String,
+    #[serde(default)]
+    pub algorithm: TsigAlgorithm,
+    pub secret: String, // base64
+}
+
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq)]
+pub enum TsigAlgorithm {
+    #[default]
+    #[serde(alias = "hmac-sha256")]
+    HmacSha256,
+    #[serde(alias = "hmac-sha512")]
+    HmacSha512,
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+/// Why an exchange with the server failed
+#[derive(Debug)]
+pub enum SendError {
+    Network(String), // no usable answer from the server
+    Tsig(String),    // signing the request or checking the signature of the answer
+}
+
+impl Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Network(e) | SendError::Tsig(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Rfc2136 {
+    pub fn new(server: &str, domain: &str, rr: &str, record_type: &str) -> Self {
+        Self {
+            server: server.to_string(),
+            domain: domain.to_string(),
+            rr: rr.to_string(),
+            ttl: None,
+            record_type: record_type.to_string(),
+            tsig: None,
+            tcp: false,
+            timeout: default_timeout(),
+        }
+    }
+
+    pub fn full_name(&self) -> String {
+        let zone = self.domain.trim_end_matches('.');
+        match self.rr.as_str() {
+            "@" | "" => zone.to_string(),
+            rr => format!("{}.{}", rr, zone),
+        }
+    }
+
+    fn rtype(&self) -> Result<u16, String> {
+        match self.record_type.as_str() {
+            "A" => Ok(TYPE_A),
+            "AAAA" => Ok(TYPE_AAAA),
+            other => Err(format!("record type {:?} is not supported", other)),
+        }
+    }
+
+    /// Replace the whole RRset of the record with `ip`: delete it, then add the new value
+    pub fn update_message(&self, id: u16, ip: &str) -> Result<Vec<u8>, String> {
+        let rtype = self.rtype()?;
+        let rdata = match (ip.parse::<IpAddr>(), rtype) {
+            (Ok(IpAddr::V4(ip)), TYPE_A) => ip.octets().to_vec(),
+            (Ok(IpAddr::V6(ip)), TYPE_AAAA) => ip.octets().to_vec(),
+            _ => {
+                return Err(format!(
+                    "{} is not a value for an {} record",
+                    ip, self.record_type
+                ))
+            }
+        };
+        let name = encode_name(&self.full_name())?;
+        let mut message = id.to_be_bytes().to_vec();
+        message.extend_from_slice(&(OPCODE_UPDATE << 11).to_be_bytes());
+        message.extend_from_slice(&[0, 1, 0, 0, 0, 2, 0, 0]); // zone, 2 updates
+        message.append(&mut encode_name(&self.domain)?);
+        message.extend_from_slice(&TYPE_SOA.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+        // delete the RRset: class ANY, TTL 0, no data
+        message.extend_from_slice(&name);
+        message.extend_from_slice(&rtype.to_be_bytes());
+        message.extend_from_slice(&CLASS_ANY.to_be_bytes());
+        message.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        // add the new value
+        let ttl = self.ttl.unwrap_or(300) as u32;
+        message.extend_from_slice(&name);
+        message.extend_from_slice(&rtype.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+        message.extend_from_slice(&ttl.to_be_bytes());
+        message.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        message.extend_from_slice(&rdata);
+        Ok(message)
+    }
+
+    /// Sign `message` if there is a key, send it to the server and check the signature
+    /// of the answer
+    async fn send(&self, message: Vec<u8>) -> Result<Message, SendError> {
+        let server = parse_nameserver(&self.server).map_err(SendError::Network)?;
+        let wait = Duration::from_secs(self.timeout);
+        let ask = |message: Vec<u8>| async move {
+            let response = exchange(server, &message, self.tcp, wait).await;
+            let response = response.map_err(SendError::Network)?;
+            let parsed = parse_message(&response).map_err(SendError::Network)?;
+            Ok::<_, SendError>((response, parsed))
+        };
+        let key = match &self.tsig {
+            Some(key) => key,
+            None => return Ok(ask(message).await?.1),
+        };
+        let now = chrono::Utc::now().timestamp() as u64;
+        let (signed, mac) = tsig_sign(&message, key, now, None).map_err(SendError::Tsig)?;
+        let (response, parsed) = ask(signed).await?;
+        match parsed.additional.last() {
+            Some(record) if record.rtype == TYPE_TSIG => {
+                tsig_verify(&response, key, Some(&mac)).map_err(SendError::Tsig)?;
+                Ok(parsed)
+            }
+            // errors about the key itself come back unsigned
+            _ if parsed.rcode != 0 => Ok(parsed),
+            _ => Err(SendError::Tsig("the response is not signed".to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl DDNSProviderTrait for Rfc2136 {
+    async fn update(&mut self, ip: &str) -> Result<(), String> {
+        let message = self.update_message(message_id(), ip)?;
+        match self.send(message).await {
+            Ok(response) if response.rcode == 0 => {
+                info!("Updated {} to {}", self.full_name(), ip);
+                Ok(())
+            }
+            Ok(response) => {
+                error!(
+                    "Failed to update domain record: {}",
+                    rcode_name(response.rcode)
+                );
+                Err(format!(
+                    "Failed to update domain record: {}",
+                    rcode_name(response.rcode)
+                ))
+            }
+            Err(e) => {
+                error!("Failed to update domain record: {}", e);
+                Err(format!("Failed to update domain record: {}", e))
+            }
+        }
+    }
+
+    async fn get_ip_address(&mut self) -> Result<String, ProvidersErrorType> {
+        let rtype = match self.rtype() {
+            Ok(rtype) => rtype,
+            Err(e) => {
+                error!("{}", e);
+                return Err(ProvidersErrorType::OtherError);
+            }
+        };
+        let message = match build_query(message_id(), &self.full_name(), rtype, false) {
+            Ok(message) => message,
+            Err(e) => {
+                error!("{}", e);
+                return Err(ProvidersErrorType::OtherError);
+            }
+        };
+        let response = match self.send(message).await {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to query domain records: {}", e);
+                return match e {
+                    SendError::Tsig(_) => Err(ProvidersErrorType::KeyError),
+                    SendError::Network(_) => Err(ProvidersErrorType::NetworkError),
+                };
+            }
+        };
+        match response.rcode {
+            0 => {}
+            RCODE_NXDOMAIN => return Err(ProvidersErrorType::NoRecordFound),
+            RCODE_NOTAUTH => return Err(ProvidersErrorType::KeyError),
+            rcode => {
+                error!("Failed to query domain records: {}", rcode_name(rcode));
+                return Err(ProvidersErrorType::QueryDomainRecordsError);
+            }
+        }
+        let values = response
+            .answers
+            .iter()
+            .filter_map(|record| match record.data {
+                RecordData::A(ip) if rtype == TYPE_A => Some(ip.to_string()),
+                RecordData::Aaaa(ip) if rtype == TYPE_AAAA => Some(ip.to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        trace!("{} has {:?}", self.full_name(), values);
+        // several values never equal the wanted one, so the update replaces them all
+        match values.is_empty() {
+            true => Err(ProvidersErrorType::NoRecordFound),
+            false => Ok(values.join(",")),
+        }
+    }
+}
+
+impl TsigAlgorithm {
+    fn name(&self) -> &'static str {
+        match self {
+            TsigAlgorithm::HmacSha256 => "hmac-sha256",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    fn sign(&self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            TsigAlgorithm::HmacSha256 => {
+                let mut mac =
+                    Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take key of any size");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            TsigAlgorithm::HmacSha512 => {
+                let mut mac =
+                    Hmac::<Sha512>::new_from_slice(key).expect("HMAC can take key of any size");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+
+    /// Constant time comparison
+    fn verify(&self, key: &[u8], data: &[u8], expected: &[u8]) -> bool {
+        match self {
+            TsigAlgorithm::HmacSha256 => {
+                let mut mac =
+                    Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take key of any size");
+                mac.update(data);
+                mac.verify_slice(expected).is_ok()
+            }
+            TsigAlgorithm::HmacSha512 => {
+                let mut mac =
+                    Hmac::<Sha512>::new_from_slice(key).expect("HMAC can take key of any size");
+                mac.update(data);
+                mac.verify_slice(expected).is_ok()
+            }
+        }
+    }
+}
+
+impl TsigKey {
+    pub fn new(name: &str, algorithm: TsigAlgorithm, secret: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            algorithm,
+            secret: secret.to_string(),
+        }
+    }
+
+    pub fn secret(&self) -> Result<Vec<u8>, String> {
+        match STANDARD.decode(self.secret.trim()) {
+            Ok(secret) if !secret.is_empty() => Ok(secret),
+            Ok(_) => Err("the TSIG secret is empty".to_string()),
+            Err(e) => Err(format!("the TSIG secret is not base64: {}", e)),
+        }
+    }
+}
+
+/// The TSIG variables covered by the MAC after the message (RFC 8945 4.3.3)
+fn tsig_variables(
+    key_name: &[u8],
+    algorithm: &[u8],
+    time: u64,
+    fudge: u16,
+    error: u16,
+    other: &[u8],
+) -> Vec<u8> {
+    let mut variables = key_name.to_vec();
+    variables.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    variables.extend_from_slice(&[0, 0, 0, 0]); // TTL
+    variables.extend_from_slice(algorithm);
+    variables.extend_from_slice(&time.to_be_bytes()[2..]); // 48 bits
+    variables.extend_from_slice(&fudge.to_be_bytes());
+    variables.extend_from_slice(&error.to_be_bytes());
+    variables.extend_from_slice(&(other.len() as u16).to_be_bytes());
+    variables.extend_from_slice(other);
+    variables
+}
+
+/// Append a TSIG record to `message`, returns the signed message and its MAC.
+///
+/// A response is signed over the MAC of the request it answers (`request_mac`).
+pub fn tsig_sign(
+    message: &[u8],
+    key: &TsigKey,
+    time: u64,
+    request_mac: Option<&[u8]>,
+) -> Result<(Vec<u8>, Vec<u8>), String> {
+    if message.len() < 12 {
+        return Err("message too short to sign".to_string());
+    }
+    let secret = key.secret()?;
+    let key_name = encode_name(&key.name.to_lowercase())?;
+    let algorithm = encode_name(key.algorithm.name())?;
+
+    let mut data = Vec::new();
+    if let Some(request_mac) = request_mac {
+        data.extend_from_slice(&(request_mac.len() as u16).to_be_bytes());
+        data.extend_from_slice(request_mac);
+    }
+    data.extend_from_slice(message);
+    data.append(&mut tsig_variables(
+        &key_name,
+        &algorithm,
+        time,
+        FUDGE,
+        0,
+        &[],
+    ));
+    let mac = key.algorithm.sign(&secret, &data);
+
+    let mut rdata = algorithm;
+    rdata.extend_from_slice(&time.to_be_bytes()[2..]);
+    rdata.extend_from_slice(&FUDGE.to_be_bytes());
+    rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+    rdata.extend_from_slice(&mac);
+    rdata.extend_from_slice(&message[..2]); // original ID
+    rdata.extend_from_slice(&[0, 0, 0, 0]); // no error, no other data
+
+    let mut signed = message.to_vec();
+    let additional = u16::from_be_bytes([message[10], message[11]]) + 1;
+    signed[10..12].copy_from_slice(&additional.to_be_bytes());
+    signed.extend_from_slice(&key_name);
+    signed.extend_from_slice(&TYPE_TSIG.to_be_bytes());
+    signed.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    signed.extend_from_slice(&[0, 0, 0, 0]);
+    signed.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    signed.extend_from_slice(&rdata);
+    Ok((signed, mac))
+}
+
+/// Check the TSIG record closing `message`, returns its MAC.
+///
+/// `request_mac` is the MAC of our request when `message` is the response to it.
+pub fn tsig_verify(
+    message: &[u8],
+    key: &TsigKey,
+    request_mac: Option<&[u8]>,
+) -> Result<Vec<u8>, String> {
+    let parsed = parse_message(message)?;
+    let (record, rdata) = match parsed.additional.last() {
+        Some(record) if record.rtype == TYPE_TSIG => match &record.data {
+            RecordData::Other(rdata) => (record, rdata),
+            _ => return Err("invalid TSIG record".to_string()),
+        },
+        _ => return Err("the message is not signed".to_string()),
+    };
+    if !record
+        .name
+        .eq_ignore_ascii_case(key.name.trim_end_matches('.'))
+    {
+        return Err(format!("signed with the unknown key {:?}", record.name));
+    }
+
+    // algorithm name (never compressed), time, fudge, MAC, original ID, error, other data
+    let mut offset = 0;
+    while let Some(len) = rdata.get(offset).filter(|len| **len != 0) {
+        offset += 1 + *len as usize;
+    }
+    let algorithm = match rdata.get(..offset + 1) {
+        Some(algorithm) => algorithm.to_ascii_lowercase(),
+        None => return Err("truncated TSIG record".to_string()),
+    };
+    let field = |start: usize, len: usize| match rdata.get(start..start + len) {
+        Some(field) => Ok(field),
+        None => Err("truncated TSIG record".to_string()),
+    };
+    let number = |bytes: &[u8]| bytes.iter().fold(0u64, |n, byte| n << 8 | *byte as u64);
+    let mut offset = offset + 1;
+    let time = number(field(offset, 6)?);
+    let fudge = number(field(offset + 6, 2)?) as u16;
+    let mac_len = number(field(offset + 8, 2)?) as usize;
+    let mac = field(offset + 10, mac_len)?.to_vec();
+    offset += 10 + mac_len;
+    let original_id = field(offset, 2)?.to_vec();
+    let error = number(field(offset + 2, 2)?) as u16;
+    let other_len = number(field(offset + 4, 2)?) as usize;
+    let other = field(offset + 6, other_len)?;
+
+    if algorithm != encode_name(key.algorithm.name())? {
+        return Err("signed with another algorithm".to_string());
+    }
+    if error != 0 {
+        return Err(format!("TSIG error {}", tsig_error_name(error)));
+    }
+    // the MAC covers the message as it was before the TSIG record was added
+    let mut unsigned = message[..record.offset].to_vec();
+    unsigned[..2].copy_from_slice(&original_id);
+    let additional = u16::from_be_bytes([message[10], message[11]]) - 1;
+    unsigned[10..12].copy_from_slice(&additional.to_be_bytes());
+
+    let mut data = Vec::new();
+    if let Some(request_mac) = request_mac {
+        data.extend_from_slice(&(request_mac.len() as u16).to_be_bytes());
+        data.extend_from_slice(request_mac);
+    }
+    data.append(&mut unsigned);
+    data.append(&mut tsig_variables(
+        &encode_name(&record.name.to_lowercase())?,
+        &algorithm,
+        time,
+        fudge,
+        error,
+        other,
+    ));
+    if !key.algorithm.verify(&key.secret()?, &data, &mac) {
+        return Err("TSIG signature mismatch (BADSIG)".to_string());
+    }
+    let now = chrono::Utc::now().timestamp() as u64;
+    if now.abs_diff(time) > fudge as u64 {
+        return Err("TSIG time outside the allowed skew (BADTIME)".to_string());
+    }
+    Ok(mac)
+}
+
+fn rcode_name(rcode: u8) -> String {
+    match rcode {
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        8 => "NXRRSET".to_string(),
+        9 => "NOTAUTH".to_string(),
+        10 => "NOTZONE".to_string(),
+        rcode => format!("rcode {}", rcode),
+    }
+}
+
+fn tsig_error_name(error: u16) -> String {
+    match error {
+        16 => "BADSIG".to_string(),
+        17 => "BADKEY".to_string(),
+        18 => "BADTIME".to_string(),
+        22 => "BADTRUNC".to_string(),
+        error => error.to_string(),
+    }
+}
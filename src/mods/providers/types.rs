@@ -10,6 +10,7 @@ pub enum ProvidersErrorType {
     NotInitialized,
     KeyError,
     NetworkError,
+    NotSupported,
     OtherError,
 }
 
@@ -24,6 +25,7 @@ impl Display for ProvidersErrorType {
             ProvidersErrorType::NotInitialized => write!(f, "NotInitialized"),
             ProvidersErrorType::KeyError => write!(f, "KeyError"),
             ProvidersErrorType::NetworkError => write!(f, "NetworkError"),
+            ProvidersErrorType::NotSupported => write!(f, "NotSupported"),
             ProvidersErrorType::OtherError => write!(f, "OtherError"),
             ProvidersErrorType::DeleteSubdomainRecordsError => {
                 write!(f, "DeleteSubdomainRecordsError")
@@ -32,3 +34,63 @@ impl Display for ProvidersErrorType {
         }
     }
 }
+
+/// One record of a record set
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordEntry {
+    pub id: String,
+    pub value: String,
+}
+
+impl RecordEntry {
+    pub fn new(id: &str, value: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
+/// Changes that turn the records of a site into the wanted set of values
+#[derive(Debug, Default, PartialEq)]
+pub struct RecordSetPlan {
+    pub change: Vec<(RecordEntry, String)>, // stale record reused for a missing value
+    pub add: Vec<String>,
+    pub remove: Vec<RecordEntry>,
+}
+
+impl RecordSetPlan {
+    pub fn new(records: &[RecordEntry], wanted: &[String]) -> Self {
+        let mut kept: Vec<&str> = Vec::new();
+        let mut stale = Vec::new();
+        for record in records.iter() {
+            // a value published twice is kept once
+            if wanted.contains(&record.value) && !kept.contains(&record.value.as_str()) {
+                kept.push(&record.value);
+            } else {
+                stale.push(record.clone());
+            }
+        }
+        let mut missing = Vec::new();
+        for value in wanted.iter() {
+            if !kept.contains(&value.as_str()) && !missing.contains(value) {
+                missing.push(value.clone());
+            }
+        }
+
+        let mut plan = RecordSetPlan::default();
+        let mut stale = stale.into_iter();
+        for value in missing {
+            match stale.next() {
+                Some(record) => plan.change.push((record, value)),
+                None => plan.add.push(value),
+            }
+        }
+        plan.remove = stale.collect();
+        plan
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.change.is_empty() && self.add.is_empty() && self.remove.is_empty()
+    }
+}
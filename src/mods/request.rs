@@ -272,6 +272,75 @@ async fn async_patchwebpage(
     Ok((status, rsp_headers, rsp_body))
 }
 
+async fn async_putwebpage(
+    url: &str,
+    content: &str,
+    proxy_open: bool,
+    proxy_url: &str,
+    user_agent: &str,
+    cookie: &str,
+    headers: Option<HeaderMap>,
+) -> Result<(u16, HashMap<String, String>, String), ()> {
+    let mut client_builder = reqwest::Client::builder();
+    if proxy_open && !proxy_url.is_empty() {
+        client_builder = client_builder.proxy(if proxy_url.contains("://") {
+            if let Ok(value) = reqwest::Proxy::all(proxy_url) {
+                value
+            } else {
+                return Err(());
+            }
+        } else {
+            if let Ok(value) = reqwest::Proxy::all(format!("socks5://{}", proxy_url)) {
+                value
+            } else {
+                return Err(());
+            }
+        });
+    }
+    let mut client = if let Ok(value) = client_builder
+        .brotli(true)
+        .gzip(true)
+        .deflate(true)
+        .timeout(Duration::from_secs(20))
+        .user_agent(user_agent)
+        .build()
+    {
+        value
+    } else {
+        return Err(());
+    }
+    .put(url)
+    .body(content.to_owned());
+    if let Some(value) = headers {
+        client = client
+            .headers(value)
+            .header("cookie", cookie)
+            .header("Accept-Encoding", "gzip, deflate, br");
+    }
+    let rsp_raw_data = if let Ok(value) = client.send().await {
+        value
+    } else {
+        return Err(());
+    };
+    // match rsp_raw_data.status().as_u16() {
+    //     404 | 429 => return Err(()),
+    //     _ => (),
+    // }
+    let status = rsp_raw_data.status().as_u16();
+
+    let rsp_headers: HashMap<String, String> = rsp_raw_data
+        .headers()
+        .iter()
+        .map(|(k, v)| (k.as_str().to_owned(), v.to_str().unwrap_or("").to_owned()))
+        .collect();
+    let rsp_body = if let Ok(value) = rsp_raw_data.text().await {
+        value
+    } else {
+        return Err(());
+    };
+    Ok((status, rsp_headers, rsp_body))
+}
+
 #[derive(Debug)]
 pub struct RequestStructure {
     pub mathod: RequestMethod,
@@ -368,6 +437,18 @@ impl RequestStructure {
                 )
                 .await
             }
+            RequestMethod::PUT => {
+                async_putwebpage(
+                    &self.url,
+                    &self.content,
+                    self.proxy.is_some(),
+                    &self.proxy.clone().unwrap_or_default(),
+                    &self.user_agent.clone().unwrap_or_default(),
+                    &self.cookie.clone().unwrap_or_default(),
+                    self.headers.clone(),
+                )
+                .await
+            }
             RequestMethod::DELETE => {
                 async_deletewebpage(
                     &self.url,
@@ -388,16 +469,45 @@ pub enum RequestMethod {
     GET,
     POST,
     PATCH,
+    PUT,
     DELETE,
 }
 
+impl RequestMethod {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "GET" => Some(RequestMethod::GET),
+            "POST" => Some(RequestMethod::POST),
+            "PATCH" => Some(RequestMethod::PATCH),
+            "PUT" => Some(RequestMethod::PUT),
+            "DELETE" => Some(RequestMethod::DELETE),
+            _ => None,
+        }
+    }
+}
+
 impl Clone for RequestMethod {
     fn clone(&self) -> Self {
         match self {
             RequestMethod::GET => RequestMethod::GET,
             RequestMethod::POST => RequestMethod::POST,
             RequestMethod::PATCH => RequestMethod::PATCH,
+            RequestMethod::PUT => RequestMethod::PUT,
             RequestMethod::DELETE => RequestMethod::DELETE,
         }
     }
 }
+
+/// Percent-encode a value for use in a query string or `application/x-www-form-urlencoded` body
+pub fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
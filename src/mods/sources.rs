@@ -0,0 +1,279 @@
+pub mod command;
+pub mod file;
+pub mod gateway;
+pub mod http;
+pub mod prefix;
+pub mod stun;
+
+use log::{debug, trace};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    net::IpAddr,
+};
+
+use self::{
+    command::CommandSource, file::FileSource, gateway::GatewaySource, http::HttpSource,
+    prefix::PrefixSource, stun::StunSource,
+};
+use super::{
+    interfaces::{
+        get_interface_ips, get_interfaces, AddressType, Cidr, IPAddress, InterfaceMatcher,
+    },
+    types::{AddressVersion, SiteConfig},
+};
+
+/// Where a site gets the address it publishes
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub enum IpSource {
+    #[default]
+    Interface, // `interface`, `address_type` and `index` of the site
+    Http(HttpSource),
+    Prefix(PrefixSource), // prefix of an interface address picked like `Interface`, plus a host suffix
+    Stun(StunSource),
+    Gateway(GatewaySource), // WAN address reported by the router, IPv4 only
+    Command(CommandSource), // first suitable address printed by a program
+    File(FileSource),       // first suitable address in a file
+}
+
+impl IpSource {
+    /// Whether the addresses come from the site's `interface`
+    pub fn reads_interface(&self) -> bool {
+        matches!(self, IpSource::Interface | IpSource::Prefix(_))
+    }
+
+    /// Rewrite addresses detected for a site with this source, for a target with a
+    /// different `host_suffix`: a no-op for every other source
+    pub fn apply(&self, ips: Vec<String>) -> Result<Vec<String>, String> {
+        match self {
+            IpSource::Prefix(source) => ips.iter().map(|ip| source.compose(ip)).collect(),
+            _ => Ok(ips),
+        }
+    }
+}
+
+/// Resolve the address a site should publish according to its `source`
+pub async fn resolve_address(site: &SiteConfig) -> Result<String, String> {
+    match &site.source {
+        IpSource::Interface => interface_address(site).await,
+        IpSource::Http(source) => {
+            check_cidrs(site, source.fetch_address(&site.address_version).await?)
+        }
+        IpSource::Prefix(source) => source.compose(&interface_address(site).await?),
+        IpSource::Stun(source) => {
+            check_cidrs(site, source.fetch_address(&site.address_version).await?)
+        }
+        IpSource::Gateway(source) => gateway_address(site, source).await,
+        IpSource::Command(source) => select_address(site, source.fetch_addresses().await?),
+        IpSource::File(source) => select_address(site, source.fetch_addresses().await?),
+    }
+}
+
+/// Resolve every address a site publishes: `count` addresses starting at `index` (0 means all)
+pub async fn resolve_addresses(site: &SiteConfig) -> Result<Vec<String>, String> {
+    let ips = match &site.source {
+        IpSource::Interface => interface_addresses(site).await?,
+        IpSource::Http(source) => vec![check_cidrs(
+            site,
+            source.fetch_address(&site.address_version).await?,
+        )?],
+        IpSource::Stun(source) => vec![check_cidrs(
+            site,
+            source.fetch_address(&site.address_version).await?,
+        )?],
+        IpSource::Gateway(source) => vec![gateway_address(site, source).await?],
+        IpSource::Command(source) => vec![select_address(site, source.fetch_addresses().await?)?],
+        IpSource::File(source) => vec![select_address(site, source.fetch_addresses().await?)?],
+        IpSource::Prefix(source) => {
+            let ips = interface_addresses(site).await?;
+            ips.iter()
+                .map(|ip| source.compose(ip))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+    let ips = match site.count {
+        0 => ips.into_iter().skip(site.index).collect::<Vec<_>>(),
+        count => ips.into_iter().skip(site.index).take(count).collect(),
+    };
+    if ips.is_empty() {
+        return Err(format!(
+            "no {} address at index {} on interface {}",
+            site.address_version.to_string(),
+            site.index,
+            site.interface
+        ));
+    }
+    Ok(ips)
+}
+
+/// The router may itself be behind a carrier-grade NAT: its address must still be of
+/// the site's `address_type`
+async fn gateway_address(site: &SiteConfig, source: &GatewaySource) -> Result<String, String> {
+    let ip = validate_address(&source.fetch_address().await?, &site.address_version)?;
+    let address_type = AddressType::classify(&ip);
+    if address_type != site.address_type {
+        return Err(format!(
+            "the gateway reported {}, a {:?} address",
+            ip, address_type
+        ));
+    }
+    check_cidrs(site, ip.to_string())
+}
+
+/// The first candidate of the site's `address_version` and `address_type` that passes
+/// `include` and `exclude`, the same checks interface addresses go through
+fn select_address(site: &SiteConfig, candidates: Vec<String>) -> Result<String, String> {
+    for candidate in candidates.iter() {
+        let checked = validate_address(candidate, &site.address_version).and_then(|ip| {
+            match AddressType::classify(&ip) {
+                address_type if address_type == site.address_type => {
+                    check_cidrs(site, ip.to_string())
+                }
+                address_type => Err(format!("{} is a {:?} address", ip, address_type)),
+            }
+        });
+        match checked {
+            Ok(ip) => return Ok(ip),
+            Err(e) => debug!("skipping {}: {}", candidate, e),
+        }
+    }
+    Err(format!(
+        "no {} {:?} address among {:?}",
+        site.address_version.to_string(),
+        site.address_type,
+        candidates
+    ))
+}
+
+async fn interface_address(site: &SiteConfig) -> Result<String, String> {
+    match interface_addresses(site).await?.get(site.index) {
+        Some(ip) => Ok(ip.clone()),
+        None => Err(format!(
+            "no {} address at index {} on interface {}",
+            site.address_version.to_string(),
+            site.index,
+            site.interface
+        )),
+    }
+}
+
+/// Addresses of the site's interface matching its `address_version`, `address_type`,
+/// `include` and `exclude`, ordered by its `address_policy`
+async fn interface_addresses(site: &SiteConfig) -> Result<Vec<String>, String> {
+    let interfaces = match get_interfaces().await {
+        Ok(interfaces) => interfaces,
+        Err(_) => return Err("failed to get interfaces".to_string()),
+    };
+
+    trace!("interfaces: {:?}", interfaces);
+
+    let names =
+        InterfaceMatcher::parse(&site.interface)?.find(&interfaces, &site.address_version)?;
+    trace!("matching interfaces: {:?}", names);
+    let mut ips = Vec::new();
+    for name in names.iter() {
+        ips.append(&mut get_interface_ips(&interfaces, name).await);
+    }
+    let ips = ips
+        .into_iter()
+        .filter(|ip| match ip {
+            IPAddress::V4(_ip, address_type) => {
+                site.address_version == AddressVersion::V4 && *address_type == site.address_type
+            }
+            IPAddress::V6(_ip, address_type, _details) => {
+                site.address_version == AddressVersion::V6 && *address_type == site.address_type
+            }
+        })
+        .filter(|ip| check_cidrs(site, ip.address().to_string()).is_ok())
+        .collect::<Vec<_>>();
+    let ips = match &site.address_policy {
+        Some(policy) => policy.select(ips),
+        None => ips,
+    };
+    let ips = ips
+        .iter()
+        .map(|ip| ip.address().to_string())
+        .collect::<Vec<_>>();
+
+    trace!("ips: {:?}", ips);
+    Ok(ips)
+}
+
+/// Reject `ip` if it is in one of the `exclude` CIDRs of the site, or outside all of its
+/// `include` CIDRs of the same version
+pub fn check_cidrs(site: &SiteConfig, ip: String) -> Result<String, String> {
+    let address = match ip.parse::<IpAddr>() {
+        Ok(address) => address,
+        Err(_) => return Err(format!("{:?} is not an IP address", ip)),
+    };
+    let parse = |cidrs: &[String]| {
+        cidrs
+            .iter()
+            .filter_map(|cidr| Cidr::parse(cidr).ok())
+            .filter(|cidr| cidr.address.is_ipv4() == address.is_ipv4())
+            .collect::<Vec<_>>()
+    };
+    if let Some(cidr) = parse(&site.exclude)
+        .iter()
+        .find(|cidr| cidr.contains(&address))
+    {
+        return Err(format!(
+            "{} is excluded by {}/{}",
+            ip, cidr.address, cidr.prefix_len
+        ));
+    }
+    let include = parse(&site.include);
+    if !include.is_empty() && !include.iter().any(|cidr| cidr.contains(&address)) {
+        return Err(format!("{} is not in any included CIDR", ip));
+    }
+    Ok(ip)
+}
+
+/// Every match of `regex` (capture group 1 if it has one), or every non-empty line
+/// without one
+pub fn extract_addresses(text: &str, regex: Option<&str>) -> Result<Vec<String>, String> {
+    let pattern = match regex {
+        Some(pattern) => match Regex::new(pattern) {
+            Ok(pattern) => pattern,
+            Err(e) => return Err(format!("Invalid regex: {}", e)),
+        },
+        None => {
+            return Ok(text
+                .lines()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect())
+        }
+    };
+    Ok(pattern
+        .captures_iter(text)
+        .filter_map(|captures| captures.get(1).or_else(|| captures.get(0)))
+        .map(|matched| matched.as_str().trim().to_string())
+        .filter(|matched| !matched.is_empty())
+        .collect())
+}
+
+/// 12 bytes to match answers to our UDP requests (STUN transaction ID, PCP nonce)
+pub fn random_id() -> [u8; 12] {
+    // every RandomState is seeded differently, good enough to tell our answers apart
+    let mut id = [0u8; 12];
+    let high = RandomState::new().build_hasher().finish().to_ne_bytes();
+    let low = RandomState::new().build_hasher().finish().to_ne_bytes();
+    id[..8].copy_from_slice(&high);
+    id[8..].copy_from_slice(&low[..4]);
+    id
+}
+
+/// Parse `value` and make sure it is an address of the requested version
+pub fn validate_address(value: &str, version: &AddressVersion) -> Result<IpAddr, String> {
+    let ip = match value.trim().parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => return Err(format!("{:?} is not an IP address", value.trim())),
+    };
+    match (ip, version) {
+        (IpAddr::V4(_), AddressVersion::V4) | (IpAddr::V6(_), AddressVersion::V6) => Ok(ip),
+        _ => Err(format!("{} is not an {} address", ip, version.to_string())),
+    }
+}
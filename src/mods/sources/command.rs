@@ -0,0 +1,82 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{process::Stdio, time::Duration};
+use tokio::{process::Command, time::timeout};
+
+use super::extract_addresses;
+
+/// Run a program, e.g. a router vendor CLI, and take the address from its output
+#[derive(Deserialize, Serialize, Clone)]
+pub struct CommandSource {
+    pub command: Vec<String>, // program and arguments, run without a shell
+    #[serde(default)]
+    pub shell: bool, // run the single string in `command` with `sh -c` instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>, // capture group 1 (or the whole match) of every match
+    #[serde(default = "default_timeout")]
+    pub timeout: u64, // seconds, the program is killed afterwards
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+impl CommandSource {
+    pub fn new(command: Vec<String>) -> Self {
+        Self {
+            command,
+            shell: false,
+            regex: None,
+            timeout: default_timeout(),
+        }
+    }
+
+    /// Candidate addresses found in the standard output, in order
+    pub async fn fetch_addresses(&self) -> Result<Vec<String>, String> {
+        let mut command = match (self.shell, self.command.as_slice()) {
+            (true, [script]) => {
+                let mut command = Command::new("sh");
+                command.arg("-c").arg(script);
+                command
+            }
+            (true, _) => return Err("a shell command must be a single string".to_string()),
+            (false, [program, args @ ..]) => {
+                let mut command = Command::new(program);
+                command.args(args);
+                command
+            }
+            (false, []) => return Err("no command to run".to_string()),
+        };
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        let child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => return Err(format!("failed to run {:?}: {}", self.command, e)),
+        };
+        let output =
+            match timeout(Duration::from_secs(self.timeout), child.wait_with_output()).await {
+                Ok(Ok(output)) => output,
+                Ok(Err(e)) => return Err(format!("{:?} failed: {}", self.command, e)),
+                Err(_) => {
+                    return Err(format!(
+                        "{:?} did not finish within {}s",
+                        self.command, self.timeout
+                    ))
+                }
+            };
+        if !output.status.success() {
+            return Err(format!(
+                "{:?} exited with {}: {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        debug!("{:?} printed {:?}", self.command, stdout);
+        extract_addresses(&stdout, self.regex.as_deref())
+    }
+}
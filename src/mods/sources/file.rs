@@ -0,0 +1,31 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use super::extract_addresses;
+
+/// Read the address from a file, e.g. a status file written by the router firmware
+#[derive(Deserialize, Serialize, Clone)]
+pub struct FileSource {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>, // capture group 1 (or the whole match) of every match
+}
+
+impl FileSource {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            regex: None,
+        }
+    }
+
+    /// Candidate addresses found in the file, in order
+    pub async fn fetch_addresses(&self) -> Result<Vec<String>, String> {
+        let content = match tokio::fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(e) => return Err(format!("failed to read {}: {}", self.path, e)),
+        };
+        debug!("{} contains {:?}", self.path, content);
+        extract_addresses(&content, self.regex.as_deref())
+    }
+}
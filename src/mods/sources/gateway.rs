@@ -0,0 +1,358 @@
+use log::{debug, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{
+    net::UdpSocket,
+    time::{timeout, Instant},
+};
+
+use super::random_id;
+use crate::mods::interfaces::default_gateway;
+
+const SSDP_ADDRESS: &str = "239.255.255.250:1900";
+const IGD_SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+const NAT_PMP_PORT: u16 = 5351;
+const PCP_MAP: u8 = 1;
+const PCP_MAP_LIFETIME: u32 = 60;
+const INITIAL_RTO: Duration = Duration::from_millis(250);
+
+/// Ask the router for its WAN address, for hosts that only have a private one
+#[derive(Deserialize, Serialize, Clone)]
+pub struct GatewaySource {
+    #[serde(default = "default_protocols")]
+    pub protocols: Vec<GatewayProtocol>, // tried in order until one answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<String>, // NAT-PMP/PCP server "ip" or "ip:port", the default gateway otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>, // UPnP device description url, skips SSDP discovery
+    #[serde(default = "default_timeout")]
+    pub timeout: u64, // seconds, per protocol
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum GatewayProtocol {
+    Upnp,   // UPnP IGD GetExternalIPAddress
+    NatPmp, // RFC 6886
+    Pcp,    // RFC 6887, a short-lived MAP request
+}
+
+fn default_protocols() -> Vec<GatewayProtocol> {
+    vec![
+        GatewayProtocol::Upnp,
+        GatewayProtocol::NatPmp,
+        GatewayProtocol::Pcp,
+    ]
+}
+
+fn default_timeout() -> u64 {
+    3
+}
+
+impl GatewaySource {
+    pub fn new(protocols: Vec<GatewayProtocol>) -> Self {
+        Self {
+            protocols,
+            gateway: None,
+            location: None,
+            timeout: default_timeout(),
+        }
+    }
+
+    /// Try the protocols in order, returns the first external address reported
+    pub async fn fetch_address(&self) -> Result<String, String> {
+        let mut errors = Vec::new();
+        for protocol in self.protocols.iter() {
+            let result = match protocol {
+                GatewayProtocol::Upnp => self.upnp_address().await,
+                GatewayProtocol::NatPmp => self.nat_pmp_address().await,
+                GatewayProtocol::Pcp => self.pcp_address().await,
+            };
+            match result {
+                Ok(ip) => {
+                    debug!("{:?} gateway returned {}", protocol, ip);
+                    return Ok(ip.to_string());
+                }
+                Err(e) => {
+                    warn!("{:?} gateway failed: {}", protocol, e);
+                    errors.push(format!("{:?}: {}", protocol, e));
+                }
+            }
+        }
+        Err(format!("no gateway answered ({})", errors.join("; ")))
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+
+    async fn upnp_address(&self) -> Result<Ipv4Addr, String> {
+        let location = match &self.location {
+            Some(location) => location.clone(),
+            None => ssdp_discover(SSDP_ADDRESS, self.timeout()).await?,
+        };
+        let client = match reqwest::Client::builder().timeout(self.timeout()).build() {
+            Ok(client) => client,
+            Err(e) => return Err(e.to_string()),
+        };
+        let description = match client.get(&location).send().await {
+            Ok(rsp) if rsp.status().is_success() => rsp.text().await.map_err(|e| e.to_string())?,
+            Ok(rsp) => return Err(format!("{}: status code {}", location, rsp.status())),
+            Err(e) => return Err(e.to_string()),
+        };
+        let (service, control_url) = find_wan_service(&description, &location)?;
+        debug!("UPnP {} at {}", service, control_url);
+
+        let body = format!(
+            "<?xml version=\"1.0\"?>\r\n\
+             <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+             s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+             <s:Body><u:GetExternalIPAddress xmlns:u=\"{}\"></u:GetExternalIPAddress></s:Body>\
+             </s:Envelope>\r\n",
+            service
+        );
+        let rsp = match client
+            .post(&control_url)
+            .header("Content-Type", "text/xml; charset=\"utf-8\"")
+            .header(
+                "SOAPAction",
+                format!("\"{}#GetExternalIPAddress\"", service),
+            )
+            .body(body)
+            .send()
+            .await
+        {
+            Ok(rsp) => rsp,
+            Err(e) => return Err(e.to_string()),
+        };
+        if !rsp.status().is_success() {
+            return Err(format!("status code {}", rsp.status().as_u16()));
+        }
+        match rsp.text().await {
+            Ok(body) => parse_soap_address(&body),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    async fn nat_pmp_address(&self) -> Result<Ipv4Addr, String> {
+        let socket = self.gateway_socket().await?;
+        // version 0, opcode 0: external address
+        let response = exchange(&socket, &[0, 0], self.timeout(), |response| {
+            response.len() >= 12 && response[0] == 0 && response[1] == 128
+        })
+        .await?;
+        parse_nat_pmp_response(&response)
+    }
+
+    async fn pcp_address(&self) -> Result<Ipv4Addr, String> {
+        let socket = self.gateway_socket().await?;
+        let local = match socket.local_addr() {
+            Ok(SocketAddr::V4(local)) => local,
+            Ok(_) => return Err("PCP is only used over IPv4".to_string()),
+            Err(e) => return Err(e.to_string()),
+        };
+        let nonce = random_id();
+        let request = pcp_map_request(*local.ip(), local.port(), &nonce, PCP_MAP_LIFETIME);
+        let response = exchange(&socket, &request, self.timeout(), |response| {
+            response.len() >= 60 && response[1] == 0x80 | PCP_MAP && response[24..36] == nonce
+        })
+        .await?;
+        // only the address was needed, drop the mapping again
+        let delete = pcp_map_request(*local.ip(), local.port(), &nonce, 0);
+        let _ = socket.send(&delete).await;
+        parse_pcp_response(&response)
+    }
+
+    async fn gateway_socket(&self) -> Result<UdpSocket, String> {
+        let gateway = match &self.gateway {
+            Some(gateway) => parse_gateway(gateway)?,
+            None => SocketAddr::new(IpAddr::V4(default_gateway()?), NAT_PMP_PORT),
+        };
+        let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await {
+            Ok(socket) => socket,
+            Err(e) => return Err(e.to_string()),
+        };
+        match socket.connect(gateway).await {
+            Ok(_) => Ok(socket),
+            Err(e) => Err(format!("{}: {}", gateway, e)),
+        }
+    }
+}
+
+/// "ip" or "ip:port", NAT-PMP and PCP use port 5351
+pub fn parse_gateway(value: &str) -> Result<SocketAddr, String> {
+    if let Ok(address) = value.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+    match value.parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, NAT_PMP_PORT)),
+        Err(_) => Err(format!("{:?} is not an address or address:port", value)),
+    }
+}
+
+/// Send `request` until an answer `accepted` arrives, with a doubling retransmission timeout
+async fn exchange(
+    socket: &UdpSocket,
+    request: &[u8],
+    wait: Duration,
+    accepted: impl Fn(&[u8]) -> bool,
+) -> Result<Vec<u8>, String> {
+    let deadline = Instant::now() + wait;
+    let mut rto = INITIAL_RTO;
+    let mut buf = [0u8; 1100];
+    loop {
+        if let Err(e) = socket.send(request).await {
+            return Err(e.to_string());
+        }
+        let until = Instant::now() + rto.min(deadline.saturating_duration_since(Instant::now()));
+        while let Ok(received) = timeout(
+            until.saturating_duration_since(Instant::now()),
+            socket.recv(&mut buf),
+        )
+        .await
+        {
+            match received {
+                Ok(len) if accepted(&buf[..len]) => return Ok(buf[..len].to_vec()),
+                Ok(_) => continue,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        if Instant::now() >= deadline {
+            return Err("no response".to_string());
+        }
+        rto *= 2;
+    }
+}
+
+/// M-SEARCH for an Internet Gateway Device, returns the LOCATION of the first answer
+pub async fn ssdp_discover(target: &str, wait: Duration) -> Result<String, String> {
+    let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await {
+        Ok(socket) => socket,
+        Err(e) => return Err(e.to_string()),
+    };
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {}\r\n\r\n",
+        SSDP_ADDRESS, IGD_SEARCH_TARGET
+    );
+    if let Err(e) = socket.send_to(request.as_bytes(), target).await {
+        return Err(format!("SSDP search failed: {}", e));
+    }
+    let deadline = Instant::now() + wait;
+    let mut buf = [0u8; 2048];
+    while let Ok(received) = timeout(
+        deadline.saturating_duration_since(Instant::now()),
+        socket.recv_from(&mut buf),
+    )
+    .await
+    {
+        let len = match received {
+            Ok((len, _)) => len,
+            Err(e) => return Err(e.to_string()),
+        };
+        let response = String::from_utf8_lossy(&buf[..len]);
+        let location = response
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("location"))
+            .map(|(_, value)| value.trim().to_string());
+        if let Some(location) = location {
+            return Ok(location);
+        }
+    }
+    Err("no UPnP gateway answered the SSDP search".to_string())
+}
+
+/// Service type and absolute control url of the WANIPConnection or WANPPPConnection
+/// service in a device description
+pub fn find_wan_service(description: &str, location: &str) -> Result<(String, String), String> {
+    let services = Regex::new(r"(?s)<service>(.*?)</service>").unwrap();
+    let field = |service: &str, name: &str| {
+        let pattern = Regex::new(&format!(r"(?s)<{}>\s*(.*?)\s*</{}>", name, name)).unwrap();
+        pattern
+            .captures(service)
+            .map(|captures| captures[1].to_string())
+    };
+    let base = match field(description, "URLBase") {
+        Some(base) => base,
+        None => location.to_string(),
+    };
+    for captures in services.captures_iter(description) {
+        let service_type = match field(&captures[1], "serviceType") {
+            Some(service_type) => service_type,
+            None => continue,
+        };
+        if !service_type.contains(":WANIPConnection:")
+            && !service_type.contains(":WANPPPConnection:")
+        {
+            continue;
+        }
+        let control_url = match field(&captures[1], "controlURL") {
+            Some(control_url) => control_url,
+            None => continue,
+        };
+        return match reqwest::Url::parse(&base).and_then(|base| base.join(&control_url)) {
+            Ok(url) => Ok((service_type, url.to_string())),
+            Err(e) => Err(format!("invalid control url {:?}: {}", control_url, e)),
+        };
+    }
+    Err("no WANIPConnection or WANPPPConnection service in the device description".to_string())
+}
+
+pub fn parse_soap_address(body: &str) -> Result<Ipv4Addr, String> {
+    let pattern =
+        Regex::new(r"<NewExternalIPAddress>\s*([^<]*?)\s*</NewExternalIPAddress>").unwrap();
+    match pattern.captures(body) {
+        Some(captures) => match captures[1].parse::<Ipv4Addr>() {
+            Ok(ip) => Ok(ip),
+            Err(_) => Err(format!("{:?} is not an IPv4 address", &captures[1])),
+        },
+        None => Err("no NewExternalIPAddress in response".to_string()),
+    }
+}
+
+/// version, opcode, result code, seconds since epoch, external address
+pub fn parse_nat_pmp_response(response: &[u8]) -> Result<Ipv4Addr, String> {
+    if response.len() < 12 {
+        return Err("truncated NAT-PMP response".to_string());
+    }
+    match u16::from_be_bytes([response[2], response[3]]) {
+        0 => Ok(Ipv4Addr::new(
+            response[8],
+            response[9],
+            response[10],
+            response[11],
+        )),
+        code => Err(format!("NAT-PMP result code {}", code)),
+    }
+}
+
+/// A MAP request for UDP `port`, a lifetime of 0 deletes the mapping
+pub fn pcp_map_request(client: Ipv4Addr, port: u16, nonce: &[u8; 12], lifetime: u32) -> Vec<u8> {
+    let mut request = vec![2, PCP_MAP, 0, 0];
+    request.extend_from_slice(&lifetime.to_be_bytes());
+    request.extend_from_slice(&client.to_ipv6_mapped().octets());
+    request.extend_from_slice(nonce);
+    request.extend_from_slice(&[17, 0, 0, 0]); // UDP
+    request.extend_from_slice(&port.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes()); // any external port
+    request.extend_from_slice(&Ipv6Addr::UNSPECIFIED.octets()); // any external address
+    request
+}
+
+/// The assigned external address of a MAP response
+pub fn parse_pcp_response(response: &[u8]) -> Result<Ipv4Addr, String> {
+    if response.len() < 60 {
+        return Err("truncated PCP response".to_string());
+    }
+    if response[3] != 0 {
+        return Err(format!("PCP result code {}", response[3]));
+    }
+    let octets: [u8; 16] = response[44..60].try_into().unwrap();
+    match Ipv6Addr::from(octets).to_ipv4_mapped() {
+        Some(ip) => Ok(ip),
+        None => Err(format!("{} is not an IPv4 address", Ipv6Addr::from(octets))),
+    }
+}
@@ -0,0 +1,125 @@
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use super::validate_address;
+use crate::mods::{providers::custom::extract_value, types::AddressVersion};
+
+/// Ask "what is my IP" services for the public address of this host
+#[derive(Deserialize, Serialize, Clone)]
+pub struct HttpSource {
+    pub endpoints: Vec<HttpEndpoint>,
+    #[serde(default = "default_consensus")]
+    pub consensus: usize, // number of endpoints that must agree, 1 means first valid answer
+    #[serde(default = "default_timeout")]
+    pub timeout: u64, // seconds, per endpoint
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct HttpEndpoint {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_path: Option<String>, // plain text body when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+}
+
+fn default_consensus() -> usize {
+    1
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+impl HttpEndpoint {
+    pub fn new(url: &str, json_path: Option<&str>) -> Self {
+        Self {
+            url: url.to_string(),
+            json_path: json_path.map(|value| value.to_string()),
+            regex: None,
+        }
+    }
+}
+
+impl HttpSource {
+    pub fn new(endpoints: Vec<HttpEndpoint>, consensus: usize) -> Self {
+        Self {
+            endpoints,
+            consensus,
+            timeout: default_timeout(),
+        }
+    }
+
+    /// Query the endpoints in order until `consensus` of them returned the same address
+    pub async fn fetch_address(&self, version: &AddressVersion) -> Result<String, String> {
+        let needed = self.consensus.max(1);
+        let mut votes: HashMap<IpAddr, usize> = HashMap::new();
+        for endpoint in self.endpoints.iter() {
+            let ip = match self.fetch_endpoint(endpoint, version).await {
+                Ok(ip) => ip,
+                Err(e) => {
+                    warn!("IP echo service {} failed: {}", endpoint.url, e);
+                    continue;
+                }
+            };
+            debug!("IP echo service {} returned {}", endpoint.url, ip);
+            let count = votes.entry(ip).or_insert(0);
+            *count += 1;
+            if *count >= needed {
+                return Ok(ip.to_string());
+            }
+        }
+        Err(format!(
+            "no {} address reached a consensus of {} among {} endpoints ({:?})",
+            version.to_string(),
+            needed,
+            self.endpoints.len(),
+            votes
+        ))
+    }
+
+    async fn fetch_endpoint(
+        &self,
+        endpoint: &HttpEndpoint,
+        version: &AddressVersion,
+    ) -> Result<IpAddr, String> {
+        // binding to the unspecified address of a family forces the connection over it
+        let local_address = match version {
+            AddressVersion::V4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            AddressVersion::V6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let client = match reqwest::Client::builder()
+            .local_address(local_address)
+            .timeout(Duration::from_secs(self.timeout))
+            .build()
+        {
+            Ok(value) => value,
+            Err(e) => return Err(e.to_string()),
+        };
+        let rsp = match client.get(&endpoint.url).send().await {
+            Ok(value) => value,
+            Err(e) => return Err(e.to_string()),
+        };
+        if !rsp.status().is_success() {
+            return Err(format!("status code {}", rsp.status().as_u16()));
+        }
+        let body = match rsp.text().await {
+            Ok(value) => value,
+            Err(e) => return Err(e.to_string()),
+        };
+        match extract_value(
+            &body,
+            endpoint.json_path.as_deref(),
+            endpoint.regex.as_deref(),
+        ) {
+            Ok(Some(value)) => validate_address(&value, version),
+            Ok(None) => Err("no address in response".to_string()),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use std::net::Ipv6Addr;
+
+/// Publish `<prefix of an interface address> + <host_suffix>`, for hosts behind a router
+/// that gets its IPv6 prefix delegated by the ISP
+#[derive(Deserialize, Serialize, Clone)]
+pub struct PrefixSource {
+    #[serde(default = "default_prefix_len")]
+    pub prefix_len: u8, // bits taken from the interface address
+    pub host_suffix: String, // the rest, e.g. "::1234" or "::211:22ff:fe33:4455"
+}
+
+fn default_prefix_len() -> u8 {
+    64
+}
+
+impl PrefixSource {
+    pub fn new(prefix_len: u8, host_suffix: &str) -> Self {
+        Self {
+            prefix_len,
+            host_suffix: host_suffix.to_string(),
+        }
+    }
+
+    /// Check `prefix_len` and that `host_suffix` has no bits inside the prefix
+    pub fn suffix(&self) -> Result<Ipv6Addr, String> {
+        if self.prefix_len == 0 || self.prefix_len >= 128 {
+            return Err(format!(
+                "prefix_len {} must be between 1 and 127",
+                self.prefix_len
+            ));
+        }
+        let suffix = match self.host_suffix.trim().parse::<Ipv6Addr>() {
+            Ok(suffix) => suffix,
+            Err(_) => {
+                return Err(format!(
+                    "host_suffix {:?} is not an IPv6 suffix such as \"::1234\"",
+                    self.host_suffix
+                ))
+            }
+        };
+        if u128::from(suffix) & self.mask() != 0 {
+            return Err(format!(
+                "host_suffix {} overlaps the first {} bits",
+                suffix, self.prefix_len
+            ));
+        }
+        Ok(suffix)
+    }
+
+    /// Replace everything after the prefix of `address` with `host_suffix`
+    pub fn compose(&self, address: &str) -> Result<String, String> {
+        let suffix = self.suffix()?;
+        let address = match address.parse::<Ipv6Addr>() {
+            Ok(address) => address,
+            Err(_) => return Err(format!("{} is not an IPv6 address", address)),
+        };
+        let composed = (u128::from(address) & self.mask()) | u128::from(suffix);
+        Ok(Ipv6Addr::from(composed).to_string())
+    }
+
+    fn mask(&self) -> u128 {
+        u128::MAX
+            .checked_shl(128 - self.prefix_len as u32)
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,251 @@
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{
+    net::{lookup_host, UdpSocket},
+    time::{timeout, Instant},
+};
+
+use super::{random_id, validate_address};
+use crate::mods::types::AddressVersion;
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const BINDING_ERROR: u16 = 0x0111;
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const MAPPED_ADDRESS: u16 = 0x0001;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const ERROR_CODE: u16 = 0x0009;
+const HEADER_LEN: usize = 20;
+const DEFAULT_PORT: u16 = 3478;
+const INITIAL_RTO: Duration = Duration::from_millis(500);
+
+/// Ask STUN servers (RFC 5389) which address our UDP packets come from
+#[derive(Deserialize, Serialize, Clone)]
+pub struct StunSource {
+    pub servers: Vec<String>, // "host" or "host:port", 3478 by default
+    #[serde(default = "default_consensus")]
+    pub consensus: usize, // number of servers that must agree, 1 means first valid answer
+    #[serde(default = "default_timeout")]
+    pub timeout: u64, // seconds, per server
+}
+
+fn default_consensus() -> usize {
+    1
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+impl StunSource {
+    pub fn new(servers: Vec<String>, consensus: usize) -> Self {
+        Self {
+            servers,
+            consensus,
+            timeout: default_timeout(),
+        }
+    }
+
+    /// Query the servers in order until `consensus` of them returned the same address
+    pub async fn fetch_address(&self, version: &AddressVersion) -> Result<String, String> {
+        let needed = self.consensus.max(1);
+        let mut votes: HashMap<IpAddr, usize> = HashMap::new();
+        for server in self.servers.iter() {
+            let ip = match self.query_server(server, version).await {
+                Ok(ip) => ip,
+                Err(e) => {
+                    warn!("STUN server {} failed: {}", server, e);
+                    continue;
+                }
+            };
+            debug!("STUN server {} returned {}", server, ip);
+            let count = votes.entry(ip).or_insert(0);
+            *count += 1;
+            if *count >= needed {
+                return Ok(ip.to_string());
+            }
+        }
+        Err(format!(
+            "no {} address reached a consensus of {} among {} STUN servers ({:?})",
+            version.to_string(),
+            needed,
+            self.servers.len(),
+            votes
+        ))
+    }
+
+    async fn query_server(&self, server: &str, version: &AddressVersion) -> Result<IpAddr, String> {
+        let server = resolve_server(server, version).await?;
+        let local_address = match version {
+            AddressVersion::V4 => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            AddressVersion::V6 => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = match UdpSocket::bind(local_address).await {
+            Ok(socket) => socket,
+            Err(e) => return Err(e.to_string()),
+        };
+        let transaction_id = random_id();
+        let request = binding_request(&transaction_id);
+
+        // UDP may drop packets: retransmit with a doubling timeout until `timeout` is over
+        let deadline = Instant::now() + Duration::from_secs(self.timeout);
+        let mut rto = INITIAL_RTO;
+        let mut buf = [0u8; 1024];
+        loop {
+            if let Err(e) = socket.send_to(&request, server).await {
+                return Err(e.to_string());
+            }
+            let wait = rto.min(deadline.saturating_duration_since(Instant::now()));
+            let until = Instant::now() + wait;
+            while let Ok(received) = timeout(
+                until.saturating_duration_since(Instant::now()),
+                socket.recv_from(&mut buf),
+            )
+            .await
+            {
+                let (len, from) = match received {
+                    Ok(received) => received,
+                    Err(e) => return Err(e.to_string()),
+                };
+                if from != server {
+                    continue;
+                }
+                match parse_binding_response(&buf[..len], &transaction_id) {
+                    Ok(Some(ip)) => return validate_address(&ip.to_string(), version),
+                    Ok(None) => continue, // not the answer to our request
+                    Err(e) => return Err(e),
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err("no response".to_string());
+            }
+            rto *= 2;
+        }
+    }
+}
+
+async fn resolve_server(server: &str, version: &AddressVersion) -> Result<SocketAddr, String> {
+    let with_port = match server.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, DEFAULT_PORT).to_string(), // bare IPv6 address
+        Err(_)
+            if server
+                .rsplit_once(':')
+                .is_some_and(|(_, p)| p.parse::<u16>().is_ok()) =>
+        {
+            server.to_string()
+        }
+        Err(_) => format!("{}:{}", server, DEFAULT_PORT),
+    };
+    let addresses = match lookup_host(&with_port).await {
+        Ok(addresses) => addresses,
+        Err(e) => return Err(format!("failed to resolve {}: {}", server, e)),
+    };
+    let mut addresses = addresses.filter(|address| match version {
+        AddressVersion::V4 => address.is_ipv4(),
+        AddressVersion::V6 => address.is_ipv6(),
+    });
+    match addresses.next() {
+        Some(address) => Ok(address),
+        None => Err(format!("{} has no {} address", server, version.to_string())),
+    }
+}
+
+/// A Binding Request without attributes
+pub fn binding_request(transaction_id: &[u8; 12]) -> Vec<u8> {
+    let mut request = Vec::with_capacity(HEADER_LEN);
+    request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(transaction_id);
+    request
+}
+
+/// The mapped address of a Binding Success response, `None` for messages that do not
+/// answer `transaction_id`
+pub fn parse_binding_response(
+    buf: &[u8],
+    transaction_id: &[u8; 12],
+) -> Result<Option<IpAddr>, String> {
+    if buf.len() < HEADER_LEN
+        || buf[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || buf[8..HEADER_LEN] != transaction_id[..]
+    {
+        return Ok(None);
+    }
+    let kind = u16::from_be_bytes([buf[0], buf[1]]);
+    let len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+    let mut attributes = match buf.get(HEADER_LEN..HEADER_LEN + len) {
+        Some(attributes) => attributes,
+        None => return Err("truncated response".to_string()),
+    };
+
+    let mut mapped = None;
+    let mut xor_mapped = None;
+    let mut error = None;
+    while attributes.len() >= 4 {
+        let attribute = u16::from_be_bytes([attributes[0], attributes[1]]);
+        let len = u16::from_be_bytes([attributes[2], attributes[3]]) as usize;
+        let value = match attributes.get(4..4 + len) {
+            Some(value) => value,
+            None => return Err("truncated attribute".to_string()),
+        };
+        match attribute {
+            XOR_MAPPED_ADDRESS => xor_mapped = Some(parse_address(value, Some(transaction_id))?),
+            MAPPED_ADDRESS => mapped = Some(parse_address(value, None)?),
+            ERROR_CODE if value.len() >= 4 => {
+                let code = value[2] as u16 * 100 + value[3] as u16;
+                let reason = String::from_utf8_lossy(&value[4..]).to_string();
+                error = Some(format!("error {} {}", code, reason));
+            }
+            _ => (),
+        }
+        let padded = (4 + len + 3) & !3;
+        attributes = &attributes[padded.min(attributes.len())..];
+    }
+    match kind {
+        // old servers only send MAPPED-ADDRESS
+        BINDING_SUCCESS => match xor_mapped.or(mapped) {
+            Some(ip) => Ok(Some(ip)),
+            None => Err("no mapped address in response".to_string()),
+        },
+        BINDING_ERROR => Err(error.unwrap_or("error response".to_string())),
+        other => Err(format!("unexpected message type {:#06x}", other)),
+    }
+}
+
+/// (XOR-)MAPPED-ADDRESS: reserved, family, port, address
+fn parse_address(value: &[u8], transaction_id: Option<&[u8; 12]>) -> Result<IpAddr, String> {
+    let key: Vec<u8> = match transaction_id {
+        Some(id) => MAGIC_COOKIE
+            .to_be_bytes()
+            .iter()
+            .chain(id)
+            .copied()
+            .collect(),
+        None => vec![0; 16],
+    };
+    let address = |len: usize| match value.get(4..4 + len) {
+        Some(address) => Ok(address
+            .iter()
+            .zip(key.iter())
+            .map(|(byte, key)| byte ^ key)
+            .collect::<Vec<_>>()),
+        None => Err("truncated address".to_string()),
+    };
+    match value.get(1) {
+        Some(0x01) => {
+            let octets: [u8; 4] = address(4)?.try_into().unwrap();
+            Ok(IpAddr::from(octets))
+        }
+        Some(0x02) => {
+            let octets: [u8; 16] = address(16)?.try_into().unwrap();
+            Ok(IpAddr::from(octets))
+        }
+        _ => Err("unknown address family".to_string()),
+    }
+}
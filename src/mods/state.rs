@@ -0,0 +1,196 @@
+use log::{error, trace, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, path::PathBuf};
+
+use super::{statics::STATE, types::DDNSProvider};
+
+/// What we remember about a site between runs
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq)]
+pub struct SiteState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_ip: Option<String>, // last value successfully published
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<i64>, // unix timestamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<i64>, // unix timestamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(default)]
+    pub error_count: u32, // consecutive failures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_propagation: Option<u64>, // seconds DNS took to serve the last update, None if it never did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>, // hash of the record `last_ip` and `record_id` belong to
+}
+
+/// Hash of what identifies the record of a site: provider kind, zone, name and type.
+/// Credentials are left out, so rotating a token keeps the state.
+pub fn provider_hash(provider: &DDNSProvider) -> String {
+    let (kind, zone, rr, record_type) = match provider {
+        DDNSProvider::Aliyun(value) => (
+            "Aliyun",
+            value.domain.clone(),
+            value.rr.as_str(),
+            value.record_type.as_str(),
+        ),
+        DDNSProvider::Cloudflare(value) => (
+            "Cloudflare",
+            format!("{}/{}", value.zone_id, value.domain),
+            value.rr.as_str(),
+            value.record_type.as_str(),
+        ),
+        DDNSProvider::Dnspod(value) => (
+            "Dnspod",
+            value.domain.clone(),
+            value.rr.as_str(),
+            value.record_type.as_str(),
+        ),
+        DDNSProvider::Dynv6(value) => (
+            "Dynv6",
+            value.zone_id.to_string(),
+            value.rr.as_str(),
+            value.record_type.as_str(),
+        ),
+        DDNSProvider::Namecheap(value) => {
+            ("Namecheap", value.domain.clone(), value.rr.as_str(), "")
+        }
+        DDNSProvider::Rfc2136(value) => (
+            "Rfc2136",
+            value.domain.clone(),
+            value.rr.as_str(),
+            value.record_type.as_str(),
+        ),
+        DDNSProvider::Custom(value) => ("Custom", value.domain.clone(), value.name.as_str(), ""),
+    };
+    let identity = serde_json::json!([kind, zone, rr, record_type]).to_string();
+    Sha256::digest(identity.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Site states keyed by site name, persisted as JSON when a path is configured
+#[derive(Default)]
+pub struct StateStore {
+    path: Option<PathBuf>,
+    sites: BTreeMap<String, SiteState>,
+}
+
+impl StateStore {
+    pub fn new() -> StateStore {
+        StateStore::default()
+    }
+
+    pub async fn load(path: Option<&str>) -> StateStore {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => return StateStore::new(),
+        };
+        let sites = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => match serde_json::from_str(&content) {
+                Ok(sites) => sites,
+                Err(e) => {
+                    warn!("Ignoring unreadable state file {}: {}", path.display(), e);
+                    BTreeMap::new()
+                }
+            },
+            Err(_) => BTreeMap::new(), // first run
+        };
+        StateStore {
+            path: Some(path),
+            sites,
+        }
+    }
+
+    /// Whether the state survives a restart
+    pub fn is_persistent(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn site(&self, name: &str) -> SiteState {
+        self.sites.get(name).cloned().unwrap_or_default()
+    }
+
+    /// The state of a site if it was recorded for the same record, a blank one otherwise
+    /// (rr, domain, record type or the provider itself changed under the same name)
+    pub fn site_for(&self, name: &str, provider: &DDNSProvider) -> SiteState {
+        match self.sites.get(name) {
+            Some(site) if site.provider == Some(provider_hash(provider)) => site.clone(),
+            Some(_) => {
+                trace!("{}: now publishes another record, ignoring its state", name);
+                SiteState::default()
+            }
+            None => SiteState::default(),
+        }
+    }
+
+    pub async fn record_success(
+        &mut self,
+        name: &str,
+        provider: &DDNSProvider,
+        ip: &str,
+        record_id: Option<String>,
+    ) {
+        let now = chrono::Utc::now().timestamp();
+        let hash = provider_hash(provider);
+        let site = self.sites.entry(name.to_string()).or_default();
+        if site.provider.as_ref() != Some(&hash) {
+            // the remembered value and ID were about another record
+            site.record_id = None;
+            site.provider = Some(hash);
+        }
+        site.last_ip = Some(ip.to_string());
+        if record_id.is_some() {
+            site.record_id = record_id;
+        }
+        site.last_success = Some(now);
+        site.error_count = 0;
+        self.save().await;
+    }
+
+    /// Outcome of the propagation check of the last update
+    pub async fn record_propagation(&mut self, name: &str, seconds: Option<u64>) {
+        let site = self.sites.entry(name.to_string()).or_default();
+        site.last_propagation = seconds;
+        self.save().await;
+    }
+
+    pub async fn record_failure(&mut self, name: &str, reason: &str) {
+        let now = chrono::Utc::now().timestamp();
+        let site = self.sites.entry(name.to_string()).or_default();
+        site.last_failure = Some(now);
+        site.last_error = Some(reason.to_string());
+        site.error_count += 1;
+        self.save().await;
+    }
+
+    async fn save(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let content = match serde_json::to_string_pretty(&self.sites) {
+            Ok(content) => content,
+            Err(e) => {
+                error!("Failed to serialize state: {}", e);
+                return;
+            }
+        };
+        // write then rename so a crash never leaves a truncated file behind
+        let tmp_path = path.with_extension("tmp");
+        if let Err(e) = tokio::fs::write(&tmp_path, content).await {
+            error!("Failed to write state file {}: {}", tmp_path.display(), e);
+            return;
+        }
+        if let Err(e) = tokio::fs::rename(&tmp_path, path).await {
+            error!("Failed to write state file {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub async fn init_state(path: Option<&str>) {
+    *STATE.lock().await = StateStore::load(path).await;
+}
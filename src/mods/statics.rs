@@ -1,8 +1,17 @@
-use crate::mods::types::Config;
+use crate::mods::{handle::SiteTask, state::StateStore, types::Config};
 use lazy_static::lazy_static;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::{
+    collections::BTreeMap,
+    sync::{atomic::AtomicBool, Arc},
+};
+use tokio::sync::{broadcast, Mutex};
 
 lazy_static! {
     pub static ref CONFIG: Arc<Mutex<Config>> = Arc::new(Mutex::new(Config::new()));
+    pub static ref STATE: Arc<Mutex<StateStore>> = Arc::new(Mutex::new(StateStore::new()));
+    pub static ref DRY_RUN: AtomicBool = AtomicBool::new(false); // set by --dry-run
+    pub static ref TASKS: Arc<Mutex<BTreeMap<String, SiteTask>>> =
+        Arc::new(Mutex::new(BTreeMap::new()));
+    // names of interfaces whose addresses just changed, see `interfaces::netlink`
+    pub static ref ADDRESS_EVENTS: broadcast::Sender<String> = broadcast::channel(64).0;
 }
@@ -3,18 +3,27 @@ use std::path::Path;
 use serde::{Deserialize, Serialize};
 
 use super::{
-    interfaces::AddressType,
-    providers::{aliyun::Aliyun, dynv6::Dynv6},
+    dns::{DnsLookup, PropagationCheck},
+    interfaces::{AddressPolicy, AddressType},
+    providers::{
+        aliyun::Aliyun, cloudflare::Cloudflare, custom::Custom, dnspod::Dnspod, dynv6::Dynv6,
+        namecheap::Namecheap, rfc2136::Rfc2136, DDNSProviderTrait,
+    },
+    sources::IpSource,
 };
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Config {
     pub log_level: LogLevel,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_file: Option<String>, // remembers published IPs and record IDs across restarts
+    #[serde(default)]
+    pub dry_run: bool, // query providers but only log the changes for every site
     // pub plugins: Vec<String>, // temporary disabled
     pub sites_config: Vec<SiteConfig>,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub enum LogLevel {
     #[serde(rename = "info")]
     Info,
@@ -40,10 +49,25 @@ impl ToString for LogLevel {
     }
 }
 
+impl LogLevel {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "trace" => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+}
+
 impl Config {
     pub fn new() -> Config {
         Config {
             log_level: LogLevel::Trace,
+            state_file: None,
+            dry_run: false,
             // plugins: Vec::new(),
             sites_config: Vec::new(),
         }
@@ -52,10 +76,10 @@ impl Config {
     pub async fn new_from_path<T: AsRef<Path>>(path: &T) -> Result<Config, String> {
         let config = tokio::fs::read_to_string(path).await;
         match config {
-            Ok(config) => {
-                let config: Config = toml::from_str(&config).unwrap();
-                Ok(config)
-            }
+            Ok(config) => match toml::from_str::<Config>(&config) {
+                Ok(config) => Ok(config),
+                Err(e) => Err(e.to_string()),
+            },
             Err(e) => Err(e.to_string()),
         }
     }
@@ -69,12 +93,59 @@ pub struct SiteConfig {
     pub index: usize,
     pub address_version: AddressVersion,
     pub address_type: AddressType,
+    #[serde(default = "default_count")]
+    pub count: usize, // addresses published starting at `index`, 0 means all of them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address_policy: Option<AddressPolicy>, // how addresses are ordered before `index` applies
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>, // CIDRs, if any of a version are given addresses must be inside one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>, // CIDRs that are never published, whatever `address_type` says
+    #[serde(default)]
+    pub source: IpSource,
     // pub plugin: String,
     pub interval: u64,
     pub enabled: bool,
     pub retry_count: u32,
     pub retry_interval: u64,
     pub retry_on_failure: bool,
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lookup: Option<DnsLookup>, // read the current value with DNS instead of the provider API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify: Option<PropagationCheck>, // after an update, wait for DNS to serve the new value
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<TargetConfig>, // more records getting the same address, besides `provider`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dual_stack: Option<FamilyConfig>, // the other address family, published to the same name
+    #[serde(skip)]
+    pub optional: bool, // family of a dual-stack site: a missing address is skipped, not an error
+}
+
+/// Another record a site publishes its address to
+#[derive(Deserialize, Serialize, Clone)]
+pub struct TargetConfig {
+    pub name: String, // unique within the site
+    pub provider: DDNSProvider,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_suffix: Option<String>, // replaces the one of a `Prefix` source for this target
+}
+
+/// Where the second address family of a dual-stack site comes from
+#[derive(Deserialize, Serialize, Clone)]
+pub struct FamilyConfig {
+    #[serde(default)]
+    pub interface: String, // empty means the interface of the site
+    #[serde(default)]
+    pub index: usize,
+    pub address_type: AddressType,
+    #[serde(default = "default_count")]
+    pub count: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address_policy: Option<AddressPolicy>,
+    #[serde(default)]
+    pub source: IpSource,
 }
 
 #[derive(Deserialize, Serialize, Clone, PartialEq, Eq)]
@@ -92,6 +163,10 @@ impl ToString for AddressVersion {
     }
 }
 
+fn default_count() -> usize {
+    1
+}
+
 impl SiteConfig {
     pub fn new() -> SiteConfig {
         SiteConfig {
@@ -101,21 +176,86 @@ impl SiteConfig {
             index: 0, // 默认取第一个ip
             address_version: AddressVersion::V4,
             address_type: AddressType::Public,
+            count: 1,
+            address_policy: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            source: IpSource::Interface,
             // plugin: String::new(),
             interval: 0,
             enabled: false,
             retry_count: 0,
             retry_interval: 60,
             retry_on_failure: true,
+            dry_run: false,
+            lookup: None,
+            verify: None,
+            targets: Vec::new(),
+            dual_stack: None,
+            optional: false,
         }
     }
+
+    /// The site itself, or for a dual-stack site one site per address family.
+    ///
+    /// The first family keeps the site name (and so its state), the second one is `name/v4` or `name/v6`.
+    pub fn families(&self) -> Vec<SiteConfig> {
+        let family = match &self.dual_stack {
+            Some(family) => family,
+            None => return vec![self.clone()],
+        };
+        let mut primary = self.clone();
+        primary.dual_stack = None;
+        primary.optional = true;
+
+        let mut secondary = primary.clone();
+        let (version, record_type) = match self.address_version {
+            AddressVersion::V4 => (AddressVersion::V6, "AAAA"),
+            AddressVersion::V6 => (AddressVersion::V4, "A"),
+        };
+        secondary.name = format!("{}/{}", self.name, version.to_string());
+        secondary.address_version = version;
+        if !family.interface.is_empty() {
+            secondary.interface = family.interface.clone();
+        }
+        secondary.index = family.index;
+        secondary.address_type = family.address_type.clone();
+        secondary.count = family.count;
+        secondary.address_policy = family.address_policy.clone();
+        secondary.source = family.source.clone();
+        secondary.provider.set_record_type(record_type);
+        for target in secondary.targets.iter_mut() {
+            target.provider.set_record_type(record_type);
+        }
+        vec![primary, secondary]
+    }
+
+    /// The site itself plus one site per entry of `targets`, named `name:target`
+    pub fn targets(&self) -> Vec<SiteConfig> {
+        let mut primary = self.clone();
+        primary.targets = Vec::new();
+        let mut sites = vec![primary.clone()];
+        for target in self.targets.iter() {
+            let mut site = primary.clone();
+            site.name = format!("{}:{}", self.name, target.name);
+            site.provider = target.provider.clone();
+            if let (IpSource::Prefix(source), Some(host_suffix)) =
+                (&mut site.source, &target.host_suffix)
+            {
+                source.host_suffix = host_suffix.clone();
+            }
+            sites.push(site);
+        }
+        sites
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone)]
 pub enum DDNSProvider {
     Aliyun(Aliyun),
-    // Namecheap, // TODO
-    // Cloudflare, // TODO
+    Namecheap(Namecheap),
+    Cloudflare(Cloudflare),
+    Dnspod(Dnspod),
     // DuckDNS, // TODO
     // NoIP, // TODO
     // Dynu, // TODO
@@ -130,11 +270,55 @@ pub enum DDNSProvider {
     // Route53, // TODO
     // Strato, // TODO
     // Yandex, // TODO
-    Custom,
+    Rfc2136(Rfc2136),
+    Custom(Custom),
 }
 
 impl DDNSProvider {
     pub fn new() -> DDNSProvider {
-        DDNSProvider::Custom
+        DDNSProvider::Custom(Custom::default())
+    }
+
+    /// Fully qualified name of the record, `None` when the config does not tell (Dynv6
+    /// only knows the zone by its ID)
+    pub fn record_name(&self) -> Option<String> {
+        let (rr, domain) = match self {
+            DDNSProvider::Aliyun(value) => (&value.rr, &value.domain),
+            DDNSProvider::Cloudflare(value) => (&value.rr, &value.domain),
+            DDNSProvider::Dnspod(value) => (&value.rr, &value.domain),
+            DDNSProvider::Namecheap(value) => (&value.rr, &value.domain),
+            DDNSProvider::Rfc2136(value) => (&value.rr, &value.domain),
+            DDNSProvider::Custom(value) => (&value.name, &value.domain),
+            DDNSProvider::Dynv6(_) => return None,
+        };
+        match (rr.as_str(), domain.as_str()) {
+            (_, "") => None,
+            ("" | "@", domain) => Some(domain.to_string()),
+            (rr, domain) => Some(format!("{}.{}", rr, domain)),
+        }
+    }
+
+    /// Switch the record type, Namecheap (A only) and Custom (no record type) are left as is
+    pub fn set_record_type(&mut self, record_type: &str) {
+        match self {
+            DDNSProvider::Aliyun(value) => value.record_type = record_type.to_string(),
+            DDNSProvider::Cloudflare(value) => value.record_type = record_type.to_string(),
+            DDNSProvider::Dnspod(value) => value.record_type = record_type.to_string(),
+            DDNSProvider::Dynv6(value) => value.record_type = record_type.to_string(),
+            DDNSProvider::Rfc2136(value) => value.record_type = record_type.to_string(),
+            DDNSProvider::Namecheap(_) | DDNSProvider::Custom(_) => {}
+        }
+    }
+
+    pub fn to_provider(&self) -> Box<dyn DDNSProviderTrait> {
+        match self.clone() {
+            DDNSProvider::Aliyun(value) => Box::new(value),
+            DDNSProvider::Cloudflare(value) => Box::new(value),
+            DDNSProvider::Dnspod(value) => Box::new(value),
+            DDNSProvider::Dynv6(value) => Box::new(value),
+            DDNSProvider::Namecheap(value) => Box::new(value),
+            DDNSProvider::Rfc2136(value) => Box::new(value),
+            DDNSProvider::Custom(value) => Box::new(value),
+        }
     }
 }
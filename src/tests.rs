@@ -1,4 +1,10 @@
+pub mod cli;
 pub mod config;
 pub mod ddns_proviers;
+pub mod dns;
+pub mod handle;
 pub mod interdaces;
+pub mod mock;
 pub mod request;
+pub mod sources;
+pub mod state;
@@ -0,0 +1,43 @@
+use crate::mods::{
+    cli::{Cli, Command},
+    types::LogLevel,
+};
+
+fn parse(args: &[&str]) -> Result<Cli, String> {
+    Cli::parse(args.iter().map(|arg| arg.to_string()))
+}
+
+#[test]
+fn test_cli_parse() {
+    let cli = parse(&[]).unwrap();
+    assert_eq!(cli.command, Command::Run);
+    assert_eq!(cli.config_path, "./config.toml");
+
+    // the old single positional path still works
+    let cli = parse(&["/etc/ddns.toml"]).unwrap();
+    assert_eq!(cli.command, Command::Run);
+    assert_eq!(cli.config_path, "/etc/ddns.toml");
+
+    let cli = parse(&["once", "home", "office", "-n", "--config=/etc/ddns.toml"]).unwrap();
+    assert_eq!(
+        cli.command,
+        Command::Once(vec!["home".to_string(), "office".to_string()])
+    );
+    assert!(cli.dry_run);
+    assert_eq!(cli.config_path, "/etc/ddns.toml");
+
+    let cli = parse(&["-l", "debug", "set", "home", "203.0.113.7"]).unwrap();
+    assert_eq!(
+        cli.command,
+        Command::Set("home".to_string(), "203.0.113.7".to_string())
+    );
+    assert_eq!(cli.log_level, Some(LogLevel::Debug));
+
+    assert_eq!(parse(&["check", "--help"]).unwrap().command, Command::Help);
+    assert!(parse(&["get"]).is_err());
+    assert!(parse(&["set", "home"]).is_err());
+    assert!(parse(&["check", "extra"]).is_err());
+    assert!(parse(&["--log-level", "loud"]).is_err());
+    assert!(parse(&["--bogus"]).is_err());
+    assert!(parse(&["-c"]).is_err());
+}
@@ -1,7 +1,15 @@
 // cSpell:ignore ddns, chrono, Aliyun, Dynv6
+use std::collections::BTreeMap;
+
 use crate::mods::{
-    interfaces::AddressType,
+    config::validate::validate_config,
+    handle::{plan_reload, ReloadPlan},
+    interfaces::{AddressPolicy, AddressType},
     providers::{aliyun::Aliyun, dynv6::Dynv6},
+    sources::{
+        http::{HttpEndpoint, HttpSource},
+        IpSource,
+    },
     types::{Config, DDNSProvider, SiteConfig},
 };
 
@@ -29,6 +37,7 @@ async fn config() {
         retry_count: 0,
         retry_interval: 60,
         retry_on_failure: true,
+        ..SiteConfig::new()
     });
     config.sites_config.push(SiteConfig {
         name: "6e771d2e-8d06-25ff-1c84-1709a4ad8cb6".to_string(),
@@ -42,6 +51,7 @@ async fn config() {
         retry_count: 0,
         retry_interval: 60,
         retry_on_failure: true,
+        ..SiteConfig::new()
     });
     config.sites_config.push(SiteConfig::new());
 
@@ -49,3 +59,187 @@ async fn config() {
     let toml = toml::to_string(&config).unwrap();
     println!("toml: {}", toml);
 }
+
+#[tokio::test]
+async fn config_example() {
+    let config =
+        Config::new_from_path(&concat!(env!("CARGO_MANIFEST_DIR"), "/config.example.toml"))
+            .await
+            .unwrap();
+    assert!(!config.sites_config.is_empty());
+    assert_eq!(validate_config(&config), Ok(()));
+}
+
+#[tokio::test]
+async fn config_validate() {
+    let mut config = Config::new();
+    // disabled placeholders are not checked
+    config.sites_config.push(SiteConfig::new());
+    config.sites_config.push(SiteConfig {
+        name: "broken".to_string(),
+        provider: DDNSProvider::Dynv6(Dynv6::new(1, "", "bad..rr", "AAAA")),
+        interface: "".to_string(),
+        address_version: crate::mods::types::AddressVersion::V4,
+        interval: 0,
+        enabled: true,
+        ..SiteConfig::new()
+    });
+    config.sites_config.push(SiteConfig::new());
+    config.sites_config.push(SiteConfig {
+        name: "broken".to_string(),
+        provider: DDNSProvider::Dynv6(Dynv6::new(1, "token", "www", "A")),
+        interface: "eth0".to_string(),
+        address_policy: Some(AddressPolicy {
+            prefix: Some("2001:db8::/32".to_string()),
+            prefer_stable: true,
+            ..Default::default()
+        }),
+        interval: 600,
+        enabled: true,
+        ..SiteConfig::new()
+    });
+
+    let errors = validate_config(&config).unwrap_err();
+    let expected = [
+        "sites_config[1] (\"broken\"): interval:",
+        "sites_config[1] (\"broken\"): interface:",
+        "sites_config[1] (\"broken\"): provider.Dynv6.token:",
+        "sites_config[1] (\"broken\"): provider.Dynv6.record_type:",
+        "sites_config[1] (\"broken\"): provider.Dynv6.rr:",
+        "sites_config[3] (\"broken\"): name: duplicate site name",
+        "sites_config[3] (\"broken\"): address_policy.prefix: is an IPv6 prefix",
+        "sites_config[3] (\"broken\"): address_policy: only prefix applies",
+    ];
+    assert_eq!(errors.len(), expected.len(), "{:#?}", errors);
+    for prefix in expected {
+        assert!(
+            errors.iter().any(|e| e.starts_with(prefix)),
+            "{} not in {:#?}",
+            prefix,
+            errors
+        );
+    }
+}
+
+#[tokio::test]
+async fn config_reload_plan() {
+    let site = |name: &str, interval: u64, enabled: bool| SiteConfig {
+        name: name.to_string(),
+        provider: DDNSProvider::Dynv6(Dynv6::new(1, "token", "www", "A")),
+        interface: "eth0".to_string(),
+        interval,
+        enabled,
+        ..SiteConfig::new()
+    };
+    let mut running = BTreeMap::new();
+    for (name, interval) in [
+        ("kept", 600),
+        ("changed", 600),
+        ("removed", 600),
+        ("disabled", 600),
+    ] {
+        running.insert(name.to_string(), site(name, interval, true));
+    }
+
+    let mut config = Config::new();
+    config.sites_config.push(site("kept", 600, true));
+    config.sites_config.push(site("changed", 300, true));
+    config.sites_config.push(site("disabled", 600, false));
+    config.sites_config.push(site("added", 600, true));
+
+    assert_eq!(
+        plan_reload(&running, &config),
+        ReloadPlan {
+            stop: vec![
+                "changed".to_string(),
+                "disabled".to_string(),
+                "removed".to_string()
+            ],
+            start: vec!["changed".to_string(), "added".to_string()],
+        }
+    );
+}
+
+#[tokio::test]
+async fn config_dual_stack() {
+    let config: Config = toml::from_str(
+        r#"
+        log_level = "info"
+
+        [[sites_config]]
+        name = "home"
+        interface = "eth0"
+        index = 0
+        address_version = "V4"
+        address_type = "Public"
+        interval = 600
+        enabled = true
+        retry_count = 0
+        retry_interval = 60
+        retry_on_failure = true
+
+        [sites_config.dual_stack]
+        index = 1
+        address_type = "Public"
+        count = 0
+
+        [sites_config.provider.Dynv6]
+        zone_id = 1
+        token = "token"
+        rr = "www"
+        record_type = "A"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(validate_config(&config), Ok(()));
+
+    let families = config.sites_config[0].families();
+    assert_eq!(families.len(), 2);
+    assert_eq!(families[0].name, "home");
+    assert!(families[0].optional && families[1].optional);
+    assert_eq!(families[1].name, "home/v6");
+    assert_eq!(families[1].interface, "eth0");
+    assert_eq!(families[1].index, 1);
+    assert_eq!(families[1].count, 0);
+    assert!(families[1].address_version == crate::mods::types::AddressVersion::V6);
+    match &families[1].provider {
+        DDNSProvider::Dynv6(provider) => assert_eq!(provider.record_type, "AAAA"),
+        _ => panic!("provider changed"),
+    }
+    match &families[0].provider {
+        DDNSProvider::Dynv6(provider) => assert_eq!(provider.record_type, "A"),
+        _ => panic!("provider changed"),
+    }
+
+    // Custom templates have no record type, both families would fight over one record
+    let mut config = config;
+    config.sites_config[0].provider = DDNSProvider::new();
+    let errors = validate_config(&config).unwrap_err();
+    assert!(
+        errors
+            .iter()
+            .any(|e| e.starts_with("sites_config[0] (\"home\"): dual_stack: Custom")),
+        "{:#?}",
+        errors
+    );
+    // the family source gets the same checks as the site's
+    let mut endpoint = HttpEndpoint::new("https://api64.ipify.org", None);
+    endpoint.regex = Some("(".to_string());
+    if let Some(family) = config.sites_config[0].dual_stack.as_mut() {
+        family.count = 1;
+        family.source = IpSource::Http(HttpSource::new(vec![endpoint], 2));
+    }
+    let errors = validate_config(&config).unwrap_err();
+    for field in [
+        "dual_stack.source.Http.consensus",
+        "dual_stack.source.Http.endpoints[0].regex",
+    ] {
+        let prefix = format!("sites_config[0] (\"home\"): {}:", field);
+        assert!(
+            errors.iter().any(|e| e.starts_with(&prefix)),
+            "{} not in {:#?}",
+            prefix,
+            errors
+        );
+    }
+}
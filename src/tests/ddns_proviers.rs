@@ -9,3 +9,457 @@
 //         println!("rst: {:?}", rst)
 //     }
 // }
+
+use std::net::Ipv4Addr;
+
+use crate::mods::dns::{RecordData, TYPE_A};
+use crate::mods::providers::{
+    cloudflare::Cloudflare,
+    custom::{extract_value, Custom, CustomQuery, CustomRequest},
+    dnspod::{tc3_authorization, Dnspod, DnspodApi},
+    namecheap::{parse_update_response, Namecheap},
+    rfc2136::{tsig_sign, tsig_verify, Rfc2136, TsigAlgorithm, TsigKey},
+    types::{ProvidersErrorType, RecordEntry, RecordSetPlan},
+    DDNSProviderTrait,
+};
+use crate::tests::mock::{spawn_dns_stub, spawn_http_stub, spawn_rfc2136_stub};
+
+#[tokio::test]
+async fn test_cloudflare_ddns() {
+    // one stale record, updated in place
+    let (base_url, requests) = spawn_http_stub(|request| match request.method.as_str() {
+        "GET" => {
+            assert_eq!(
+                request.path,
+                "/zones/zone/dns_records?type=A&name=www.example.com"
+            );
+            (
+                200,
+                r#"{"success":true,"errors":[],"result":[{"id":"rec1","type":"A","name":"www.example.com","content":"1.1.1.1","ttl":1,"proxied":false}]}"#.to_string(),
+            )
+        }
+        "PATCH" => (
+            200,
+            r#"{"success":true,"errors":[],"result":{"id":"rec1","type":"A","name":"www.example.com","content":"2.2.2.2","ttl":600,"proxied":true}}"#.to_string(),
+        ),
+        _ => (404, String::new()),
+    })
+    .await;
+    let mut cloudflare =
+        Cloudflare::new("zone", "token", "example.com", "www", Some(600), true, "A");
+    cloudflare.api_base = Some(base_url);
+    assert_eq!(cloudflare.get_ip_address().await.unwrap(), "1.1.1.1");
+    cloudflare.update("2.2.2.2").await.unwrap();
+    let requests = requests.lock().unwrap().clone();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].method, "PATCH");
+    assert_eq!(requests[1].path, "/zones/zone/dns_records/rec1");
+    let body: serde_json::Value = serde_json::from_str(&requests[1].body).unwrap();
+    assert_eq!(body["content"], "2.2.2.2");
+    assert_eq!(body["ttl"], 600);
+    assert_eq!(body["proxied"], true);
+
+    // missing record is created
+    let (base_url, requests) = spawn_http_stub(|request| match request.method.as_str() {
+        "GET" => (
+            200,
+            r#"{"success":true,"errors":[],"result":[]}"#.to_string(),
+        ),
+        "POST" => (
+            200,
+            r#"{"success":true,"errors":[],"result":{"id":"rec2","content":"2.2.2.2"}}"#
+                .to_string(),
+        ),
+        _ => (404, String::new()),
+    })
+    .await;
+    let mut cloudflare = Cloudflare::new("zone", "token", "example.com", "@", None, false, "A");
+    cloudflare.api_base = Some(base_url);
+    assert!(matches!(
+        cloudflare.get_ip_address().await,
+        Err(ProvidersErrorType::NoRecordFound)
+    ));
+    cloudflare.update("2.2.2.2").await.unwrap();
+    let requests = requests.lock().unwrap().clone();
+    assert_eq!(requests[1].method, "POST");
+    let body: serde_json::Value = serde_json::from_str(&requests[1].body).unwrap();
+    assert_eq!(body["name"], "example.com");
+    assert_eq!(body["ttl"], 1);
+
+    // authentication errors are reported from the envelope
+    let (base_url, _) = spawn_http_stub(|_| {
+        (
+            403,
+            r#"{"success":false,"errors":[{"code":10000,"message":"Authentication error"}],"result":null}"#.to_string(),
+        )
+    })
+    .await;
+    let mut cloudflare = Cloudflare::new("zone", "bad", "example.com", "www", None, false, "A");
+    cloudflare.api_base = Some(base_url);
+    assert!(matches!(
+        cloudflare.get_ip_address().await,
+        Err(ProvidersErrorType::KeyError)
+    ));
+}
+
+#[tokio::test]
+async fn test_dnspod_ddns() {
+    // legacy token api
+    let (base_url, requests) = spawn_http_stub(|request| match request.path.as_str() {
+        "/Record.List" => (
+            200,
+            r#"{"status":{"code":"1","message":"Action completed successful"},"records":[{"id":"42","name":"www","type":"A","value":"1.1.1.1","line":"默认"}]}"#.to_string(),
+        ),
+        "/Record.Ddns" => (
+            200,
+            r#"{"status":{"code":"1","message":"Action completed successful"},"record":{"id":42,"name":"www","value":"2.2.2.2"}}"#.to_string(),
+        ),
+        _ => (404, String::new()),
+    })
+    .await;
+    let mut dnspod = Dnspod::new(
+        DnspodApi::Legacy,
+        "13490",
+        "token",
+        "example.com",
+        "www",
+        None,
+        "A",
+    );
+    dnspod.endpoint = Some(base_url);
+    assert_eq!(dnspod.get_ip_address().await.unwrap(), "1.1.1.1");
+    dnspod.update("2.2.2.2").await.unwrap();
+    let requests = requests.lock().unwrap().clone();
+    assert!(requests[0]
+        .body
+        .starts_with("login_token=13490%2Ctoken&format=json"));
+    assert!(requests[1].body.contains("&record_id=42&"));
+    assert!(requests[1].body.contains("&value=2.2.2.2"));
+
+    // TencentCloud API 3.0
+    let (base_url, requests) = spawn_http_stub(|request| {
+        match request.headers.get("x-tc-action").map(|v| v.as_str()) {
+            Some("DescribeRecordList") => (
+                200,
+                r#"{"Response":{"Error":{"Code":"ResourceNotFound.NoDataOfRecord","Message":"记录列表为空。"},"RequestId":"1"}}"#.to_string(),
+            ),
+            Some("CreateRecord") => (200, r#"{"Response":{"RecordId":162,"RequestId":"2"}}"#.to_string()),
+            _ => (404, String::new()),
+        }
+    })
+    .await;
+    let mut dnspod = Dnspod::new(
+        DnspodApi::TencentCloud,
+        "AKIDtest",
+        "secret",
+        "example.com",
+        "@",
+        Some(600),
+        "AAAA",
+    );
+    dnspod.endpoint = Some(base_url);
+    assert!(matches!(
+        dnspod.get_ip_address().await,
+        Err(ProvidersErrorType::NoRecordFound)
+    ));
+    dnspod.update("2001:db8::1").await.unwrap();
+    let requests = requests.lock().unwrap().clone();
+    assert_eq!(requests[1].headers["x-tc-version"], "2021-03-23");
+    assert!(
+        requests[1].headers["authorization"].starts_with("TC3-HMAC-SHA256 Credential=AKIDtest/")
+    );
+    let body: serde_json::Value = serde_json::from_str(&requests[1].body).unwrap();
+    assert_eq!(body["SubDomain"], "@");
+    assert_eq!(body["TTL"], 600);
+
+    // signature matches the reference algorithm
+    assert_eq!(
+        tc3_authorization(
+            "AKIDtest",
+            "secret",
+            "dnspod.tencentcloudapi.com",
+            r#"{"Domain":"example.com"}"#,
+            1700000000
+        ),
+        "TC3-HMAC-SHA256 Credential=AKIDtest/2023-11-14/dnspod/tc3_request, SignedHeaders=content-type;host, Signature=ea5cbc70c0e68ef1ab057fec5b3dd545f17dbd1b473d39d41c17cfd9e41a96c2"
+    );
+}
+
+#[tokio::test]
+async fn test_namecheap_ddns() {
+    let (base_url, requests) = spawn_http_stub(|request| {
+        if request.path.contains("password=secret") {
+            (
+                200,
+                r#"<?xml version="1.0" encoding="utf-16"?><interface-response><Command>SETDNSHOST</Command><Language>eng</Language><IP>2.2.2.2</IP><ErrCount>0</ErrCount><errors /><ResponseCount>0</ResponseCount><Done>true</Done><debug><![CDATA[]]></debug></interface-response>"#.to_string(),
+            )
+        } else {
+            (
+                200,
+                r#"<?xml version="1.0" encoding="utf-16"?><interface-response><Command>SETDNSHOST</Command><Language>eng</Language><ErrCount>1</ErrCount><errors><Err1>Passwords do not match</Err1></errors><ResponseCount>1</ResponseCount><Done>true</Done></interface-response>"#.to_string(),
+            )
+        }
+    })
+    .await;
+
+    // without a cached value the current address comes from DNS
+    let (nameserver, _) = spawn_dns_stub(
+        vec![(
+            "example.com",
+            TYPE_A,
+            RecordData::A(Ipv4Addr::new(8, 8, 8, 8)),
+        )],
+        false,
+    )
+    .await;
+    let mut namecheap = Namecheap::new("example.com", "@", "secret");
+    namecheap.endpoint = Some(format!("{}/update", base_url));
+    namecheap.nameservers = vec![nameserver];
+    assert_eq!(namecheap.get_ip_address().await.unwrap(), "8.8.8.8");
+    namecheap.update("2.2.2.2").await.unwrap();
+    assert_eq!(namecheap.get_ip_address().await.unwrap(), "2.2.2.2");
+    assert_eq!(
+        requests.lock().unwrap()[0].path,
+        "/update?host=%40&domain=example.com&password=secret&ip=2.2.2.2"
+    );
+
+    let mut namecheap = Namecheap::new("example.com", "www", "wrong");
+    namecheap.endpoint = Some(format!("{}/update", base_url));
+    assert!(namecheap.update("2.2.2.2").await.is_err());
+
+    assert!(matches!(
+        parse_update_response("<interface-response><ErrCount>1</ErrCount><errors><Err1>No Records updated. A record not Found;</Err1></errors></interface-response>"),
+        Err(ProvidersErrorType::NoRecordFound)
+    ));
+    assert!(matches!(
+        parse_update_response("<html>Bad Gateway</html>"),
+        Err(ProvidersErrorType::OtherError)
+    ));
+}
+
+#[tokio::test]
+async fn test_custom_ddns() {
+    let (base_url, requests) = spawn_http_stub(|request| match request.method.as_str() {
+        "GET" => (
+            200,
+            r#"{"result":[{"name":"www","content":"1.1.1.1"}]}"#.to_string(),
+        ),
+        "PUT" => (200, r#"{"ok":true}"#.to_string()),
+        _ => (404, String::new()),
+    })
+    .await;
+
+    let mut update = CustomRequest {
+        method: "PUT".to_string(),
+        url: format!("{}/zones/{{domain}}/records/{{name}}", base_url),
+        body: r#"{"content":"{ip}"}"#.to_string(),
+        success_body: Some(r#""ok":true"#.to_string()),
+        ..Default::default()
+    };
+    update
+        .headers
+        .insert("X-Record".to_string(), "{name}.{domain}".to_string());
+    let query = CustomQuery {
+        request: CustomRequest {
+            method: "GET".to_string(),
+            url: format!("{}/zones/{{domain}}/records/{{name}}", base_url),
+            ..Default::default()
+        },
+        json_path: Some("$.result[0].content".to_string()),
+        regex: None,
+    };
+    let mut custom = Custom::new("example.com", "www", update, Some(query));
+    assert_eq!(custom.get_ip_address().await.unwrap(), "1.1.1.1");
+    custom.update("2.2.2.2").await.unwrap();
+    let sent = requests.lock().unwrap().clone();
+    assert_eq!(sent[1].path, "/zones/example.com/records/www");
+    assert_eq!(sent[1].headers["x-record"], "www.example.com");
+    assert_eq!(sent[1].body, r#"{"content":"2.2.2.2"}"#);
+
+    // values are percent-encoded in the URL only
+    let mut update = CustomRequest {
+        method: "PUT".to_string(),
+        url: format!("{}/update?host={{name}}&ip={{ip}}", base_url),
+        body: r#"{"content":"{ip}"}"#.to_string(),
+        ..Default::default()
+    };
+    update
+        .headers
+        .insert("X-Record".to_string(), "{name}".to_string());
+    let mut custom = Custom::new("example.com", "@", update, None);
+    custom.update("fe80::1%eth0").await.unwrap();
+    let request = requests.lock().unwrap().last().unwrap().clone();
+    assert_eq!(request.path, "/update?host=%40&ip=fe80%3A%3A1%25eth0");
+    assert_eq!(request.headers["x-record"], "@");
+    assert_eq!(request.body, r#"{"content":"fe80::1%eth0"}"#);
+
+    // success criteria are enforced
+    let update = CustomRequest {
+        method: "PUT".to_string(),
+        url: format!("{}/update", base_url),
+        success_status: vec![204],
+        ..Default::default()
+    };
+    let mut custom = Custom::new("example.com", "www", update, None);
+    assert!(custom.update("2.2.2.2").await.is_err());
+
+    assert_eq!(
+        extract_value("good 9.9.9.9\n", None, Some(r"(\d+\.\d+\.\d+\.\d+)")).unwrap(),
+        Some("9.9.9.9".to_string())
+    );
+    assert_eq!(
+        extract_value(r#"{"a":{"b":[null]}}"#, Some("a.b.0"), None).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn test_record_set_plan() {
+    let wanted = vec!["2001:db8::1".to_string(), "2001:db8::2".to_string()];
+    let records = vec![
+        RecordEntry::new("r1", "2001:db8::1"),
+        RecordEntry::new("r2", "2001:db8::1"),
+        RecordEntry::new("r3", "2001:db8::9"),
+        RecordEntry::new("r4", "2001:db8::8"),
+    ];
+    let plan = RecordSetPlan::new(&records, &wanted);
+    assert_eq!(
+        plan.change,
+        vec![(records[1].clone(), "2001:db8::2".to_string())]
+    );
+    assert!(plan.add.is_empty());
+    assert_eq!(plan.remove, vec![records[2].clone(), records[3].clone()]);
+
+    let plan = RecordSetPlan::new(&[], &wanted);
+    assert_eq!(plan.add, wanted);
+    assert!(RecordSetPlan::new(&records[..1], &wanted[..1]).is_empty());
+}
+
+#[tokio::test]
+async fn test_cloudflare_record_set() {
+    let (base_url, requests) = spawn_http_stub(|request| match request.method.as_str() {
+        "GET" => (
+            200,
+            r#"{"success":true,"errors":[],"result":[{"id":"rec1","content":"2001:db8::1"},{"id":"rec2","content":"2001:db8::9"},{"id":"rec3","content":"2001:db8::8"}]}"#.to_string(),
+        ),
+        _ => (
+            200,
+            r#"{"success":true,"errors":[],"result":{"id":"new"}}"#.to_string(),
+        ),
+    })
+    .await;
+    let mut cloudflare =
+        Cloudflare::new("zone", "token", "example.com", "www", None, false, "AAAA");
+    cloudflare.api_base = Some(base_url);
+
+    let records = cloudflare.get_record_set().await.unwrap();
+    assert_eq!(records.len(), 3);
+    let wanted = vec!["2001:db8::1".to_string(), "2001:db8::2".to_string()];
+    cloudflare
+        .update_set(&RecordSetPlan::new(&records, &wanted))
+        .await
+        .unwrap();
+
+    let requests = requests.lock().unwrap().clone();
+    let calls = requests
+        .iter()
+        .skip(1)
+        .map(|request| format!("{} {}", request.method, request.path))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        calls,
+        vec![
+            "PATCH /zones/zone/dns_records/rec2",
+            "DELETE /zones/zone/dns_records/rec3"
+        ]
+    );
+    let body: serde_json::Value = serde_json::from_str(&requests[1].body).unwrap();
+    assert_eq!(body["content"], "2001:db8::2");
+}
+
+#[tokio::test]
+async fn test_rfc2136_ddns() {
+    let secret = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+    let key = TsigKey::new("ddns-key", TsigAlgorithm::HmacSha256, secret);
+
+    // known answer, MAC computed independently over the message and the TSIG variables
+    let provider = Rfc2136::new("127.0.0.1", "example.com", "www", "A");
+    let message = provider.update_message(0x1234, "1.1.1.1").unwrap();
+    let (signed, mac) = tsig_sign(&message, &key, 1700000000, None).unwrap();
+    let mac_hex = mac.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    assert_eq!(
+        mac_hex,
+        "f354e74aabeb2662ec4121f61f47f85449aa2b1ed353c8013bcbabd7320483ed"
+    );
+    assert_eq!(&signed[..10], &message[..10]);
+    assert_eq!(&signed[10..12], &[0, 1]);
+    // the signature checks out but is far too old
+    let e = tsig_verify(&signed, &key, None).unwrap_err();
+    assert!(e.contains("BADTIME"), "{}", e);
+    let mut tampered = signed.clone();
+    tampered[message.len() - 1] = 2;
+    let e = tsig_verify(&tampered, &key, None).unwrap_err();
+    assert!(e.contains("BADSIG"), "{}", e);
+
+    // update and read back through a fake primary
+    let (server, zone) = spawn_rfc2136_stub(Some(key.clone())).await;
+    let mut provider = Rfc2136::new(&server, "example.com", "www", "A");
+    provider.tsig = Some(key.clone());
+    assert!(matches!(
+        provider.get_ip_address().await,
+        Err(ProvidersErrorType::NoRecordFound)
+    ));
+    zone.lock().unwrap().extend([
+        (
+            "www.example.com".to_string(),
+            1,
+            RecordData::A(Ipv4Addr::new(8, 8, 8, 8)),
+        ),
+        (
+            "www.example.com".to_string(),
+            1,
+            RecordData::A(Ipv4Addr::new(9, 9, 9, 9)),
+        ),
+    ]);
+    assert_eq!(provider.get_ip_address().await.unwrap(), "8.8.8.8,9.9.9.9");
+    provider.update("1.1.1.1").await.unwrap();
+    assert_eq!(provider.get_ip_address().await.unwrap(), "1.1.1.1");
+    assert_eq!(zone.lock().unwrap().len(), 1);
+    assert!(provider.update("2606:4700::1111").await.is_err());
+
+    // a wrong key is refused
+    provider.tsig = Some(TsigKey::new(
+        "ddns-key",
+        TsigAlgorithm::HmacSha256,
+        "d3Jvbmcgc2VjcmV0",
+    ));
+    let e = provider.update("9.9.9.9").await.unwrap_err();
+    assert!(e.contains("NOTAUTH"), "{}", e);
+    assert!(matches!(
+        provider.get_ip_address().await,
+        Err(ProvidersErrorType::KeyError)
+    ));
+    // and so is an unsigned update
+    provider.tsig = None;
+    assert!(provider.update("9.9.9.9").await.is_err());
+    assert_eq!(
+        zone.lock().unwrap()[0].2,
+        RecordData::A(Ipv4Addr::new(1, 1, 1, 1))
+    );
+
+    // no answer at all is a network problem, not a key one
+    let closed = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut provider = Rfc2136::new(
+        &closed.local_addr().unwrap().to_string(),
+        "example.com",
+        "www",
+        "A",
+    );
+    drop(closed);
+    provider.tsig = Some(key.clone());
+    provider.timeout = 1;
+    assert!(matches!(
+        provider.get_ip_address().await,
+        Err(ProvidersErrorType::NetworkError)
+    ));
+}
@@ -0,0 +1,300 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::sync::watch;
+
+use crate::mods::{
+    dns::{
+        authoritative_servers, build_query, parse_message, parse_resolv_conf, DnsLookup,
+        PropagationCheck, RecordData, TYPE_A, TYPE_AAAA, TYPE_CNAME, TYPE_NS,
+    },
+    handle::{update_site, SiteRunner},
+    providers::custom::{Custom, CustomRequest},
+    sources::{command::CommandSource, IpSource},
+    statics::STATE,
+    types::{AddressVersion, DDNSProvider, SiteConfig},
+};
+use crate::tests::mock::{spawn_dns_stub, spawn_http_stub, STATE_LOCK};
+
+fn zone() -> Vec<(&'static str, u16, RecordData)> {
+    vec![
+        (
+            "example.com",
+            TYPE_NS,
+            RecordData::Name("ns1.example.com".to_string()),
+        ),
+        (
+            "ns1.example.com",
+            TYPE_A,
+            RecordData::A(Ipv4Addr::new(127, 0, 0, 53)),
+        ),
+        (
+            "home.example.com",
+            TYPE_A,
+            RecordData::A(Ipv4Addr::new(1, 1, 1, 1)),
+        ),
+        (
+            "www.example.com",
+            TYPE_CNAME,
+            RecordData::Name("home.example.com".to_string()),
+        ),
+        (
+            "v6.example.com",
+            TYPE_AAAA,
+            RecordData::Aaaa("2606:4700::1111".parse().unwrap()),
+        ),
+        (
+            "xn--bcher-kva.example.com",
+            TYPE_A,
+            RecordData::A(Ipv4Addr::new(2, 2, 2, 2)),
+        ),
+    ]
+}
+
+#[test]
+fn test_dns_message() {
+    let query = build_query(0x1234, "www.example.com.", TYPE_AAAA, true).unwrap();
+    assert_eq!(query[..4], [0x12, 0x34, 0x01, 0x00]);
+    assert_eq!(query[12..17], [3, b'w', b'w', b'w', 7]);
+    assert_eq!(query[query.len() - 4..], [0, 28, 0, 1]);
+    assert!(build_query(1, "bad..name", TYPE_A, true).is_err());
+    // internationalized names go out in their ASCII form
+    let idn = build_query(1, "Bücher.example", TYPE_A, true).unwrap();
+    assert_eq!(idn[12..26], *b"\x0dxn--bcher-kva");
+
+    // the answer refers to the question name with a compression pointer
+    let mut response = query.clone();
+    response[2..4].copy_from_slice(&[0x81, 0x80]);
+    response[6..8].copy_from_slice(&[0, 1]);
+    response.extend_from_slice(&[0xc0, 12, 0, 28, 0, 1, 0, 0, 0, 60, 0, 16]);
+    response.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+    let message = parse_message(&response).unwrap();
+    assert_eq!(message.id, 0x1234);
+    assert!(!message.truncated);
+    assert_eq!(message.answers[0].name, "www.example.com");
+    assert_eq!(message.answers[0].ttl, 60);
+    assert_eq!(
+        message.answers[0].data,
+        RecordData::Aaaa("2001:db8::1".parse().unwrap())
+    );
+    // pointers must go backwards, a loop is rejected
+    let start = response.len() - 28;
+    response[start + 1] = start as u8;
+    assert!(parse_message(&response).is_err());
+
+    assert_eq!(
+        parse_resolv_conf("# comment\nnameserver 192.0.2.53\nsearch lan\nnameserver ::1\n"),
+        vec![
+            "192.0.2.53:53".parse::<SocketAddr>().unwrap(),
+            "[::1]:53".parse().unwrap()
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_dns_lookup() {
+    let (server, questions) = spawn_dns_stub(zone(), false).await;
+    let lookup = DnsLookup::new(vec![server.clone()]);
+    let v4 = AddressVersion::V4;
+    assert_eq!(
+        lookup.lookup("home.example.com", &v4).await.unwrap(),
+        vec!["1.1.1.1"]
+    );
+    assert_eq!(
+        lookup.lookup("www.example.com", &v4).await.unwrap(),
+        vec!["1.1.1.1"]
+    );
+    assert_eq!(
+        lookup
+            .lookup("v6.example.com", &AddressVersion::V6)
+            .await
+            .unwrap(),
+        vec!["2606:4700::1111"]
+    );
+    // no AAAA record and no such name both mean nothing is published
+    assert!(lookup
+        .lookup("home.example.com", &AddressVersion::V6)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(lookup
+        .lookup("nx.example.com", &v4)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        lookup.lookup("bücher.example.com", &v4).await.unwrap(),
+        vec!["2.2.2.2"]
+    );
+    assert!(questions.lock().unwrap().iter().all(|(_, _, tcp)| !tcp));
+
+    // truncated answers are asked again over TCP
+    let (truncating, questions) = spawn_dns_stub(zone(), true).await;
+    let lookup = DnsLookup::new(vec![truncating]);
+    assert_eq!(
+        lookup.lookup("home.example.com", &v4).await.unwrap(),
+        vec!["1.1.1.1"]
+    );
+    assert_eq!(
+        *questions.lock().unwrap(),
+        vec![
+            ("home.example.com".to_string(), TYPE_A, false),
+            ("home.example.com".to_string(), TYPE_A, true)
+        ]
+    );
+
+    // the zone is found by walking up from the record name
+    let (resolver, questions) = spawn_dns_stub(zone(), false).await;
+    let resolvers = vec![resolver.parse().unwrap()];
+    assert_eq!(
+        authoritative_servers("a.b.example.com", &resolvers, Duration::from_secs(2))
+            .await
+            .unwrap(),
+        vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 53))]
+    );
+    let asked = questions
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, _, _)| name.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        asked,
+        vec!["a.b.example.com", "b.example.com", "example.com"]
+    );
+
+    let mut silent = DnsLookup::new(vec!["127.0.0.1:9".to_string()]);
+    silent.timeout = 1;
+    assert!(silent.lookup("home.example.com", &v4).await.is_err());
+}
+
+#[tokio::test]
+async fn test_dns_lookup_update() {
+    let _guard = STATE_LOCK.lock().await;
+    let (server, questions) = spawn_dns_stub(zone(), false).await;
+    let (base_url, updates) = spawn_http_stub(|_| (200, String::new())).await;
+    let mut site = SiteConfig {
+        name: "lookup-test".to_string(),
+        provider: DDNSProvider::Custom(Custom::new(
+            "example.com",
+            "home",
+            CustomRequest {
+                method: "GET".to_string(),
+                url: format!("{}/update?ip={{ip}}", base_url),
+                ..Default::default()
+            },
+            None,
+        )),
+        source: IpSource::Command(CommandSource::new(vec![
+            "echo".to_string(),
+            "1.1.1.1".to_string(),
+        ])),
+        lookup: Some(DnsLookup::new(vec![server])),
+        ..SiteConfig::new()
+    };
+    let mut provider = site.provider.to_provider();
+
+    // the record already has the address: no update is sent
+    update_site(&site, provider.as_mut()).await.unwrap();
+    assert!(updates.lock().unwrap().is_empty());
+    assert_eq!(questions.lock().unwrap()[0].0, "home.example.com");
+
+    site.source = IpSource::Command(CommandSource::new(vec![
+        "echo".to_string(),
+        "8.8.8.8".to_string(),
+    ]));
+    update_site(&site, provider.as_mut()).await.unwrap();
+    assert_eq!(updates.lock().unwrap()[0].path, "/update?ip=8.8.8.8");
+}
+
+#[tokio::test]
+async fn test_propagation_check() {
+    let _guard = STATE_LOCK.lock().await;
+    let (server, questions) = spawn_dns_stub(zone(), false).await;
+    let mut check = PropagationCheck::new(DnsLookup::new(vec![server.clone()]));
+    check.deadline = 1;
+    check.poll_interval = 1;
+    let v4 = AddressVersion::V4;
+    assert!(check
+        .wait_for("home.example.com", &v4, &["1.1.1.1".to_string()])
+        .await
+        .is_ok());
+    // a stale second value is not the expected record
+    let error = check
+        .wait_for(
+            "home.example.com",
+            &v4,
+            &["1.1.1.1".to_string(), "8.8.8.8".to_string()],
+        )
+        .await
+        .unwrap_err();
+    assert!(error.contains("still serves [\"1.1.1.1\"]"));
+    assert_eq!(questions.lock().unwrap().len(), 2);
+
+    let (base_url, updates) = spawn_http_stub(|_| (200, String::new())).await;
+    let mut site = SiteConfig {
+        name: "verify-test".to_string(),
+        provider: DDNSProvider::Custom(Custom::new(
+            "example.com",
+            "home",
+            CustomRequest {
+                method: "GET".to_string(),
+                url: format!("{}/update?ip={{ip}}", base_url),
+                ..Default::default()
+            },
+            None,
+        )),
+        source: IpSource::Command(CommandSource::new(vec![
+            "echo".to_string(),
+            "1.1.1.1".to_string(),
+        ])),
+        verify: Some(check.clone()),
+        ..SiteConfig::new()
+    };
+    // no read API and no lookup: the first round always updates, then waits for DNS
+    let mut provider = site.provider.to_provider();
+    update_site(&site, provider.as_mut()).await.unwrap();
+    assert_eq!(updates.lock().unwrap().len(), 1);
+    assert_eq!(
+        STATE.lock().await.site("verify-test").last_propagation,
+        Some(0)
+    );
+
+    // never served: only a warning, unless asked to fail
+    site.source = IpSource::Command(CommandSource::new(vec![
+        "echo".to_string(),
+        "8.8.8.8".to_string(),
+    ]));
+    update_site(&site, provider.as_mut()).await.unwrap();
+    assert_eq!(
+        STATE.lock().await.site("verify-test").last_propagation,
+        None
+    );
+    site.source = IpSource::Command(CommandSource::new(vec![
+        "echo".to_string(),
+        "9.9.9.9".to_string(),
+    ]));
+    check.fail_on_timeout = true;
+    site.verify = Some(check.clone());
+    assert!(update_site(&site, provider.as_mut())
+        .await
+        .unwrap_err()
+        .contains("did not propagate"));
+    assert_eq!(updates.lock().unwrap().len(), 3);
+
+    // stopping the site does not wait for the deadline
+    check.deadline = 300;
+    site.verify = Some(check);
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let mut runner = SiteRunner::new(&site).await.with_stop(stop_rx);
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let _ = stop_tx.send(true);
+    });
+    let results = tokio::time::timeout(Duration::from_secs(5), runner.run_round())
+        .await
+        .unwrap();
+    assert!(results[0].1.is_ok());
+    assert_eq!(updates.lock().unwrap().len(), 4);
+}
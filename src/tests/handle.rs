@@ -0,0 +1,226 @@
+use std::time::Duration;
+
+use crate::mods::{
+    handle::{
+        backoff_delay, reconcile_tasks, shutdown_tasks, site_statuses, update_site, SiteRunner,
+        TaskState, MAX_BACKOFF,
+    },
+    interfaces::AddressType,
+    providers::{
+        cloudflare::Cloudflare,
+        custom::{Custom, CustomRequest},
+        dynv6::Dynv6,
+    },
+    sources::{
+        http::{HttpEndpoint, HttpSource},
+        IpSource,
+    },
+    statics::STATE,
+    types::{Config, DDNSProvider, FamilyConfig, SiteConfig, TargetConfig},
+};
+use crate::tests::mock::{spawn_http_stub, STATE_LOCK};
+
+#[test]
+fn test_backoff_delay() {
+    let base = Duration::from_secs(60);
+    assert_eq!(backoff_delay(base, 0), Duration::from_secs(60));
+    assert_eq!(backoff_delay(base, 1), Duration::from_secs(120));
+    assert_eq!(backoff_delay(base, 3), Duration::from_secs(480));
+    assert_eq!(backoff_delay(base, 100), MAX_BACKOFF);
+}
+
+#[tokio::test]
+async fn test_supervisor() {
+    let _guard = STATE_LOCK.lock().await;
+    let (base_url, _) = spawn_http_stub(|_| (500, String::new())).await;
+
+    let mut config = Config::new();
+    config.sites_config.push(SiteConfig {
+        name: "supervised".to_string(),
+        provider: DDNSProvider::Dynv6(Dynv6::new(1, "token", "www", "A")),
+        source: IpSource::Http(HttpSource::new(vec![HttpEndpoint::new(&base_url, None)], 1)),
+        interval: 600,
+        enabled: true,
+        retry_on_failure: false,
+        retry_interval: 600,
+        ..SiteConfig::new()
+    });
+    reconcile_tasks(&config).await;
+
+    // the task gives up after the first failure and the supervisor schedules a restart
+    let mut status = None;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        status = site_statuses().await.remove("supervised");
+        if let Some(TaskState::BackingOff { .. }) = status.as_ref().map(|s| &s.state) {
+            break;
+        }
+    }
+    let status = status.unwrap();
+    assert!(matches!(status.state, TaskState::BackingOff { .. }));
+    assert_eq!(status.restarts, 1);
+    assert!(status
+        .last_error
+        .unwrap()
+        .contains("Failed to get IP address"));
+
+    // stopping does not wait for the backoff
+    assert!(shutdown_tasks(Duration::from_secs(5)).await);
+    assert!(site_statuses().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_dry_run() {
+    let _guard = STATE_LOCK.lock().await;
+    let (source_url, _) = spawn_http_stub(|_| (200, "2.2.2.2".to_string())).await;
+    // two records: a real run would delete the duplicate and patch the first one
+    let (base_url, requests) = spawn_http_stub(|request| match request.method.as_str() {
+        "GET" => (
+            200,
+            r#"{"success":true,"errors":[],"result":[{"id":"rec1","content":"1.1.1.1"},{"id":"rec2","content":"3.3.3.3"}]}"#.to_string(),
+        ),
+        _ => (200, r#"{"success":true,"errors":[],"result":{}}"#.to_string()),
+    })
+    .await;
+    let mut cloudflare = Cloudflare::new("zone", "token", "example.com", "www", None, false, "A");
+    cloudflare.api_base = Some(base_url);
+
+    let site = SiteConfig {
+        name: "dry-run-test".to_string(),
+        provider: DDNSProvider::Cloudflare(cloudflare),
+        source: IpSource::Http(HttpSource::new(
+            vec![HttpEndpoint::new(&source_url, None)],
+            1,
+        )),
+        dry_run: true,
+        ..SiteConfig::new()
+    };
+    let mut provider = site.provider.to_provider();
+    update_site(&site, provider.as_mut()).await.unwrap();
+
+    let requests = requests.lock().unwrap().clone();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "GET");
+    assert_eq!(STATE.lock().await.site("dry-run-test").last_ip, None);
+}
+
+#[tokio::test]
+async fn test_dual_stack_absent_family() {
+    let _guard = STATE_LOCK.lock().await;
+    // the echo service only knows an IPv4 address
+    let (source_url, _) = spawn_http_stub(|_| (200, "203.0.113.7".to_string())).await;
+    let (base_url, requests) = spawn_http_stub(|_| (500, String::new())).await;
+    let mut cloudflare = Cloudflare::new("zone", "token", "example.com", "www", None, false, "A");
+    cloudflare.api_base = Some(base_url);
+
+    let mut family = SiteConfig {
+        name: "dual-stack-test".to_string(),
+        provider: DDNSProvider::Cloudflare(cloudflare),
+        address_version: crate::mods::types::AddressVersion::V6,
+        source: IpSource::Http(HttpSource::new(
+            vec![HttpEndpoint::new(&source_url, None)],
+            1,
+        )),
+        ..SiteConfig::new()
+    };
+    let mut provider = family.provider.to_provider();
+    assert!(update_site(&family, provider.as_mut()).await.is_err());
+
+    // as one family of a dual-stack site the missing address is skipped
+    family.optional = true;
+    update_site(&family, provider.as_mut()).await.unwrap();
+    assert!(requests.lock().unwrap().is_empty());
+
+    // but not both families at once
+    let (source_url, _) = spawn_http_stub(|_| (200, "no address here".to_string())).await;
+    let source = IpSource::Http(HttpSource::new(
+        vec![HttpEndpoint::new(&source_url, None)],
+        1,
+    ));
+    let site = SiteConfig {
+        name: "dual-stack-none-test".to_string(),
+        provider: family.provider.clone(),
+        source: source.clone(),
+        dual_stack: Some(FamilyConfig {
+            interface: String::new(),
+            index: 0,
+            address_type: AddressType::Public,
+            count: 1,
+            address_policy: None,
+            source,
+        }),
+        ..SiteConfig::new()
+    };
+    let results = SiteRunner::new(&site).await.run_round().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].0, "dual-stack-none-test");
+    assert!(results[0].1.is_err());
+    assert_eq!(
+        STATE.lock().await.site("dual-stack-none-test").error_count,
+        1
+    );
+    assert!(requests.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_site_targets() {
+    let _guard = STATE_LOCK.lock().await;
+    let (source_url, detections) = spawn_http_stub(|_| (200, "203.0.113.7".to_string())).await;
+    let (base_url, updates) = spawn_http_stub(|request| match request.path.as_str() {
+        "/example.net/vpn" => (500, String::new()),
+        _ => (200, String::new()),
+    })
+    .await;
+    let provider = |domain: &str, name: &str| {
+        DDNSProvider::Custom(Custom::new(
+            domain,
+            name,
+            CustomRequest {
+                method: "GET".to_string(),
+                url: format!("{}/{{domain}}/{{name}}", base_url),
+                ..Default::default()
+            },
+            None,
+        ))
+    };
+
+    let site = SiteConfig {
+        name: "targets-test".to_string(),
+        provider: provider("example.com", "www"),
+        targets: vec![
+            TargetConfig {
+                name: "vpn".to_string(),
+                provider: provider("example.net", "vpn"),
+                host_suffix: None,
+            },
+            TargetConfig {
+                name: "apex".to_string(),
+                provider: provider("example.com", "@"),
+                host_suffix: None,
+            },
+        ],
+        source: IpSource::Http(HttpSource::new(
+            vec![HttpEndpoint::new(&source_url, None)],
+            1,
+        )),
+        ..SiteConfig::new()
+    };
+    let results = SiteRunner::new(&site).await.run_round().await;
+
+    // one detection, every target tried, the failing one reported on its own
+    assert_eq!(detections.lock().unwrap().len(), 1);
+    assert_eq!(updates.lock().unwrap().len(), 3);
+    let names = results
+        .iter()
+        .map(|(name, result)| (name.as_str(), result.is_ok()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec![
+            ("targets-test", true),
+            ("targets-test:vpn", false),
+            ("targets-test:apex", true)
+        ]
+    );
+    assert_eq!(STATE.lock().await.site("targets-test:vpn").error_count, 1);
+}
@@ -3,7 +3,17 @@ use network_interface::NetworkInterface;
 use network_interface::NetworkInterfaceConfig;
 use network_interface::V6IfAddr;
 
-use crate::mods::interfaces::AddressType;
+use std::{net::Ipv4Addr, time::Duration};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::mods::{
+    interfaces::{
+        netlink::{debounce, parse_address_events, AddressEvent},
+        parse_ipv4_default_gateway, parse_ipv4_default_route, parse_ipv6_default_route,
+        AddressDetails, AddressPolicy, AddressType, Cidr, IPAddress, InterfaceMatcher,
+    },
+    types::AddressVersion,
+};
 
 #[tokio::test]
 async fn test_interfaces() {
@@ -24,3 +34,246 @@ async fn test_interfaces() {
     println!("test_ipv6_private_type: {:?}", test_ipv6_private_type);
     assert_eq!(test_ipv6_private_type, AddressType::LinkLocal);
 }
+
+#[test]
+fn test_netlink_address_events() {
+    // RTM_NEWADDR for index 3 with IFA_ADDRESS and IFA_LABEL "ppp0", then an unrelated RTM_NEWLINK
+    let mut buf = Vec::new();
+    let attributes: Vec<u8> = [
+        &8u16.to_ne_bytes()[..],
+        &1u16.to_ne_bytes(),
+        &[203, 0, 113, 7],
+        &9u16.to_ne_bytes(),
+        &3u16.to_ne_bytes(),
+        b"ppp0\0\0\0\0",
+    ]
+    .concat();
+    let len = 16 + 8 + attributes.len() as u32;
+    buf.extend_from_slice(&len.to_ne_bytes());
+    buf.extend_from_slice(&20u16.to_ne_bytes());
+    buf.extend_from_slice(&[0; 10]);
+    buf.extend_from_slice(&[2, 32, 0, 0]);
+    buf.extend_from_slice(&3u32.to_ne_bytes());
+    buf.extend_from_slice(&attributes);
+    buf.extend_from_slice(&24u32.to_ne_bytes());
+    buf.extend_from_slice(&16u16.to_ne_bytes());
+    buf.extend_from_slice(&[0; 18]);
+
+    assert_eq!(
+        parse_address_events(&buf),
+        vec![AddressEvent {
+            index: 3,
+            label: Some("ppp0".to_string()),
+        }]
+    );
+    // truncated messages are ignored
+    assert_eq!(parse_address_events(&buf[..20]), vec![]);
+}
+
+#[tokio::test]
+async fn test_netlink_debounce() {
+    let (tx, rx) = mpsc::channel(16);
+    let (changed, mut changes) = broadcast::channel(16);
+    tokio::spawn(debounce(rx, Duration::from_millis(200), changed));
+
+    // a burst on two interfaces is reported once per interface after it calmed down
+    for name in ["eth0", "ppp0", "eth0", "eth0"] {
+        tx.send(name.to_string()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(changes.try_recv().is_err());
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(changes.try_recv().unwrap(), "eth0");
+    assert_eq!(changes.try_recv().unwrap(), "ppp0");
+    assert!(changes.try_recv().is_err());
+}
+
+#[test]
+fn test_address_policy() {
+    let v6 = |ip: &str, details: AddressDetails| {
+        IPAddress::V6(ip.to_string(), AddressType::Public, details)
+    };
+    let temporary = AddressDetails {
+        temporary: true,
+        preferred_lifetime: Some(86400),
+        ..Default::default()
+    };
+    let stable = |preferred_lifetime| AddressDetails {
+        preferred_lifetime,
+        ..Default::default()
+    };
+    let ips = vec![
+        v6("2001:db8:1::5e1f:9a3b:2c4d:1e2f", temporary),
+        v6("fd00::1", stable(None)),
+        v6("2001:db8:1::1234", stable(Some(3600))),
+        v6("2001:db8:1::211:22ff:fe33:4455", stable(Some(7200))),
+        v6("2001:db8:2::1", stable(None)),
+    ];
+    let select = |policy: AddressPolicy| {
+        policy
+            .select(ips.clone())
+            .iter()
+            .map(|ip| ip.address().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // no policy keeps the order of the OS
+    assert_eq!(select(AddressPolicy::default()).len(), 5);
+    assert_eq!(
+        select(AddressPolicy {
+            prefix: Some("2001:db8:1::/48".to_string()),
+            prefer_stable: true,
+            ..Default::default()
+        }),
+        vec![
+            "2001:db8:1::1234",
+            "2001:db8:1::211:22ff:fe33:4455",
+            "2001:db8:1::5e1f:9a3b:2c4d:1e2f"
+        ]
+    );
+    assert_eq!(
+        select(AddressPolicy {
+            interface_id: Some("eui64".to_string()),
+            ..Default::default()
+        })[0],
+        "2001:db8:1::211:22ff:fe33:4455"
+    );
+    assert_eq!(
+        select(AddressPolicy {
+            interface_id: Some("::1234".to_string()),
+            ..Default::default()
+        })[0],
+        "2001:db8:1::1234"
+    );
+    // forever beats any lifetime, ties keep their order
+    assert_eq!(
+        select(AddressPolicy {
+            exclude_ula: true,
+            prefer_lifetime: true,
+            ..Default::default()
+        }),
+        vec![
+            "2001:db8:2::1",
+            "2001:db8:1::5e1f:9a3b:2c4d:1e2f",
+            "2001:db8:1::211:22ff:fe33:4455",
+            "2001:db8:1::1234"
+        ]
+    );
+}
+
+#[test]
+fn test_cidr() {
+    let cidr = Cidr::parse("2001:db8::/32").unwrap();
+    assert!(cidr.contains(&"2001:db8:ffff::1".parse().unwrap()));
+    assert!(!cidr.contains(&"2001:db9::1".parse().unwrap()));
+    assert!(!cidr.contains(&"192.0.2.1".parse().unwrap()));
+    let cidr = Cidr::parse("192.168.0.0/16").unwrap();
+    assert!(cidr.contains(&"192.168.10.1".parse().unwrap()));
+    assert!(!cidr.contains(&"192.169.0.1".parse().unwrap()));
+    assert!(Cidr::parse("0.0.0.0/0")
+        .unwrap()
+        .contains(&"203.0.113.1".parse().unwrap()));
+    assert_eq!(Cidr::parse("192.0.2.1").unwrap().prefix_len, 32);
+    assert!(Cidr::parse("192.0.2.0/33").is_err());
+    assert!(Cidr::parse("example.com/24").is_err());
+}
+
+#[test]
+fn test_address_classification() {
+    let cases = [
+        ("8.8.8.8", AddressType::Public),
+        ("100.72.1.1", AddressType::Shared),
+        ("192.168.1.1", AddressType::Private),
+        ("192.0.2.10", AddressType::Documentation),
+        ("198.19.0.1", AddressType::Benchmarking),
+        ("192.0.0.9", AddressType::Public),
+        ("192.0.0.170", AddressType::Reserved),
+        ("250.1.2.3", AddressType::Reserved),
+        ("255.255.255.255", AddressType::Broadcast),
+        ("0.0.0.0", AddressType::Unspecified),
+        ("2606:4700::1111", AddressType::Public),
+        ("2001:db8::1", AddressType::Documentation),
+        ("2002:c000:204::1", AddressType::Transition),
+        ("2001:0:4136:e378::1", AddressType::Transition),
+        ("64:ff9b::c000:201", AddressType::Transition),
+        ("::ffff:192.0.2.1", AddressType::Transition),
+        ("2001:2::1", AddressType::Benchmarking),
+        ("2001:4:112::1", AddressType::Public),
+        ("2001:100::1", AddressType::Reserved),
+        ("fd00::1", AddressType::Private),
+        ("fe80::1", AddressType::LinkLocal),
+        ("ff02::1", AddressType::Multicast),
+        ("4000::1", AddressType::Other),
+    ];
+    for (ip, expected) in cases {
+        assert_eq!(
+            AddressType::classify(&ip.parse().unwrap()),
+            expected,
+            "{}",
+            ip
+        );
+    }
+}
+
+#[test]
+fn test_interface_matcher() {
+    let itf = |name: &str, mac: &str| NetworkInterface {
+        name: name.to_string(),
+        addr: Vec::new(),
+        mac_addr: Some(mac.to_string()),
+        index: 0,
+    };
+    let interfaces = vec![
+        itf("ppp1", "00:00:00:00:00:00"),
+        itf("eth0", "52:54:00:AB:CD:EF"),
+        itf("ppp0", "00:00:00:00:00:00"),
+        itf("eth0.10", "52:54:00:ab:cd:ef"),
+        itf("enx0a1b2c3d4e5f", "0a:1b:2c:3d:4e:5f"),
+    ];
+    let find = |value: &str| {
+        InterfaceMatcher::parse(value)
+            .unwrap()
+            .find(&interfaces, &AddressVersion::V4)
+            .unwrap()
+    };
+
+    assert_eq!(find("eth0"), vec!["eth0"]);
+    // several matches are sorted by name
+    assert_eq!(find("ppp*"), vec!["ppp0", "ppp1"]);
+    assert_eq!(find("ppp[!0]"), vec!["ppp1"]);
+    assert_eq!(find("/^enx[0-9a-f]{12}$/"), vec!["enx0a1b2c3d4e5f"]);
+    assert_eq!(find("52-54-00-ab-cd-ef"), vec!["eth0", "eth0.10"]);
+    assert_eq!(find("eth0.1?"), vec!["eth0.10"]);
+    assert!(find("wlan0").is_empty());
+    assert!(InterfaceMatcher::parse("/(/").is_err());
+
+    // only names are known when an address changes
+    assert!(InterfaceMatcher::parse("ppp*").unwrap().may_match("ppp3"));
+    assert!(!InterfaceMatcher::parse("eth0").unwrap().may_match("eth1"));
+    assert!(InterfaceMatcher::parse("default-route")
+        .unwrap()
+        .may_match("eth1"));
+}
+
+#[test]
+fn test_default_route() {
+    let ipv4 = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wwan0\t00000000\t010A000A\t0003\t0\t0\t700\t00000000\t0\t0\t0
+eth0\t00000000\t010200C0\t0003\t0\t0\t100\t00000000\t0\t0\t0
+eth0\t000200C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0";
+    assert_eq!(parse_ipv4_default_route(ipv4).as_deref(), Some("eth0"));
+    assert_eq!(
+        parse_ipv4_default_gateway(ipv4),
+        Some(Ipv4Addr::new(192, 0, 2, 1))
+    );
+    assert_eq!(
+        parse_ipv4_default_route(&ipv4.replace("\t100\t", "\t900\t")).as_deref(),
+        Some("wwan0")
+    );
+
+    let ipv6 = "fd000000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001     eth0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00000003     ppp0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200       lo";
+    assert_eq!(parse_ipv6_default_route(ipv6).as_deref(), Some("ppp0"));
+    assert_eq!(parse_ipv6_default_route(""), None);
+}
@@ -0,0 +1,422 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, UdpSocket},
+};
+
+use lazy_static::lazy_static;
+
+use crate::mods::{
+    dns::{encode_name, parse_message, RecordData},
+    providers::rfc2136::{tsig_sign, tsig_verify, TsigKey},
+};
+
+lazy_static! {
+    /// Held by the tests that run rounds or read the global `STATE`, so the one swapping it
+    /// for a persistent store never runs alongside them
+    pub static ref STATE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+/// A request captured by the mock server
+#[derive(Clone, Debug)]
+pub struct MockRequest {
+    pub method: String,
+    pub path: String,                     // path with query
+    pub headers: HashMap<String, String>, // lowercase names
+    pub body: String,
+}
+
+/// Spawn a minimal HTTP/1.1 server on 127.0.0.1 answering every request with `handler`.
+/// Returns the base url (`http://127.0.0.1:port`) and the log of received requests.
+pub async fn spawn_http_stub<F>(handler: F) -> (String, Arc<Mutex<Vec<MockRequest>>>)
+where
+    F: Fn(&MockRequest) -> (u16, String) + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let handler = Arc::new(handler);
+    let log = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = match listener.accept().await {
+                Ok(value) => value,
+                Err(_) => break,
+            };
+            let handler = handler.clone();
+            let log = log.clone();
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 4096];
+                let header_end = loop {
+                    let n = stream.read(&mut chunk).await.unwrap_or(0);
+                    if n == 0 {
+                        return;
+                    }
+                    buf.extend_from_slice(&chunk[..n]);
+                    if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break pos + 4;
+                    }
+                };
+                let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+                let headers: HashMap<String, String> = head
+                    .lines()
+                    .skip(1)
+                    .filter_map(|line| line.split_once(':'))
+                    .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+                    .collect();
+                let content_length = headers
+                    .get("content-length")
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .unwrap_or(0);
+                while buf.len() < header_end + content_length {
+                    let n = stream.read(&mut chunk).await.unwrap_or(0);
+                    if n == 0 {
+                        break;
+                    }
+                    buf.extend_from_slice(&chunk[..n]);
+                }
+                let body = String::from_utf8_lossy(&buf[header_end..]).to_string();
+                let mut request_line = head.lines().next().unwrap_or("").split(' ');
+                let request = MockRequest {
+                    method: request_line.next().unwrap_or("").to_string(),
+                    path: request_line.next().unwrap_or("").to_string(),
+                    headers,
+                    body,
+                };
+                let (status, rsp_body) = handler(&request);
+                log.lock().unwrap().push(request);
+                let response = format!(
+                    "HTTP/1.1 {} OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    rsp_body.len(),
+                    rsp_body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+    (base_url, requests)
+}
+
+/// Spawn a STUN responder on 127.0.0.1 answering Binding Requests with `mapped` as
+/// XOR-MAPPED-ADDRESS, dropping the first `drop_first` requests.
+/// Returns its address and the number of requests received.
+pub async fn spawn_stun_stub(mapped: IpAddr, drop_first: usize) -> (String, Arc<Mutex<usize>>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap().to_string();
+    let received = Arc::new(Mutex::new(0));
+    let count = received.clone();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1024];
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf).await {
+                Ok(value) => value,
+                Err(_) => break,
+            };
+            let seen = {
+                let mut count = count.lock().unwrap();
+                *count += 1;
+                *count
+            };
+            if len < 20 || seen <= drop_first {
+                continue;
+            }
+            // cookie and transaction ID, also the XOR key
+            let key = buf[4..20].to_vec();
+            let (family, address) = match mapped {
+                IpAddr::V4(ip) => (1u8, ip.octets().to_vec()),
+                IpAddr::V6(ip) => (2u8, ip.octets().to_vec()),
+            };
+            let mut value = vec![0, family];
+            value.extend_from_slice(&(3478u16 ^ 0x2112).to_be_bytes());
+            value.extend(address.iter().zip(key.iter()).map(|(a, k)| a ^ k));
+            let mut response = vec![0x01, 0x01];
+            response.extend_from_slice(&(4 + value.len() as u16).to_be_bytes());
+            response.extend_from_slice(&key);
+            response.extend_from_slice(&[0x00, 0x20]);
+            response.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            response.extend_from_slice(&value);
+            let _ = socket.send_to(&response, from).await;
+        }
+    });
+    (address, received)
+}
+
+/// Spawn a NAT-PMP and PCP server on 127.0.0.1 reporting `external` as the WAN address.
+/// Returns its address and the requests received.
+pub async fn spawn_gateway_stub(external: Ipv4Addr) -> (String, Arc<Mutex<Vec<Vec<u8>>>>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap().to_string();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let log = requests.clone();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1100];
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf).await {
+                Ok(value) => value,
+                Err(_) => break,
+            };
+            let request = buf[..len].to_vec();
+            log.lock().unwrap().push(request.clone());
+            let response = match request.first() {
+                // NAT-PMP external address
+                Some(0) => {
+                    let mut response = vec![0, 128, 0, 0, 0, 0, 0, 42];
+                    response.extend_from_slice(&external.octets());
+                    response
+                }
+                // PCP MAP: echo the request body with the assigned port and address
+                Some(2) if len >= 60 => {
+                    let mut response = vec![2, 0x80 | request[1], 0, 0];
+                    response.extend_from_slice(&request[4..8]);
+                    response.extend_from_slice(&[0; 16]);
+                    response.extend_from_slice(&request[24..40]);
+                    response.extend_from_slice(&request[40..42]); // internal port
+                    response.extend_from_slice(&request[40..42]); // same external port
+                    response.extend_from_slice(&external.to_ipv6_mapped().octets());
+                    response
+                }
+                _ => continue,
+            };
+            let _ = socket.send_to(&response, from).await;
+        }
+    });
+    (address, requests)
+}
+
+/// Spawn an authoritative DNS server on 127.0.0.1, over UDP and TCP on the same port,
+/// answering from `records` (name, type, data). With `truncate_udp` every UDP answer is
+/// empty and truncated so clients have to retry over TCP.
+/// Returns its address and the questions received as (name, type, over TCP).
+pub async fn spawn_dns_stub(
+    records: Vec<(&'static str, u16, RecordData)>,
+    truncate_udp: bool,
+) -> (String, Arc<Mutex<Vec<(String, u16, bool)>>>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    let listener = TcpListener::bind(address).await.unwrap();
+    let questions = Arc::new(Mutex::new(Vec::new()));
+    let records = Arc::new(records);
+
+    let (log, zone) = (questions.clone(), records.clone());
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf).await {
+                Ok(value) => value,
+                Err(_) => break,
+            };
+            let response = dns_answer(&buf[..len], &zone, truncate_udp, false, &log);
+            let _ = socket.send_to(&response, from).await;
+        }
+    });
+    let (log, zone) = (questions.clone(), records);
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = match listener.accept().await {
+                Ok(value) => value,
+                Err(_) => break,
+            };
+            let len = match stream.read_u16().await {
+                Ok(len) => len as usize,
+                Err(_) => continue,
+            };
+            let mut request = vec![0u8; len];
+            if stream.read_exact(&mut request).await.is_err() {
+                continue;
+            }
+            let response = dns_answer(&request, &zone, false, true, &log);
+            let mut framed = (response.len() as u16).to_be_bytes().to_vec();
+            framed.extend_from_slice(&response);
+            let _ = stream.write_all(&framed).await;
+        }
+    });
+    (address.to_string(), questions)
+}
+
+fn dns_answer(
+    request: &[u8],
+    zone: &[(&'static str, u16, RecordData)],
+    truncate: bool,
+    tcp: bool,
+    log: &Mutex<Vec<(String, u16, bool)>>,
+) -> Vec<u8> {
+    // a single uncompressed question
+    let mut labels = Vec::new();
+    let mut offset = 12;
+    while request[offset] != 0 {
+        let len = request[offset] as usize;
+        labels.push(String::from_utf8_lossy(&request[offset + 1..offset + 1 + len]).to_string());
+        offset += 1 + len;
+    }
+    let name = labels.join(".");
+    let qtype = u16::from_be_bytes([request[offset + 1], request[offset + 2]]);
+    let question = &request[12..offset + 5];
+    log.lock().unwrap().push((name.clone(), qtype, tcp));
+
+    let matching = |name: &str, qtype: u16| {
+        zone.iter()
+            .filter(move |(owner, rtype, _)| *owner == name && (*rtype == qtype || *rtype == 5))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    let mut answers = matching(&name, qtype);
+    // follow one CNAME, and add the glue of NS records
+    let mut additional = Vec::new();
+    for (_, rtype, data) in answers.clone() {
+        if let RecordData::Name(target) = data {
+            let target = zone
+                .iter()
+                .find(|(owner, _, _)| *owner == target)
+                .map(|(owner, _, _)| *owner);
+            match (rtype, target) {
+                (5, Some(target)) => answers.extend(matching(target, qtype)),
+                (2, Some(target)) => additional.extend(matching(target, 1)),
+                _ => (),
+            }
+        }
+    }
+    let exists = zone.iter().any(|(owner, _, _)| *owner == name);
+    if truncate {
+        answers.clear();
+        additional.clear();
+    }
+
+    let flags = 0x8400 | u16::from(truncate) << 9 | (request[2] as u16 & 0x01) << 8;
+    let rcode = if exists { 0 } else { 3 };
+    let mut response = request[..2].to_vec();
+    response.extend_from_slice(&(flags | rcode).to_be_bytes());
+    for count in [1, answers.len(), 0, additional.len()] {
+        response.extend_from_slice(&(count as u16).to_be_bytes());
+    }
+    response.extend_from_slice(question);
+    for (owner, rtype, data) in answers.iter().chain(additional.iter()) {
+        let rdata = match data {
+            RecordData::A(ip) => ip.octets().to_vec(),
+            RecordData::Aaaa(ip) => ip.octets().to_vec(),
+            RecordData::Name(name) => encode_name(name).unwrap(),
+            RecordData::Other(data) => data.clone(),
+        };
+        response.append(&mut encode_name(owner).unwrap());
+        response.extend_from_slice(&rtype.to_be_bytes());
+        response.extend_from_slice(&[0, 1, 0, 0, 0x0e, 0x10]); // IN, TTL 3600
+        response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        response.extend_from_slice(&rdata);
+    }
+    response
+}
+
+/// A primary nameserver accepting RFC 2136 updates over UDP, signed with `key` if any.
+/// Returns its address and the zone as (name, type, value), shared with the server.
+pub async fn spawn_rfc2136_stub(
+    key: Option<TsigKey>,
+) -> (String, Arc<Mutex<Vec<(String, u16, RecordData)>>>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    let records = Arc::new(Mutex::new(Vec::new()));
+
+    let zone = records.clone();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1232];
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf).await {
+                Ok(value) => value,
+                Err(_) => break,
+            };
+            let response = rfc2136_answer(&buf[..len], key.as_ref(), &zone);
+            let _ = socket.send_to(&response, from).await;
+        }
+    });
+    (address.to_string(), records)
+}
+
+fn rfc2136_answer(
+    request: &[u8],
+    key: Option<&TsigKey>,
+    zone: &Mutex<Vec<(String, u16, RecordData)>>,
+) -> Vec<u8> {
+    // a single uncompressed question (the zone of an update)
+    let mut labels = Vec::new();
+    let mut offset = 12;
+    while request[offset] != 0 {
+        let len = request[offset] as usize;
+        labels.push(String::from_utf8_lossy(&request[offset + 1..offset + 1 + len]).to_string());
+        offset += 1 + len;
+    }
+    let name = labels.join(".");
+    let qtype = u16::from_be_bytes([request[offset + 1], request[offset + 2]]);
+    let question = &request[12..offset + 5];
+    let opcode = (request[2] >> 3) & 0x0f;
+
+    let reply = |rcode: u8, answers: &[(String, u16, RecordData)]| {
+        let mut response = request[..2].to_vec();
+        response.extend_from_slice(&[0x80 | opcode << 3, rcode]);
+        for count in [1, answers.len(), 0, 0] {
+            response.extend_from_slice(&(count as u16).to_be_bytes());
+        }
+        response.extend_from_slice(question);
+        for (owner, rtype, data) in answers {
+            let rdata = match data {
+                RecordData::A(ip) => ip.octets().to_vec(),
+                RecordData::Aaaa(ip) => ip.octets().to_vec(),
+                RecordData::Name(name) => encode_name(name).unwrap(),
+                RecordData::Other(data) => data.clone(),
+            };
+            response.append(&mut encode_name(owner).unwrap());
+            response.extend_from_slice(&rtype.to_be_bytes());
+            response.extend_from_slice(&[0, 1, 0, 0, 0x01, 0x2c]); // IN, TTL 300
+            response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            response.extend_from_slice(&rdata);
+        }
+        response
+    };
+    // bad signatures get an unsigned NOTAUTH
+    let mac = match key.map(|key| tsig_verify(request, key, None)) {
+        Some(Ok(mac)) => Some(mac),
+        Some(Err(_)) => return reply(9, &[]),
+        None => None,
+    };
+    let message = parse_message(request).unwrap();
+
+    let (rcode, answers) = match opcode {
+        5 => {
+            // updates only: delete the RRset (no data) or add a value
+            let mut zone = zone.lock().unwrap();
+            for record in &message.authority {
+                match &record.data {
+                    RecordData::Other(data) if data.is_empty() => {
+                        zone.retain(|(owner, rtype, _)| {
+                            !(*owner == record.name && *rtype == record.rtype)
+                        })
+                    }
+                    data => zone.push((record.name.clone(), record.rtype, data.clone())),
+                }
+            }
+            (0, Vec::new())
+        }
+        _ => {
+            let zone = zone.lock().unwrap();
+            let answers = zone
+                .iter()
+                .filter(|(owner, rtype, _)| *owner == name && *rtype == qtype)
+                .cloned()
+                .collect::<Vec<_>>();
+            let exists = zone.iter().any(|(owner, _, _)| *owner == name);
+            (if exists { 0 } else { 3 }, answers)
+        }
+    };
+    let response = reply(rcode, &answers);
+    match (key, mac) {
+        (Some(key), Some(mac)) => {
+            let now = chrono::Utc::now().timestamp() as u64;
+            tsig_sign(&response, key, now, Some(&mac)).unwrap().0
+        }
+        _ => response,
+    }
+}
@@ -0,0 +1,334 @@
+use crate::mods::{
+    sources::{
+        check_cidrs,
+        command::CommandSource,
+        extract_addresses,
+        file::FileSource,
+        gateway::{
+            find_wan_service, parse_pcp_response, pcp_map_request, ssdp_discover, GatewayProtocol,
+            GatewaySource,
+        },
+        http::{HttpEndpoint, HttpSource},
+        prefix::PrefixSource,
+        resolve_address,
+        stun::{binding_request, parse_binding_response, StunSource},
+        validate_address, IpSource,
+    },
+    types::{AddressVersion, DDNSProvider, SiteConfig, TargetConfig},
+};
+use crate::tests::mock::{spawn_gateway_stub, spawn_http_stub, spawn_stun_stub};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+use tokio::net::UdpSocket;
+
+#[tokio::test]
+async fn test_http_source() {
+    let (base_url, _) = spawn_http_stub(|request| match request.path.as_str() {
+        "/text" => (200, "203.0.113.7\n".to_string()),
+        "/json" => (200, r#"{"data":{"ip":"203.0.113.7"}}"#.to_string()),
+        "/v6" => (200, "2001:db8::1".to_string()),
+        _ => (500, String::new()),
+    })
+    .await;
+
+    let endpoints = vec![
+        HttpEndpoint::new(&format!("{}/error", base_url), None),
+        HttpEndpoint::new(&format!("{}/v6", base_url), None),
+        HttpEndpoint::new(&format!("{}/text", base_url), None),
+        HttpEndpoint::new(&format!("{}/json", base_url), Some("$.data.ip")),
+    ];
+
+    // failing and wrong-family endpoints are skipped
+    let source = HttpSource::new(endpoints.clone(), 1);
+    assert_eq!(
+        source.fetch_address(&AddressVersion::V4).await.unwrap(),
+        "203.0.113.7"
+    );
+
+    let source = HttpSource::new(endpoints.clone(), 2);
+    assert_eq!(
+        source.fetch_address(&AddressVersion::V4).await.unwrap(),
+        "203.0.113.7"
+    );
+
+    let source = HttpSource::new(endpoints, 3);
+    assert!(source.fetch_address(&AddressVersion::V4).await.is_err());
+
+    assert!(validate_address("2001:db8::1", &AddressVersion::V6).is_ok());
+    assert!(validate_address("2001:db8::1", &AddressVersion::V4).is_err());
+    assert!(validate_address("<html>", &AddressVersion::V4).is_err());
+}
+
+#[tokio::test]
+async fn test_prefix_source() {
+    // a /56 delegated by the ISP, hosts in the 0x12 subnet
+    let source = PrefixSource::new(56, "::12:0:0:0:1234");
+    assert_eq!(
+        source.compose("2001:db8:aa:bb01::1").unwrap(),
+        "2001:db8:aa:bb12::1234"
+    );
+    assert!(source.compose("192.0.2.1").is_err());
+    assert!(PrefixSource::new(64, "2001:db8::1").suffix().is_err());
+    assert!(PrefixSource::new(128, "::1").suffix().is_err());
+    assert!(PrefixSource::new(64, "host").suffix().is_err());
+
+    // every target puts its own suffix on the prefix the site detected
+    let site = SiteConfig {
+        name: "lan".to_string(),
+        address_version: AddressVersion::V6,
+        source: IpSource::Prefix(PrefixSource::new(64, "::1")),
+        targets: vec![
+            TargetConfig {
+                name: "nas".to_string(),
+                provider: DDNSProvider::new(),
+                host_suffix: Some("::211:22ff:fe33:4455".to_string()),
+            },
+            TargetConfig {
+                name: "router".to_string(),
+                provider: DDNSProvider::new(),
+                host_suffix: None,
+            },
+        ],
+        ..SiteConfig::new()
+    };
+    let detected = vec!["2001:db8:1:2::1".to_string()];
+    let published = site
+        .targets()
+        .iter()
+        .map(|target| target.source.apply(detected.clone()).unwrap()[0].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        published,
+        vec![
+            "2001:db8:1:2::1",
+            "2001:db8:1:2:211:22ff:fe33:4455",
+            "2001:db8:1:2::1"
+        ]
+    );
+}
+
+#[test]
+fn test_check_cidrs() {
+    let site = SiteConfig {
+        include: vec!["2001:db8::/32".to_string()],
+        exclude: vec!["100.64.0.0/10".to_string(), "2001:db8:bad::/48".to_string()],
+        ..SiteConfig::new()
+    };
+    // include only restricts addresses of its own version
+    assert!(check_cidrs(&site, "198.51.100.1".to_string()).is_ok());
+    assert!(check_cidrs(&site, "100.100.0.1".to_string()).is_err());
+    assert!(check_cidrs(&site, "2001:db8:1::1".to_string()).is_ok());
+    assert!(check_cidrs(&site, "2001:db8:bad::1".to_string()).is_err());
+    assert!(check_cidrs(&site, "2606:4700::1".to_string()).is_err());
+}
+
+#[tokio::test]
+async fn test_stun_source() {
+    let mapped: IpAddr = "203.0.113.9".parse().unwrap();
+    let (first, _) = spawn_stun_stub(mapped, 0).await;
+    // loses the first request, answered after a retransmission
+    let (lossy, received) = spawn_stun_stub(mapped, 1).await;
+    let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    let mut source = StunSource::new(
+        vec![
+            silent.local_addr().unwrap().to_string(),
+            first,
+            lossy.clone(),
+        ],
+        2,
+    );
+    source.timeout = 2;
+    assert_eq!(
+        source.fetch_address(&AddressVersion::V4).await.unwrap(),
+        "203.0.113.9"
+    );
+    assert_eq!(*received.lock().unwrap(), 2);
+    // the answer must be of the requested family
+    assert!(StunSource::new(vec![lossy], 1)
+        .fetch_address(&AddressVersion::V6)
+        .await
+        .is_err());
+
+    // IPv6 XOR-MAPPED-ADDRESS is keyed with the cookie and the transaction ID
+    let id = [7u8; 12];
+    let ip: Ipv6Addr = "2001:db8::5".parse().unwrap();
+    let mut key = vec![0x21, 0x12, 0xa4, 0x42];
+    key.extend_from_slice(&id);
+    let mut response = vec![0x01, 0x01, 0x00, 0x18, 0x21, 0x12, 0xa4, 0x42];
+    response.extend_from_slice(&id);
+    response.extend_from_slice(&[0x00, 0x20, 0x00, 0x14, 0x00, 0x02, 0x00, 0x00]);
+    response.extend(ip.octets().iter().zip(key.iter()).map(|(a, k)| a ^ k));
+    assert_eq!(
+        parse_binding_response(&response, &id).unwrap(),
+        Some(IpAddr::V6(ip))
+    );
+    assert_eq!(parse_binding_response(&response, &[8u8; 12]).unwrap(), None);
+    assert_eq!(
+        binding_request(&id)[..8],
+        [0, 1, 0, 0, 0x21, 0x12, 0xa4, 0x42]
+    );
+}
+
+const IGD_DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+<device>
+<deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+<serviceList>
+<service>
+<serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+<controlURL>/ctl/L3F</controlURL>
+</service>
+<service>
+<serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+<controlURL>/ctl/IPConn</controlURL>
+</service>
+</serviceList>
+</device>
+</root>"#;
+
+#[tokio::test]
+async fn test_gateway_source() {
+    // UPnP: SSDP answer, device description, then the SOAP call
+    let (base_url, requests) = spawn_http_stub(|request| match request.path.as_str() {
+        "/rootDesc.xml" => (200, IGD_DESCRIPTION.to_string()),
+        "/ctl/IPConn" => (
+            200,
+            "<s:Envelope><s:Body><u:GetExternalIPAddressResponse>\
+             <NewExternalIPAddress>198.51.100.7</NewExternalIPAddress>\
+             </u:GetExternalIPAddressResponse></s:Body></s:Envelope>"
+                .to_string(),
+        ),
+        _ => (404, String::new()),
+    })
+    .await;
+    let location = format!("{}/rootDesc.xml", base_url);
+    let responder = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let target = responder.local_addr().unwrap().to_string();
+    let answer = format!(
+        "HTTP/1.1 200 OK\r\nST: upnp:rootdevice\r\nLOCATION: {}\r\n\r\n",
+        location
+    );
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1024];
+        if let Ok((_, from)) = responder.recv_from(&mut buf).await {
+            assert!(String::from_utf8_lossy(&buf).starts_with("M-SEARCH"));
+            let _ = responder.send_to(answer.as_bytes(), from).await;
+        }
+    });
+    assert_eq!(
+        ssdp_discover(&target, Duration::from_secs(2))
+            .await
+            .unwrap(),
+        location
+    );
+
+    let mut source = GatewaySource::new(vec![GatewayProtocol::Upnp]);
+    source.location = Some(location.clone());
+    assert_eq!(source.fetch_address().await.unwrap(), "198.51.100.7");
+    let soap = requests.lock().unwrap().last().unwrap().clone();
+    assert_eq!(soap.method, "POST");
+    assert_eq!(
+        soap.headers["soapaction"],
+        "\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\""
+    );
+    assert_eq!(
+        find_wan_service(IGD_DESCRIPTION, "http://192.168.1.1:5000/rootDesc.xml").unwrap(),
+        (
+            "urn:schemas-upnp-org:service:WANIPConnection:1".to_string(),
+            "http://192.168.1.1:5000/ctl/IPConn".to_string()
+        )
+    );
+
+    // NAT-PMP, then PCP when the first protocol fails
+    let (gateway, received) = spawn_gateway_stub(Ipv4Addr::new(198, 51, 100, 8)).await;
+    let mut source = GatewaySource::new(vec![GatewayProtocol::NatPmp]);
+    source.gateway = Some(gateway.clone());
+    assert_eq!(source.fetch_address().await.unwrap(), "198.51.100.8");
+    assert_eq!(received.lock().unwrap()[0], vec![0, 0]);
+
+    let mut source = GatewaySource::new(vec![GatewayProtocol::Upnp, GatewayProtocol::Pcp]);
+    source.gateway = Some(gateway);
+    source.location = Some(format!("{}/missing.xml", base_url));
+    source.timeout = 1;
+    assert_eq!(source.fetch_address().await.unwrap(), "198.51.100.8");
+    // the MAP request, then its deletion with a lifetime of 0
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 3);
+    assert_eq!(received[1][..2], [2, 1]);
+    assert_eq!(received[1][4..8], 60u32.to_be_bytes());
+    assert_eq!(received[2][4..8], [0, 0, 0, 0]);
+    assert_eq!(received[1][24..36], received[2][24..36]);
+
+    let mut response = pcp_map_request(Ipv4Addr::LOCALHOST, 1234, &[1; 12], 60);
+    response[1] = 0x81;
+    response[3] = 2; // NOT_AUTHORIZED
+    assert!(parse_pcp_response(&response).is_err());
+}
+
+#[tokio::test]
+async fn test_command_and_file_sources() {
+    // the CGNAT and private addresses come first and are skipped like on an interface
+    let status = "WAN1 100.64.1.2 up\nLAN 192.168.1.1 up\nWAN2 1.1.1.1 up\n";
+    let regex = r"WAN\d (\S+)";
+    assert_eq!(
+        extract_addresses(status, Some(regex)).unwrap(),
+        vec!["100.64.1.2", "1.1.1.1"]
+    );
+    assert_eq!(
+        extract_addresses(" 1.1.1.1 \n\n2606:4700::1111\n", None).unwrap(),
+        vec!["1.1.1.1", "2606:4700::1111"]
+    );
+
+    let path = std::env::temp_dir().join(format!("ddns-rust-wan-{}.txt", std::process::id()));
+    std::fs::write(&path, status).unwrap();
+    let mut source = FileSource::new(path.to_str().unwrap());
+    source.regex = Some(r"(\S+) up".to_string());
+    let mut site = SiteConfig {
+        source: IpSource::File(source),
+        ..SiteConfig::new()
+    };
+    assert_eq!(resolve_address(&site).await.unwrap(), "1.1.1.1");
+    site.exclude = vec!["1.1.1.0/24".to_string()];
+    assert!(resolve_address(&site).await.is_err());
+    std::fs::remove_file(&path).unwrap();
+    assert!(resolve_address(&site).await.is_err());
+
+    // arguments are passed as is, no shell expands them
+    let mut source = CommandSource::new(vec![
+        "echo".to_string(),
+        "$HOME 2606:4700::1111".to_string(),
+    ]);
+    source.regex = Some(r"[0-9a-f:]*:[0-9a-f:]+".to_string());
+    let site = SiteConfig {
+        address_version: AddressVersion::V6,
+        source: IpSource::Command(source),
+        ..SiteConfig::new()
+    };
+    assert_eq!(resolve_address(&site).await.unwrap(), "2606:4700::1111");
+
+    let mut source = CommandSource::new(vec!["echo 8.8.8.8 | tr 8 9".to_string()]);
+    source.shell = true;
+    let site = SiteConfig {
+        source: IpSource::Command(source),
+        ..SiteConfig::new()
+    };
+    assert_eq!(resolve_address(&site).await.unwrap(), "9.9.9.9");
+
+    let mut source = CommandSource::new(vec!["sleep".to_string(), "5".to_string()]);
+    source.timeout = 1;
+    assert!(source
+        .fetch_addresses()
+        .await
+        .unwrap_err()
+        .contains("within 1s"));
+    let source = CommandSource::new(vec!["false".to_string()]);
+    assert!(source
+        .fetch_addresses()
+        .await
+        .unwrap_err()
+        .contains("exited"));
+}
@@ -0,0 +1,143 @@
+use async_trait::async_trait;
+
+use crate::mods::{
+    handle::{update_site, SiteRunner},
+    providers::{
+        cloudflare::Cloudflare,
+        custom::{Custom, CustomQuery, CustomRequest},
+        types::ProvidersErrorType,
+        DDNSProviderTrait,
+    },
+    sources::{
+        http::{HttpEndpoint, HttpSource},
+        IpSource,
+    },
+    state::{SiteState, StateStore},
+    statics::STATE,
+    types::{DDNSProvider, SiteConfig},
+};
+use crate::tests::mock::{spawn_http_stub, STATE_LOCK};
+
+#[derive(Default)]
+struct CountingProvider {
+    remote: String,
+    queries: usize,
+    updates: usize,
+}
+
+#[async_trait]
+impl DDNSProviderTrait for CountingProvider {
+    async fn update(&mut self, ip: &str) -> Result<(), String> {
+        self.updates += 1;
+        self.remote = ip.to_string();
+        Ok(())
+    }
+
+    async fn get_ip_address(&mut self) -> Result<String, ProvidersErrorType> {
+        self.queries += 1;
+        Ok(self.remote.clone())
+    }
+
+    fn record_id(&self) -> Option<String> {
+        Some("42".to_string())
+    }
+}
+
+#[tokio::test]
+async fn test_state_skips_unchanged_after_restart() {
+    let _guard = STATE_LOCK.lock().await;
+    let path = std::env::temp_dir().join(format!("ddns-rust-state-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    *STATE.lock().await = StateStore::load(path.to_str()).await;
+
+    let (source_url, _) = spawn_http_stub(|_| (200, "203.0.113.9".to_string())).await;
+    let (base_url, requests) = spawn_http_stub(|_| (200, "203.0.113.9".to_string())).await;
+    let request = |path: &str| CustomRequest {
+        method: "GET".to_string(),
+        url: format!("{}{}", base_url, path),
+        ..Default::default()
+    };
+    let query = CustomQuery {
+        request: request("/query"),
+        json_path: None,
+        regex: None,
+    };
+    let site = SiteConfig {
+        name: "state-test".to_string(),
+        provider: DDNSProvider::Custom(Custom::new(
+            "example.com",
+            "www",
+            request("/update?ip={ip}"),
+            Some(query),
+        )),
+        source: IpSource::Http(HttpSource::new(
+            vec![HttpEndpoint::new(&source_url, None)],
+            1,
+        )),
+        ..SiteConfig::new()
+    };
+
+    let mut provider = CountingProvider {
+        remote: "1.1.1.1".to_string(),
+        ..Default::default()
+    };
+    update_site(&site, &mut provider).await.unwrap();
+    assert_eq!(provider.queries, 1);
+    assert_eq!(provider.updates, 1);
+
+    // a restart picks up the published value and record ID
+    *STATE.lock().await = StateStore::load(path.to_str()).await;
+    let site_state = STATE.lock().await.site("state-test");
+    assert_eq!(site_state.last_ip.as_deref(), Some("203.0.113.9"));
+    assert_eq!(site_state.record_id.as_deref(), Some("42"));
+    assert_eq!(site_state.error_count, 0);
+
+    // so its first round does not ask the provider, the following ones do
+    update_site(&site, &mut provider).await.unwrap();
+    assert_eq!(provider.queries, 1);
+    let mut runner = SiteRunner::new(&site).await;
+    runner.run_round().await;
+    assert!(requests.lock().unwrap().is_empty());
+    runner.run_round().await;
+    assert_eq!(requests.lock().unwrap().len(), 1);
+    assert_eq!(requests.lock().unwrap()[0].path, "/query");
+
+    // the same name for another record starts over, even right after a restart
+    let mut moved = site.clone();
+    if let DDNSProvider::Custom(custom) = &mut moved.provider {
+        custom.name = "home".to_string();
+    }
+    *STATE.lock().await = StateStore::load(path.to_str()).await;
+    let site_state = STATE.lock().await.site_for("state-test", &moved.provider);
+    assert_eq!(site_state, SiteState::default());
+    SiteRunner::new(&moved).await.run_round().await;
+    assert_eq!(requests.lock().unwrap().len(), 2);
+    let site_state = STATE.lock().await.site_for("state-test", &moved.provider);
+    assert_eq!(site_state.last_ip.as_deref(), Some("203.0.113.9"));
+    assert_eq!(site_state.record_id, None);
+
+    *STATE.lock().await = StateStore::new();
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_state_survives_credential_change() {
+    let mut state = StateStore::new();
+    let mut cloudflare =
+        Cloudflare::new("zone", "old-token", "example.com", "www", None, false, "A");
+    let provider = DDNSProvider::Cloudflare(cloudflare.clone());
+    state
+        .record_success("rotated", &provider, "1.1.1.1", Some("rec1".to_string()))
+        .await;
+
+    // a new token still publishes the same record
+    cloudflare.api_token = "new-token".to_string();
+    let provider = DDNSProvider::Cloudflare(cloudflare.clone());
+    let site_state = state.site_for("rotated", &provider);
+    assert_eq!(site_state.last_ip.as_deref(), Some("1.1.1.1"));
+    assert_eq!(site_state.record_id.as_deref(), Some("rec1"));
+
+    cloudflare.record_type = "AAAA".to_string();
+    let provider = DDNSProvider::Cloudflare(cloudflare);
+    assert_eq!(state.site_for("rotated", &provider), SiteState::default());
+}